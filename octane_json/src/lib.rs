@@ -85,6 +85,10 @@ mod test {
         let (string, rest) = parse::parse_string(r#""as\n\t\u0041d\"f"foo"#).unwrap();
         assert_eq!(string, "as\n\tAd\"f".to_string());
         assert_eq!(rest, "foo");
+        // Escapes after the closing quote aren't part of the string.
+        let (string, rest) = parse::parse_string(r#""ab","c\"d""#).unwrap();
+        assert_eq!(string, "ab");
+        assert_eq!(rest, r#","c\"d""#);
     }
 
     #[test]
@@ -11,7 +11,9 @@ pub fn parse_string(dat: &str) -> Option<(String, &str)> {
     let mut ret = String::with_capacity(dat.len());
     let mut cur = &dat[1..];
     while !cur.is_empty() {
-        if let Some(i) = cur.find('\\') {
+        let end = cur.find('"')?;
+        // only escapes before the closing quote belong to the string
+        if let Some(i) = cur[..end].find('\\') {
             ret.push_str(&cur[..i]);
             let chr = cur.as_bytes()[i + 1];
             if chr == b'u' {
@@ -41,9 +43,8 @@ pub fn parse_string(dat: &str) -> Option<(String, &str)> {
                 cur = &cur[i + 2..];
             }
         } else {
-            let v = cur.find('"')?;
-            ret.push_str(&cur[..v]);
-            return Some((ret, &cur[v + 1..]));
+            ret.push_str(&cur[..end]);
+            return Some((ret, &cur[end + 1..]));
         }
     }
     None
@@ -1,10 +1,13 @@
 extern crate proc_macro;
 
 mod json;
+mod runtime;
 mod status;
 mod stream_parser;
+mod test_client;
 mod util;
 
+use crate::runtime::RuntimeArgs;
 use crate::stream_parser::StreamParser;
 use proc_macro::TokenStream;
 use proc_macro::TokenTree;
@@ -90,6 +93,20 @@ pub fn derive_to_json(toks: TokenStream) -> TokenStream {
 /// octane::main attribute sets the thread stack to 10 megabytes, core threads
 /// to the number of cpus available * 2, use this in production
 ///
+/// The runtime can be tuned with arguments,
+///
+/// - `workers = 4`: the number of worker threads
+/// - `thread_name = "api"`: the name of the runtime threads, `octane-main` by default
+/// - `flavor = "current_thread"`: runs everything on the current thread
+///   instead of a pool of workers, `"multi_thread"` is the default
+///
+/// ```ignore
+/// #[octane::main(workers = 4, thread_name = "api")]
+/// async fn main() -> Result<(), Box<dyn Error>> {
+///     // ...
+/// }
+/// ```
+///
 /// # Example
 ///
 /// ```no_run
@@ -113,10 +130,9 @@ pub fn derive_to_json(toks: TokenStream) -> TokenStream {
 /// }
 /// ```
 #[proc_macro_attribute]
-pub fn main(_attr: TokenStream, item: TokenStream) -> TokenStream {
+pub fn main(attr: TokenStream, item: TokenStream) -> TokenStream {
     let stream = StreamParser::new(item.into());
     let properties = stream.parse();
-    let num_cpus = num_cpus::get() * 2;
     let compile_error;
     if properties.is_async {
         compile_error = quote! {};
@@ -125,17 +141,18 @@ pub fn main(_attr: TokenStream, item: TokenStream) -> TokenStream {
             compile_error!("the async keyword is missing from function declaration");
         }
     }
+    let builder = match RuntimeArgs::parse(attr.into()) {
+        Ok(args) => args.builder(),
+        Err(message) => quote! {
+            compile_error!(#message);
+        },
+    };
     let signature = properties.signature;
     let rest = properties.rest;
     let tokens = quote! {
         #signature {
             #compile_error
-            let mut builder = tokio::runtime::Builder::new_multi_thread();
-            builder
-                .enable_io()
-                .thread_stack_size(10485760)
-                .thread_name("octane-main")
-                .worker_threads(#num_cpus);
+            #builder
 
             let mut runtime = builder.build().expect("Unable to build tokio runtime");
             runtime.block_on(async {
@@ -152,8 +169,28 @@ pub fn main(_attr: TokenStream, item: TokenStream) -> TokenStream {
 ///
 /// octane::test attribute sets the scheduler to a basic scheduler, keeps everything
 /// else to defaults
+///
+/// Given a function building the app, the test receives a
+/// [`TestClient`](../octane/testing/struct.TestClient.html) for it
+///
+/// ```ignore
+/// use octane::prelude::*;
+/// use octane::testing::TestClient;
+///
+/// fn app() -> Octane {
+///     let mut app = Octane::new();
+///     app.get("/", route_stop!(|req, res| res.send("Hello"))).unwrap();
+///     app
+/// }
+///
+/// #[octane::test(app = app)]
+/// async fn get_root(client: TestClient) {
+///     let res = client.get("/").await.unwrap();
+///     assert_eq!(res.text(), "Hello");
+/// }
+/// ```
 #[proc_macro_attribute]
-pub fn test(_attr: TokenStream, item: TokenStream) -> TokenStream {
+pub fn test(attr: TokenStream, item: TokenStream) -> TokenStream {
     let stream = StreamParser::new(item.into());
     let properties = stream.parse();
     let compile_error;
@@ -164,15 +201,26 @@ pub fn test(_attr: TokenStream, item: TokenStream) -> TokenStream {
             compile_error!("the async keyword is missing from function declaration");
         }
     }
-    let signature = properties.signature;
+    let (signature, client) =
+        match test_client::client_setup(attr.into(), properties.signature.clone()) {
+            Ok(setup) => setup,
+            Err(message) => (
+                properties.signature,
+                quote! {
+                    compile_error!(#message);
+                },
+            ),
+        };
     let rest = properties.rest;
     let tokens = quote! {
         #[test]
         #signature {
             #compile_error
+            #client
             let mut builder = tokio::runtime::Builder::new_current_thread();
             builder
                 .enable_io()
+                .enable_time()
                 .thread_name("octane-test");
 
             let mut runtime = builder.build().expect("Unable to build tokio runtime");
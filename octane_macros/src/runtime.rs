@@ -0,0 +1,153 @@
+use proc_macro2::{TokenStream, TokenTree};
+use quote::quote;
+
+// The runtime options passed to the main attribute, like
+// `#[octane::main(workers = 4, thread_name = "api")]`
+#[derive(Debug, Default, PartialEq)]
+pub struct RuntimeArgs {
+    workers: Option<usize>,
+    thread_name: Option<String>,
+    current_thread: bool,
+}
+
+impl RuntimeArgs {
+    // Parses a comma separated list of `name = value` arguments
+    pub fn parse(attr: TokenStream) -> Result<Self, String> {
+        let mut args = RuntimeArgs::default();
+        let mut tokens = attr.into_iter();
+        while let Some(token) = tokens.next() {
+            let name = match token {
+                TokenTree::Ident(name) => name.to_string(),
+                other => return Err(format!("expected an argument name, found `{}`", other)),
+            };
+            match tokens.next() {
+                Some(TokenTree::Punct(punct)) if punct.as_char() == '=' => (),
+                _ => return Err(format!("expected `=` after `{}`", name)),
+            }
+            let value = match tokens.next() {
+                Some(TokenTree::Literal(value)) => value.to_string(),
+                _ => return Err(format!("expected a literal value for `{}`", name)),
+            };
+            match name.as_str() {
+                "workers" => match value.parse() {
+                    Ok(workers) if workers > 0 => args.workers = Some(workers),
+                    _ => return Err("`workers` should be a positive integer".to_owned()),
+                },
+                "thread_name" => args.thread_name = Some(string_literal(&name, &value)?),
+                "flavor" => {
+                    args.current_thread = match string_literal(&name, &value)?.as_str() {
+                        "multi_thread" => false,
+                        "current_thread" => true,
+                        _ => {
+                            return Err("`flavor` should be \"multi_thread\" or \"current_thread\""
+                                .to_owned())
+                        }
+                    }
+                }
+                _ => {
+                    return Err(format!(
+                        "unknown argument `{}`, expected `workers`, `thread_name` or `flavor`",
+                        name
+                    ))
+                }
+            }
+            match tokens.next() {
+                None => break,
+                Some(TokenTree::Punct(punct)) if punct.as_char() == ',' => (),
+                Some(other) => return Err(format!("expected `,`, found `{}`", other)),
+            }
+        }
+        if args.current_thread && args.workers.is_some() {
+            return Err("`workers` can't be set for the current_thread flavor".to_owned());
+        }
+        Ok(args)
+    }
+
+    // Generates the code which sets up the runtime builder
+    pub fn builder(&self) -> TokenStream {
+        let thread_name = self.thread_name.as_deref().unwrap_or("octane-main");
+        if self.current_thread {
+            return quote! {
+                let mut builder = tokio::runtime::Builder::new_current_thread();
+                builder
+                    .enable_io()
+                    .enable_time()
+                    .thread_name(#thread_name);
+            };
+        }
+        let workers = self.workers.unwrap_or_else(|| num_cpus::get() * 2);
+        quote! {
+            let mut builder = tokio::runtime::Builder::new_multi_thread();
+            builder
+                .enable_io()
+                .enable_time()
+                .thread_stack_size(10485760)
+                .thread_name(#thread_name)
+                .worker_threads(#workers);
+        }
+    }
+}
+
+// Strips the quotes off a plain string literal
+fn string_literal(name: &str, value: &str) -> Result<String, String> {
+    match value.strip_prefix('"').and_then(|v| v.strip_suffix('"')) {
+        Some(string) if !string.contains('\\') => Ok(string.to_owned()),
+        _ => Err(format!("`{}` should be a string", name)),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn builder(attr: &str) -> String {
+        let args = RuntimeArgs::parse(attr.parse().unwrap()).unwrap();
+        args.builder().to_string().replace(' ', "")
+    }
+
+    #[test]
+    fn success_workers() {
+        // The worker count and thread name should end up in the builder.
+        let code = builder(r#"workers = 4, thread_name = "api""#);
+        assert!(code.contains("Builder::new_multi_thread()"));
+        assert!(code.contains(".worker_threads(4usize)"));
+        assert!(code.contains(r#".thread_name("api")"#));
+    }
+
+    #[test]
+    fn success_defaults() {
+        // Without arguments the runtime should use twice the cpus.
+        let code = builder("");
+        let workers = format!(".worker_threads({}usize)", num_cpus::get() * 2);
+        assert!(code.contains(&workers));
+        assert!(code.contains(r#".thread_name("octane-main")"#));
+    }
+
+    #[test]
+    fn success_current_thread() {
+        // The current thread flavor shouldn't set up workers.
+        let code = builder(r#"flavor = "current_thread""#);
+        assert!(code.contains("Builder::new_current_thread()"));
+        assert!(!code.contains("worker_threads"));
+    }
+
+    #[test]
+    fn fail_arguments() {
+        // Bad values and unknown arguments should be rejected.
+        for attr in &[
+            "workers = 0",
+            "workers = \"4\"",
+            "threads = 4",
+            "workers 4",
+            "workers = 4 thread_name = \"a\"",
+            "flavor = \"single\"",
+            "flavor = \"current_thread\", workers = 2",
+        ] {
+            assert!(
+                RuntimeArgs::parse(attr.parse().unwrap()).is_err(),
+                "{}",
+                attr
+            );
+        }
+    }
+}
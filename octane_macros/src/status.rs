@@ -20,7 +20,11 @@ pub fn status_codes(toks: TokenStream) -> TokenStream {
                     let name: &str = &repr[1..repr.len() - 1];
                     let cased = pascal_case(name);
                     entries.push((prev_num, name.to_string(), cased.clone()));
-                    enum_stream.extend::<TokenStream>("#[doc(hidden)]".parse().unwrap());
+                    enum_stream.extend::<TokenStream>(
+                        format!("#[doc = \"`{} {}`\"]", prev_num, name)
+                            .parse()
+                            .unwrap(),
+                    );
                     extend(&mut enum_stream, Ident::new(&cased, Span::call_site()));
                     extend(&mut enum_stream, Punct::new(',', Spacing::Alone));
                 }
@@ -29,7 +33,12 @@ pub fn status_codes(toks: TokenStream) -> TokenStream {
             _ => continue,
         }
     }
-    enum_stream.extend::<TokenStream>("#[doc(hidden)] Other(i32, &'static str)".parse().unwrap());
+    enum_stream.extend::<TokenStream>(
+        "/// A status code which isn't listed, along with its reason phrase
+        Other(i32, &'static str)"
+            .parse()
+            .unwrap(),
+    );
     let enum_group = Group::new(Delimiter::Brace, enum_stream);
     let mut enum_tot = TokenStream::new();
     enum_tot.extend::<TokenStream>(
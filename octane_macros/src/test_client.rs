@@ -0,0 +1,133 @@
+use proc_macro2::{Delimiter, Group, TokenStream, TokenTree};
+use quote::quote;
+
+// Handles the client parameter of `#[octane::test(app = build_app)]`
+// tests. Returns the signature without the parameter, test functions
+// can't take any, and the code binding the parameter to a client for
+// the app which is built by calling the given function
+pub fn client_setup(
+    attr: TokenStream,
+    signature: TokenStream,
+) -> Result<(TokenStream, TokenStream), String> {
+    let app = parse_app(attr)?;
+    let (signature, params) = take_params(signature);
+    if params.is_empty() {
+        if app.is_some() {
+            return Err(
+                "the test needs a parameter to receive the client, like `client: TestClient`"
+                    .to_owned(),
+            );
+        }
+        return Ok((signature, TokenStream::new()));
+    }
+    let app = app.ok_or_else(|| {
+        "the test takes a client, pass the app with `#[octane::test(app = build_app)]`".to_owned()
+    })?;
+    let (pat, ty) = split_param(params)?;
+    let client = quote! {
+        let #pat: #ty = <#ty>::new(#app());
+    };
+    Ok((signature, client))
+}
+
+// Parses the `app = path` argument, the attribute can be empty
+fn parse_app(attr: TokenStream) -> Result<Option<TokenStream>, String> {
+    let mut tokens = attr.into_iter();
+    match tokens.next() {
+        None => return Ok(None),
+        Some(TokenTree::Ident(name)) if name == "app" => (),
+        Some(other) => return Err(format!("unknown argument `{}`, expected `app`", other)),
+    }
+    match tokens.next() {
+        Some(TokenTree::Punct(punct)) if punct.as_char() == '=' => (),
+        _ => return Err("expected `=` after `app`".to_owned()),
+    }
+    let app: TokenStream = tokens.collect();
+    if app.is_empty() {
+        return Err("expected the function building the app after `app =`".to_owned());
+    }
+    Ok(Some(app))
+}
+
+// Replaces the parameter list after `fn name` with an empty one and
+// returns it
+fn take_params(signature: TokenStream) -> (TokenStream, TokenStream) {
+    let mut params = TokenStream::new();
+    let mut after_name = false;
+    let mut after_fn = false;
+    let signature = signature
+        .into_iter()
+        .map(|tt| {
+            let tt = match tt {
+                TokenTree::Group(group)
+                    if after_name && group.delimiter() == Delimiter::Parenthesis =>
+                {
+                    params = group.stream();
+                    TokenTree::Group(Group::new(Delimiter::Parenthesis, TokenStream::new()))
+                }
+                tt => tt,
+            };
+            after_name = after_fn && matches!(tt, TokenTree::Ident(_));
+            after_fn = matches!(&tt, TokenTree::Ident(ident) if ident == "fn");
+            tt
+        })
+        .collect();
+    (signature, params)
+}
+
+// Splits a single `pattern: Type` parameter
+fn split_param(params: TokenStream) -> Result<(TokenStream, TokenStream), String> {
+    let mut tokens: Vec<TokenTree> = params.into_iter().collect();
+    if matches!(tokens.last(), Some(TokenTree::Punct(punct)) if punct.as_char() == ',') {
+        tokens.pop();
+    }
+    let is_punct = |tt: &TokenTree, c: char| matches!(tt, TokenTree::Punct(p) if p.as_char() == c);
+    if tokens.iter().any(|tt| is_punct(tt, ',')) {
+        return Err("the test can only take the client as parameter".to_owned());
+    }
+    let colon = tokens
+        .iter()
+        .position(|tt| is_punct(tt, ':'))
+        .ok_or_else(|| "the client parameter needs a type".to_owned())?;
+    let ty = tokens.split_off(colon + 1);
+    tokens.pop();
+    Ok((tokens.into_iter().collect(), ty.into_iter().collect()))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn setup(attr: &str, signature: &str) -> Result<(String, String), String> {
+        client_setup(attr.parse().unwrap(), signature.parse().unwrap())
+            .map(|(signature, client)| (signature.to_string(), client.to_string()))
+    }
+
+    #[test]
+    fn success_client() {
+        // The parameter should be bound to a client for the app.
+        let (signature, client) = setup("app = build_app", "fn get(client: TestClient)").unwrap();
+        assert_eq!(signature, "fn get ()");
+        assert_eq!(
+            client,
+            "let client : TestClient = < TestClient > :: new (build_app ()) ;"
+        );
+    }
+
+    #[test]
+    fn success_without_client() {
+        // Plain tests should be left alone.
+        let (signature, client) = setup("", "fn plain()").unwrap();
+        assert_eq!(signature, "fn plain ()");
+        assert!(client.is_empty());
+    }
+
+    #[test]
+    fn fail_client() {
+        // A client needs an app and an app needs a client.
+        assert!(setup("", "fn get(client: TestClient)").is_err());
+        assert!(setup("app = build_app", "fn get()").is_err());
+        assert!(setup("app", "fn get(client: TestClient)").is_err());
+        assert!(setup("app = build_app", "fn get(a: TestClient, b: u8)").is_err());
+    }
+}
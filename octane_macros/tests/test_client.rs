@@ -0,0 +1,34 @@
+use octane::prelude::*;
+use octane::testing::TestClient;
+
+fn app() -> Octane {
+    let mut app = Octane::new();
+    app.get("/", route_stop!(|req, res| res.send("Hello, World")))
+        .unwrap();
+    app.post("/echo", route_stop!(|req, res| res.send(req.body.to_vec())))
+        .unwrap();
+    app
+}
+
+#[octane::test(app = app)]
+async fn get_root(client: TestClient) {
+    // The client should reach the routes of the app.
+    let res = client.get("/").await.unwrap();
+    assert_eq!(res.status, 200);
+    assert_eq!(res.text(), "Hello, World");
+}
+
+#[octane::test(app = app)]
+async fn post_echo(client: TestClient) {
+    // Bodies should be sent along with the request.
+    let res = client.post("/echo", "ping").await.unwrap();
+    assert_eq!(res.body, b"ping");
+    assert_eq!(res.header("content-length"), Some("4"));
+}
+
+#[octane::test(app = app)]
+async fn get_missing(client: TestClient) {
+    // Unknown paths should get the 404 page.
+    let res = client.get("/missing").await.unwrap();
+    assert_eq!(res.status, 404);
+}
@@ -0,0 +1,329 @@
+use crate::constants::BUF_SIZE;
+use crate::request::{Headers, SpooledBody};
+use crate::util::find_in_slice;
+use std::io;
+use std::str;
+use tokio::fs::File;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWriteExt};
+
+// The longest chunk size line we accept, chunk extensions included
+const MAX_SIZE_LINE: usize = 1024;
+
+/// The ways reading a chunked request body can fail
+#[derive(Debug)]
+pub enum ChunkedError {
+    /// The body isn't valid chunked data
+    Malformed,
+    /// The decoded body or the trailers exceed the configured limits
+    TooLarge,
+    /// The connection failed or ended before the last chunk
+    Io(io::Error),
+}
+
+impl From<io::Error> for ChunkedError {
+    fn from(err: io::Error) -> Self {
+        ChunkedError::Io(err)
+    }
+}
+
+// A decoded chunked body along with its trailers and the bytes
+// which were read past its end. The body is empty if it was spooled
+pub struct Chunked {
+    pub body: Vec<u8>,
+    pub spooled: Option<SpooledBody>,
+    pub trailers: Headers,
+    pub rest: Vec<u8>,
+}
+
+// Counts the decoded bytes of a body which doesn't declare its length
+// and fails as soon as they go over the limit. The body is kept in
+// memory until it grows past the spool threshold, then it is moved to
+// a temporary file and the rest is streamed there
+pub struct BodyLimit {
+    limit: Option<usize>,
+    spool_threshold: Option<usize>,
+    counted: usize,
+    memory: Vec<u8>,
+    spooled: Option<(File, SpooledBody)>,
+}
+
+impl BodyLimit {
+    pub fn new(limit: Option<usize>) -> Self {
+        BodyLimit {
+            limit,
+            spool_threshold: None,
+            counted: 0,
+            memory: Vec::new(),
+            spooled: None,
+        }
+    }
+    // Spools the body once it is larger than the threshold
+    pub fn spool_over(mut self, threshold: Option<usize>) -> Self {
+        self.spool_threshold = threshold;
+        self
+    }
+    // Counts bytes which are about to be written, so that an oversized
+    // chunk is refused before it is read
+    fn count(&mut self, len: usize) -> Result<(), ChunkedError> {
+        self.counted = self.counted.saturating_add(len);
+        if self.limit.map_or(false, |max| self.counted > max) {
+            return Err(ChunkedError::TooLarge);
+        }
+        Ok(())
+    }
+    async fn write(&mut self, data: &[u8]) -> Result<(), ChunkedError> {
+        let spool = self.spool_threshold.map_or(false, |threshold| {
+            self.memory.len() + data.len() > threshold
+        });
+        if self.spooled.is_none() && spool {
+            // the handle is created along with the file so it is removed on failure
+            let (file, spooled) = SpooledBody::create()?;
+            let mut file = File::from_std(file);
+            file.write_all(&std::mem::take(&mut self.memory)).await?;
+            self.spooled = Some((file, spooled));
+        }
+        match &mut self.spooled {
+            Some((file, _)) => file.write_all(data).await?,
+            None => self.memory.extend_from_slice(data),
+        }
+        Ok(())
+    }
+    async fn finish(self) -> Result<(Vec<u8>, Option<SpooledBody>), ChunkedError> {
+        match self.spooled {
+            Some((mut file, spooled)) => {
+                file.flush().await?;
+                Ok((Vec::new(), Some(spooled.with_len(self.counted))))
+            }
+            None => Ok((self.memory, None)),
+        }
+    }
+}
+
+// Decodes a chunked body, starting with the bytes which were already
+// read along with the headers. The body limit applies to the decoded
+// size and the trailer limit to the size of the trailer section.
+// When the request announced its trailers with a `Trailer` header,
+// the fields it didn't announce are dropped
+pub async fn read_chunked<R>(
+    received: &[u8],
+    reader: &mut R,
+    mut body: BodyLimit,
+    trailer_limit: Option<usize>,
+    announced: Option<&str>,
+) -> Result<Chunked, ChunkedError>
+where
+    R: AsyncRead + Unpin,
+{
+    let mut data = received.to_vec();
+    let mut pos = 0;
+    loop {
+        let line_end = fill_until(&mut data, pos, reader, Some(MAX_SIZE_LINE)).await?;
+        let line = str::from_utf8(&data[pos..line_end]).map_err(|_| ChunkedError::Malformed)?;
+        // chunk extensions are ignored
+        let size = line.split(';').next().unwrap_or("").trim();
+        let size = usize::from_str_radix(size, 16).map_err(|_| ChunkedError::Malformed)?;
+        pos = line_end + 2;
+        if size == 0 {
+            break;
+        }
+        body.count(size)?;
+        // the chunk is written as it arrives so it is never held whole
+        let mut left = size;
+        loop {
+            let available = left.min(data.len() - pos);
+            body.write(&data[pos..pos + available]).await?;
+            pos += available;
+            left -= available;
+            if left == 0 {
+                break;
+            }
+            data.clear();
+            pos = 0;
+            read_more(&mut data, reader).await?;
+        }
+        fill_to(&mut data, pos + 2, reader).await?;
+        if &data[pos..pos + 2] != b"\r\n" {
+            return Err(ChunkedError::Malformed);
+        }
+        data.drain(..pos + 2);
+        pos = 0;
+    }
+    fill_to(&mut data, pos + 2, reader).await?;
+    let trailers = if &data[pos..pos + 2] == b"\r\n" {
+        pos += 2;
+        Headers::default()
+    } else {
+        // the trailer section ends with an empty line like the headers
+        let end = loop {
+            if let Some(i) = find_in_slice(&data[pos..], b"\r\n\r\n") {
+                break pos + i;
+            }
+            if trailer_limit.map_or(false, |max| data.len() - pos > max) {
+                return Err(ChunkedError::TooLarge);
+            }
+            read_more(&mut data, reader).await?;
+        };
+        let section = str::from_utf8(&data[pos..end]).map_err(|_| ChunkedError::Malformed)?;
+        let trailers = parse_trailers(section, announced)?;
+        pos = end + 4;
+        trailers
+    };
+    let (body, spooled) = body.finish().await?;
+    Ok(Chunked {
+        body,
+        spooled,
+        trailers,
+        rest: data[pos..].to_vec(),
+    })
+}
+
+// Parses the trailer fields, keeping only the announced ones if
+// the request listed them
+fn parse_trailers(section: &str, announced: Option<&str>) -> Result<Headers, ChunkedError> {
+    let announced: Option<Vec<String>> = announced.map(|names| {
+        names
+            .split(',')
+            .map(|name| name.trim().to_ascii_lowercase())
+            .collect()
+    });
+    let fields: Vec<&str> = section
+        .split("\r\n")
+        .filter(|field| match &announced {
+            Some(names) => {
+                let name = field.split(':').next().unwrap_or("");
+                names.contains(&name.trim().to_ascii_lowercase())
+            }
+            None => true,
+        })
+        .collect();
+    if fields.is_empty() {
+        return Ok(Headers::default());
+    }
+    Headers::parse(fields.join("\r\n")).ok_or(ChunkedError::Malformed)
+}
+
+// Reads until there is a CRLF at or after pos and returns its index,
+// failing if more than limit bytes come before it
+async fn fill_until<R>(
+    data: &mut Vec<u8>,
+    pos: usize,
+    reader: &mut R,
+    limit: Option<usize>,
+) -> Result<usize, ChunkedError>
+where
+    R: AsyncRead + Unpin,
+{
+    loop {
+        if let Some(i) = find_in_slice(&data[pos..], b"\r\n") {
+            return Ok(pos + i);
+        }
+        if limit.map_or(false, |max| data.len() - pos > max) {
+            return Err(ChunkedError::TooLarge);
+        }
+        read_more(data, reader).await?;
+    }
+}
+
+// Reads until data holds at least len bytes
+async fn fill_to<R>(data: &mut Vec<u8>, len: usize, reader: &mut R) -> Result<(), ChunkedError>
+where
+    R: AsyncRead + Unpin,
+{
+    while data.len() < len {
+        read_more(data, reader).await?;
+    }
+    Ok(())
+}
+
+async fn read_more<R>(data: &mut Vec<u8>, reader: &mut R) -> Result<(), ChunkedError>
+where
+    R: AsyncRead + Unpin,
+{
+    let mut buf = [0; BUF_SIZE];
+    let read = reader.read(&mut buf).await?;
+    if read == 0 {
+        return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+    }
+    data.extend_from_slice(&buf[..read]);
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    async fn decode(raw: &[u8], announced: Option<&str>) -> Result<Chunked, ChunkedError> {
+        read_chunked(raw, &mut &b""[..], BodyLimit::new(None), None, announced).await
+    }
+
+    #[crate::test]
+    async fn success_chunks() {
+        // Chunks should be joined and extensions ignored.
+        let chunked = decode(b"5;ext=1\r\nHello\r\n7\r\n, World\r\n0\r\n\r\nGET", None)
+            .await
+            .unwrap();
+        assert_eq!(chunked.body, b"Hello, World");
+        assert!(chunked.trailers.is_empty());
+        assert_eq!(chunked.rest, b"GET");
+    }
+
+    #[crate::test]
+    async fn success_split_reads() {
+        // The body should be read from the stream when it isn't buffered.
+        let mut rest = &b"lo\r\n0\r\nX-Checksum: abc\r\n\r\n"[..];
+        let chunked = read_chunked(b"5\r\nHel", &mut rest, BodyLimit::new(None), None, None)
+            .await
+            .unwrap();
+        assert_eq!(chunked.body, b"Hello");
+        assert_eq!(chunked.trailers.get("x-checksum"), Some(&"abc".to_owned()));
+    }
+
+    #[crate::test]
+    async fn success_announced_trailers() {
+        // Only the announced trailers should be kept.
+        let raw = b"0\r\nX-Checksum: abc\r\nX-Other: 1\r\n\r\n";
+        let chunked = decode(raw, Some("X-Checksum")).await.unwrap();
+        assert_eq!(chunked.trailers.get("x-checksum"), Some(&"abc".to_owned()));
+        assert_eq!(chunked.trailers.get("x-other"), None);
+    }
+
+    #[crate::test]
+    async fn fail_chunks() {
+        // Bad sizes, missing CRLFs and oversized bodies should fail.
+        assert!(matches!(
+            decode(b"zz\r\n", None).await,
+            Err(ChunkedError::Malformed)
+        ));
+        assert!(matches!(
+            decode(b"2\r\nabc\r\n0\r\n\r\n", None).await,
+            Err(ChunkedError::Malformed)
+        ));
+        assert!(matches!(
+            decode(b"5\r\nHel", None).await,
+            Err(ChunkedError::Io(_))
+        ));
+        let raw = b"5\r\nHello\r\n0\r\n\r\n";
+        let limited = read_chunked(raw, &mut &b""[..], BodyLimit::new(Some(4)), None, None).await;
+        assert!(matches!(limited, Err(ChunkedError::TooLarge)));
+    }
+
+    #[crate::test]
+    async fn success_spool() {
+        // Bodies past the threshold should be moved to a file.
+        let raw = b"5\r\nHello\r\n7\r\n, World\r\n0\r\n\r\n";
+        let limit = BodyLimit::new(None).spool_over(Some(8));
+        let chunked = read_chunked(raw, &mut &b""[..], limit, None, None)
+            .await
+            .unwrap();
+        assert!(chunked.body.is_empty());
+        let spooled = chunked.spooled.unwrap();
+        assert_eq!(spooled.len(), 12);
+        assert_eq!(std::fs::read(spooled.path()).unwrap(), b"Hello, World");
+        let limit = BodyLimit::new(None).spool_over(Some(12));
+        let chunked = read_chunked(raw, &mut &b""[..], limit, None, None)
+            .await
+            .unwrap();
+        assert_eq!(chunked.body, b"Hello, World");
+        assert!(chunked.spooled.is_none());
+    }
+}
@@ -0,0 +1,117 @@
+use flate2::read::{GzDecoder, ZlibDecoder};
+use std::io::Read;
+
+/// The ways decoding a compressed request body can fail
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DecodeError {
+    /// The body uses a content coding which isn't supported
+    Unsupported,
+    /// The decoded body exceeds the configured body size limit
+    TooLarge,
+    /// The body isn't valid for the coding it declares
+    Malformed,
+}
+
+// Decode a body according to the value of its `Content-Encoding`
+// header. Codings are listed in the order they were applied, so
+// they are undone in reverse. The limit is enforced on the decoded
+// size to protect against decompression bombs
+pub fn decode(encoding: &str, body: &[u8], limit: Option<usize>) -> Result<Vec<u8>, DecodeError> {
+    let mut decoded = body.to_vec();
+    for coding in encoding.rsplit(',').map(str::trim) {
+        decoded = match coding.to_ascii_lowercase().as_str() {
+            "" | "identity" => continue,
+            "gzip" | "x-gzip" => read_limited(GzDecoder::new(&decoded[..]), limit)?,
+            "deflate" => read_limited(ZlibDecoder::new(&decoded[..]), limit)?,
+            _ => return Err(DecodeError::Unsupported),
+        };
+    }
+    Ok(decoded)
+}
+
+// Returns true if bodies encoded with the coding can be decoded
+pub fn supports(coding: &str) -> bool {
+    matches!(
+        coding.to_ascii_lowercase().as_str(),
+        "" | "identity" | "gzip" | "x-gzip" | "deflate"
+    )
+}
+
+// Read the decoder to the end, but never more than one byte over
+// the limit so that we can tell that it was exceeded
+fn read_limited<R: Read>(reader: R, limit: Option<usize>) -> Result<Vec<u8>, DecodeError> {
+    let mut ret = Vec::new();
+    let read = match limit {
+        Some(max) => reader.take(max as u64 + 1).read_to_end(&mut ret),
+        None => { reader }.read_to_end(&mut ret),
+    };
+    read.map_err(|_| DecodeError::Malformed)?;
+    if limit.map_or(false, |max| ret.len() > max) {
+        return Err(DecodeError::TooLarge);
+    }
+    Ok(ret)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use flate2::write::{GzEncoder, ZlibEncoder};
+    use flate2::Compression;
+    use std::io::Write;
+
+    fn gzip(data: &[u8]) -> Vec<u8> {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    #[test]
+    fn success_gzip() {
+        // Gzipped bodies should be decoded.
+        let decoded = decode("gzip", &gzip(b"hello, world"), None).unwrap();
+        assert_eq!(decoded, b"hello, world");
+    }
+
+    #[test]
+    fn success_deflate() {
+        // Deflated bodies should be decoded.
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(b"hello, world").unwrap();
+        let decoded = decode("deflate", &encoder.finish().unwrap(), None).unwrap();
+        assert_eq!(decoded, b"hello, world");
+    }
+
+    #[test]
+    fn success_multiple_codings() {
+        // Multiple codings should be undone in reverse order.
+        let twice = gzip(&gzip(b"hello, world"));
+        let decoded = decode("gzip, identity, gzip", &twice, None).unwrap();
+        assert_eq!(decoded, b"hello, world");
+    }
+
+    #[test]
+    fn fail_unsupported() {
+        // Unknown codings should be rejected.
+        assert_eq!(decode("br", b"", None), Err(DecodeError::Unsupported));
+    }
+
+    #[test]
+    fn fail_malformed() {
+        // Bodies that aren't actually compressed should be rejected.
+        assert_eq!(
+            decode("gzip", b"not gzip", None),
+            Err(DecodeError::Malformed)
+        );
+    }
+
+    #[test]
+    fn fail_too_large() {
+        // The limit should apply to the decoded size.
+        let bomb = gzip(&vec![0; 1024 * 1024]);
+        assert!(bomb.len() < 1024 * 16);
+        assert_eq!(
+            decode("gzip", &bomb, Some(1024 * 16)),
+            Err(DecodeError::TooLarge)
+        );
+    }
+}
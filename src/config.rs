@@ -1,3 +1,6 @@
+use crate::constants::{
+    LISTEN_BACKLOG, MAX_HEADERS, MAX_HEADER_SIZE, MAX_PATH_SEGMENTS, MAX_REQUEST_LINE,
+};
 use crate::default;
 use core::time::Duration;
 use std::path::PathBuf;
@@ -34,6 +37,9 @@ pub struct Ssl {
     pub cert: PathBuf,
     /// The port where TLS should listen, it is 443 by default.
     pub port: u16,
+    /// The protocols offered with ALPN during the handshake, in order of
+    /// preference. It is empty by default so no protocol is negotiated.
+    pub alpn: Vec<String>,
 }
 
 impl Ssl {
@@ -44,6 +50,7 @@ impl Ssl {
             key: PathBuf::new(),
             cert: PathBuf::new(),
             port: 443,
+            alpn: Vec::new(),
         }
     }
     /// Mutates the Ssl struct and sets the private key path
@@ -78,6 +85,26 @@ impl Ssl {
         self.cert = PathBuf::from(path);
         self
     }
+    /// Mutates the Ssl struct and sets the protocols offered with
+    /// ALPN, the one the client picked is available with
+    /// [`Request::alpn_protocol()`](../request/struct.Request.html#method.alpn_protocol).
+    /// Octane only speaks HTTP/1.1 so offering `h2` only makes sense
+    /// behind something which handles it
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use octane::config::OctaneConfig;
+    ///
+    /// let mut config = OctaneConfig::new();
+    /// config
+    ///    .ssl
+    ///    .alpn(&["http/1.1"]);
+    /// ```
+    pub fn alpn(&mut self, protocols: &[&str]) -> &mut Self {
+        self.alpn = protocols.iter().map(|p| (*p).to_owned()).collect();
+        self
+    }
 }
 
 /// An independent OctaneConfig struct that can be used
@@ -101,10 +128,128 @@ impl Ssl {
 /// item. If no config is specified then defaults are used.
 ///
 pub struct OctaneConfig {
-    /// The duration for keep alive requests. It is 5 seconds by default
+    /// The duration for keep alive requests. It is 5 seconds by default,
+    /// `None` disables keep alive
     pub keep_alive: Option<Duration>,
     /// An instance of the `Ssl` struct to store the values of key and certificates.
     pub ssl: Ssl,
+    /// Whether `TCP_NODELAY` should be set on the accepted sockets, disabling
+    /// Nagle's algorithm. It is true by default
+    pub tcp_nodelay: bool,
+    /// The maximum size of a request body in bytes, larger bodies are
+    /// rejected with `413 Payload Too Large`. When the `compression`
+    /// feature is enabled, the limit also applies to the decompressed
+    /// body. Chunked bodies, which don't declare their length, are cut
+    /// off as soon as they go over it. It is `None` by default, which
+    /// means no limit, except that bodies decompressed under the
+    /// `compression` feature are still limited to 10 megabytes once
+    /// decompressed so that a small body can't expand without bound
+    pub max_body_size: Option<usize>,
+    /// Request bodies larger than this many bytes are written to a temporary
+    /// file instead of being buffered in memory, and are available to the
+    /// handlers through `req.spooled_body`. Chunked bodies are buffered
+    /// until they grow past it and then streamed to the file. When the
+    /// `compression` feature is enabled, bodies with a `Content-Encoding`
+    /// or a transfer coding other than chunked are never spooled, they
+    /// are decoded in memory under `max_body_size`. Spool files get
+    /// random names and are only readable by the owner. It is `None`
+    /// (never spool) by default
+    pub spool_threshold: Option<usize>,
+    /// The maximum length of the request line in bytes, longer request
+    /// lines are rejected with `414 URI Too Long`. It is 8 kilobytes by
+    /// default, `None` means no limit
+    pub max_request_line: Option<usize>,
+    /// The maximum number of segments in the path of a request, the
+    /// parts between its slashes, deeper paths are rejected with
+    /// `414 URI Too Long` before they are parsed. It is 128 by default,
+    /// `None` means no limit
+    pub max_path_segments: Option<usize>,
+    /// The maximum size of the request headers in bytes, larger headers
+    /// are rejected with `431 Request Header Fields Too Large`. It is 16
+    /// kilobytes by default, `None` means no limit
+    pub max_header_size: Option<usize>,
+    /// The maximum number of request headers, requests with more
+    /// are rejected with `431 Request Header Fields Too Large`. It
+    /// is 100 by default, `None` means no limit
+    pub max_headers: Option<usize>,
+    /// Whether the server should count requests, responses and connections,
+    /// see [`Octane::metrics_snapshot()`](../struct.Octane.html#method.metrics_snapshot).
+    /// It is false by default
+    pub metrics: bool,
+    /// The maximum number of pending connections the listener queues up
+    /// before the OS starts refusing them, a larger value helps with bursts
+    /// of connections. It is 2048 by default
+    pub listen_backlog: i32,
+    /// Whether the server runs behind a trusted proxy, in which case the
+    /// `X-Forwarded-Proto` header is used to tell if the client connected
+    /// over https, see [`Request::is_secure()`](../request/struct.Request.html#method.is_secure).
+    /// It is false by default as clients can set the header themselves
+    pub trust_proxy: bool,
+    /// Whether the status line and headers should be flushed to the client
+    /// before the body is sent. By default they are buffered with the start
+    /// of the body so that small responses go out in a single write, which
+    /// delays the first byte when a body is slow to produce. Streamed bodies,
+    /// like server-sent events, are always flushed early. It is false by default
+    pub flush_headers_early: bool,
+    /// The `Content-Type` of files sent with
+    /// [`Response::send_file()`](../responder/struct.Response.html#method.send_file)
+    /// whose extension isn't known, like `text/plain`. It is `None` by
+    /// default, which sends them as `application/octet-stream`
+    pub unknown_file_type: Option<String>,
+    /// Whether text request bodies, those with a `text/*`, json or
+    /// urlencoded form `Content-Type`, are rejected with `400 Bad Request`
+    /// when they aren't valid UTF-8. It is false by default, in which case
+    /// handlers can check the body themselves with
+    /// [`Request::body_str()`](../request/struct.Request.html#method.body_str)
+    pub strict_utf8: bool,
+    /// Whether request paths are matched without regard to case, which
+    /// helps with links written for Windows servers. The static parts of
+    /// route paths match in any case, while url variables and the path
+    /// static files are looked up with keep the case of the request. It
+    /// is false by default as urls are case sensitive
+    pub case_insensitive_paths: bool,
+    /// The html the built-in error pages, like the `404 Not Found` page, are
+    /// rendered from instead of the default page. `{{code}}` is replaced by
+    /// the status code, `{{status}}` by its name, `{{reason}}` by the
+    /// explanation of the error, if there is one, and `{{path}}` by the path
+    /// of the request, when it is known. Errors sent as json don't use it
+    pub error_template: Option<String>,
+    /// Whether the head of each request, the request line and headers,
+    /// is kept exactly as it was received so that logging middleware can
+    /// see it through
+    /// [`Request::raw_head()`](../request/struct.Request.html#method.raw_head).
+    /// It is false by default to avoid copying every head
+    pub capture_raw_head: bool,
+    /// The `Cache-Control` header of the files served by
+    /// [`Octane::static_dir()`](../struct.Octane.html#method.static_dir) and
+    /// [`Route::file()`](../router/trait.Route.html#method.file), see
+    /// [`Config::set_static_cache_control()`](trait.Config.html#tymethod.set_static_cache_control).
+    /// By default none is sent
+    pub static_cache_control: Option<String>,
+    /// Whether HTTP/0.9 simple requests, a `GET` request line without a
+    /// version like `GET /index.html`, are answered. Such responses are
+    /// only the body, without a status line or headers, and the
+    /// connection is closed after them. It is false by default, in which
+    /// case they are rejected with `400 Bad Request`. Simple requests are
+    /// only told apart under the `faithful` feature
+    pub http09: bool,
+    /// Whether a directory requested from
+    /// [`Octane::static_dir()`](../struct.Octane.html#method.static_dir)
+    /// gets a listing of its entries when it has no `index.html`, as an
+    /// html page with links or as json for clients preferring it. Hidden
+    /// entries are left out, and the directory is read off the async
+    /// workers so the listing is streamed and the connection closed after
+    /// it. It is false by default so that the layout of the served
+    /// directories isn't exposed
+    pub autoindex: bool,
+    /// The longest the closures answering a request may run for. Requests
+    /// whose closures haven't returned by then are answered with `504
+    /// Gateway Timeout` and the response of the closures is dropped once
+    /// they do, as they can't be interrupted. The closures run on the
+    /// blocking threads of the runtime then, with a copy of the request
+    /// body. It is `None` by default, which runs them on the task serving
+    /// the connection without a limit
+    pub route_timeout: Option<Duration>,
     worker_threads: Option<usize>,
 }
 
@@ -135,6 +280,36 @@ pub trait Config {
     /// app.set_keepalive(Duration::new(5, 0));
     /// ```
     fn set_keepalive(&mut self, duration: Duration);
+    /// Disables keep alive entirely, every response is sent with
+    /// `Connection: close` and the connection is closed after a
+    /// single request, regardless of what the client asked for
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use octane::Octane;
+    /// use octane::config::Config;
+    ///
+    /// let mut app = Octane::new();
+    /// app.disable_keepalive();
+    /// ```
+    fn disable_keepalive(&mut self);
+    /// Sets the `Cache-Control` header sent with static files, like
+    /// `public, max-age=31536000, immutable` for assets with a hash in
+    /// their names. The `ETag` and `Last-Modified` headers are still sent
+    /// so that clients can revalidate once the file is stale, and closures
+    /// which set a `Cache-Control` before the file is served keep theirs
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use octane::Octane;
+    /// use octane::config::Config;
+    ///
+    /// let mut app = Octane::new();
+    /// app.set_static_cache_control("public, max-age=31536000, immutable");
+    /// ```
+    fn set_static_cache_control(&mut self, value: &str);
     /// Replaces the current ssl config with the one
     /// specified in the arguments
     ///
@@ -205,13 +380,112 @@ impl OctaneConfig {
         OctaneConfig {
             ssl: Ssl::new(),
             keep_alive: Some(Duration::from_secs(5)),
+            tcp_nodelay: true,
+            max_body_size: None,
+            spool_threshold: None,
+            max_request_line: Some(MAX_REQUEST_LINE),
+            max_path_segments: Some(MAX_PATH_SEGMENTS),
+            max_header_size: Some(MAX_HEADER_SIZE),
+            max_headers: Some(MAX_HEADERS),
+            metrics: false,
+            listen_backlog: LISTEN_BACKLOG,
+            trust_proxy: false,
+            flush_headers_early: false,
+            unknown_file_type: None,
+            strict_utf8: false,
+            case_insensitive_paths: false,
+            error_template: None,
+            capture_raw_head: false,
+            static_cache_control: None,
+            http09: false,
+            autoindex: false,
+            route_timeout: None,
             worker_threads: None,
         }
     }
-    // Appends a settings instance to self
+    // Appends a settings instance to self, a field only replaces the
+    // current value if it was changed from its default in the appended
+    // settings, so partial configs don't reset what was set before
     pub(crate) fn append(&mut self, settings: Self) {
-        self.ssl = settings.ssl;
-        self.keep_alive = settings.keep_alive;
+        let default = Self::new();
+        if !settings.ssl.key.as_os_str().is_empty() {
+            self.ssl.key = settings.ssl.key;
+        }
+        if !settings.ssl.cert.as_os_str().is_empty() {
+            self.ssl.cert = settings.ssl.cert;
+        }
+        if settings.ssl.port != default.ssl.port {
+            self.ssl.port = settings.ssl.port;
+        }
+        if !settings.ssl.alpn.is_empty() {
+            self.ssl.alpn = settings.ssl.alpn;
+        }
+        if settings.keep_alive != default.keep_alive {
+            self.keep_alive = settings.keep_alive;
+        }
+        if settings.tcp_nodelay != default.tcp_nodelay {
+            self.tcp_nodelay = settings.tcp_nodelay;
+        }
+        if settings.max_body_size != default.max_body_size {
+            self.max_body_size = settings.max_body_size;
+        }
+        if settings.spool_threshold.is_some() {
+            self.spool_threshold = settings.spool_threshold;
+        }
+        if settings.max_request_line != default.max_request_line {
+            self.max_request_line = settings.max_request_line;
+        }
+        if settings.max_path_segments != default.max_path_segments {
+            self.max_path_segments = settings.max_path_segments;
+        }
+        if settings.max_header_size != default.max_header_size {
+            self.max_header_size = settings.max_header_size;
+        }
+        if settings.max_headers != default.max_headers {
+            self.max_headers = settings.max_headers;
+        }
+        if settings.metrics {
+            self.metrics = true;
+        }
+        if settings.listen_backlog != default.listen_backlog {
+            self.listen_backlog = settings.listen_backlog;
+        }
+        if settings.trust_proxy {
+            self.trust_proxy = true;
+        }
+        if settings.flush_headers_early {
+            self.flush_headers_early = true;
+        }
+        if settings.unknown_file_type.is_some() {
+            self.unknown_file_type = settings.unknown_file_type;
+        }
+        if settings.strict_utf8 {
+            self.strict_utf8 = true;
+        }
+        if settings.case_insensitive_paths {
+            self.case_insensitive_paths = true;
+        }
+        if settings.error_template.is_some() {
+            self.error_template = settings.error_template;
+        }
+        if settings.capture_raw_head {
+            self.capture_raw_head = true;
+        }
+        if settings.static_cache_control.is_some() {
+            self.static_cache_control = settings.static_cache_control;
+        }
+        if settings.http09 {
+            self.http09 = true;
+        }
+        if settings.autoindex {
+            self.autoindex = true;
+        }
+        if settings.route_timeout.is_some() {
+            self.route_timeout = settings.route_timeout;
+        }
+        if settings.worker_threads.is_some() {
+            self.worker_threads = settings.worker_threads;
+        }
     }
 
     /// Sets the number of worker threads, this is settings
@@ -248,13 +522,60 @@ impl Config for OctaneConfig {
     fn set_keepalive(&mut self, duration: Duration) {
         self.keep_alive = Some(duration);
     }
+    fn disable_keepalive(&mut self) {
+        self.keep_alive = None;
+    }
+    fn set_static_cache_control(&mut self, value: &str) {
+        self.static_cache_control = Some(value.to_owned());
+    }
 
     fn with_ssl_config(&mut self, ssl_conf: Ssl) {
         self.ssl.key = ssl_conf.key;
         self.ssl.cert = ssl_conf.cert;
+        self.ssl.alpn = ssl_conf.alpn;
     }
     fn ssl(&mut self, port: u16) -> &mut Ssl {
         self.ssl.port = port;
         &mut self.ssl
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn success_append_ssl_only() {
+        // Appending an ssl only config shouldn't reset other settings.
+        let mut settings = OctaneConfig::new();
+        settings.disable_keepalive();
+        settings.tcp_nodelay = false;
+        settings.spool_threshold = Some(1024);
+        let mut ssl_only = OctaneConfig::new();
+        ssl_only.ssl(8443).key("key.pem").cert("cert.pem");
+        settings.append(ssl_only);
+        assert_eq!(settings.keep_alive, None);
+        assert!(!settings.tcp_nodelay);
+        assert_eq!(settings.spool_threshold, Some(1024));
+        assert_eq!(settings.ssl.port, 8443);
+        assert_eq!(settings.ssl.key, PathBuf::from("key.pem"));
+        assert_eq!(settings.ssl.cert, PathBuf::from("cert.pem"));
+    }
+
+    #[test]
+    fn success_append_keeps_ssl() {
+        // Appending a config without ssl shouldn't clobber the existing ssl.
+        let mut settings = OctaneConfig::new();
+        settings.ssl(8443).key("key.pem").cert("cert.pem");
+        let mut other = OctaneConfig::new();
+        other.set_keepalive(Duration::from_secs(30));
+        other.max_body_size = None;
+        other.worker_threads(4);
+        settings.append(other);
+        assert_eq!(settings.ssl.port, 8443);
+        assert_eq!(settings.ssl.key, PathBuf::from("key.pem"));
+        assert_eq!(settings.keep_alive, Some(Duration::from_secs(30)));
+        assert_eq!(settings.max_body_size, None);
+        assert_eq!(settings.worker_threads, Some(4));
+    }
+}
@@ -1,6 +1,7 @@
 #![allow(dead_code)]
 use std::collections::HashSet;
 use std::iter::FromIterator;
+use std::time::Duration;
 
 pub const SP: char = ' ';
 pub const HT: char = '\t';
@@ -10,7 +11,7 @@ pub const CRLF: &str = "\r\n";
 pub const B_CRLF: &[u8] = b"\r\n";
 pub const WEEKS: [&str; 7] = ["Sun", "Mon", "Tue", "Wed", "Thu", "Fri", "Sat"];
 pub const MONTHS: [&str; 12] = [
-    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sept", "Oct", "Nov", "Dec",
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
 ];
 
 // constants for time.rs
@@ -19,9 +20,24 @@ pub const DAYS_PER_400Y: i64 = 365 * 400 + 97;
 pub const DAYS_PER_100Y: i64 = 365 * 100 + 24;
 pub const DAYS_PER_4Y: i64 = 365 * 4 + 1;
 pub static DAYS_IN_MONTH: [i64; 12] = [31, 30, 31, 30, 31, 31, 30, 31, 30, 31, 31, 29];
-pub const NOT_FOUND: &str = r#"<!DOCTYPE html><html><head><title>404 NOT FOUND - OCTANE</title></head><body style="padding: 20px;"><h2 style="text-align: center;">404 NOT FOUND</h2><hr><h5>OCTANE - 0.1</h2></body></html>"#;
 // Default buffer size
 pub const BUF_SIZE: usize = 512;
+// Maximum size of a decompressed request body when there is no
+// limit set for it, 10 megabytes
+#[cfg(feature = "compression")]
+pub const MAX_BODY_SIZE: usize = 10 * 1024 * 1024;
+// Default maximum length of the request line, 8 kilobytes
+pub const MAX_REQUEST_LINE: usize = 8 * 1024;
+// Default maximum number of segments in a request path
+pub const MAX_PATH_SEGMENTS: usize = 128;
+// Default maximum size of the request headers, 16 kilobytes
+pub const MAX_HEADER_SIZE: usize = 16 * 1024;
+// Default maximum number of request headers
+pub const MAX_HEADERS: usize = 100;
+// How long clients are asked to wait before retrying while the server drains
+pub const RETRY_AFTER: Duration = Duration::from_secs(5);
+// Default size of the queue of pending connections on the listener
+pub const LISTEN_BACKLOG: i32 = 2048;
 
 lazy_static! {
     pub static ref TOKEN_CHARS: HashSet<char> = HashSet::from_iter(
@@ -1,6 +1,7 @@
 use crate::constants::*;
 use crate::{default, deref};
 use std::collections::HashMap;
+use std::time::Duration;
 
 /// Represents the cookies, cookies are stored
 /// with the name and values. By default you have
@@ -29,6 +30,8 @@ use std::collections::HashMap;
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Cookies {
     cookies: HashMap<String, String>,
+    // the attributes of the cookies which were set with some
+    attributes: HashMap<String, CookieAttributes>,
 }
 
 impl Cookies {
@@ -36,6 +39,7 @@ impl Cookies {
     pub(crate) fn new() -> Self {
         Cookies {
             cookies: HashMap::new(),
+            attributes: HashMap::new(),
         }
     }
     // Parse a Cookie header value and populate the
@@ -50,7 +54,10 @@ impl Cookies {
             let (first, second) = tok.split_at(eq_ind);
             cookies.insert(first.to_owned(), second[1..].to_owned());
         }
-        Self { cookies }
+        Self {
+            cookies,
+            attributes: HashMap::new(),
+        }
     }
     /// Insert a cookie with the `key` being the name and
     /// `value` being the value of the cookie. This is called
@@ -71,19 +78,133 @@ impl Cookies {
     /// );
     /// ```
     pub fn set(&mut self, key: &str, value: &str) {
+        self.attributes.remove(key);
         self.cookies.insert(key.to_owned(), value.to_owned());
     }
+    /// Insert a cookie along with its attributes, this is called
+    /// when you do `res.cookie_with("name", "value", &attributes)`
+    pub fn set_with(&mut self, key: &str, value: &str, attributes: &CookieAttributes) {
+        self.set(key, value);
+        self.attributes.insert(key.to_owned(), attributes.clone());
+    }
     // Prepare the `Set-Cookie` Header string from the values
     // in the HashMap
     pub(crate) fn serialise(&self) -> String {
         let mut cookies_str = String::new();
         for cookie in self.iter() {
-            cookies_str.push_str(&format!("Set-Cookie:{}={}{}", cookie.0, cookie.1, CRLF))
+            let attributes = self
+                .attributes
+                .get(cookie.0)
+                .map_or_else(String::new, CookieAttributes::serialise);
+            cookies_str.push_str(&format!(
+                "Set-Cookie:{}={}{}{}",
+                cookie.0, cookie.1, attributes, CRLF
+            ))
         }
         cookies_str
     }
 }
 
+/// The value of the `SameSite` attribute of a cookie, which controls
+/// whether browsers send it along with requests from other sites
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SameSite {
+    /// The cookie is only sent with requests from the same site
+    Strict,
+    /// The cookie is also sent when navigating to the site from another one
+    Lax,
+    /// The cookie is sent with every request, browsers require it to be `Secure`
+    None,
+}
+
+/// The attributes sent along with a cookie, see
+/// [`res.cookie_with()`](../responder/struct.Response.html#method.cookie_with)
+///
+/// # Example
+///
+/// ```
+/// use octane::cookies::{CookieAttributes, SameSite};
+///
+/// let mut attributes = CookieAttributes::new();
+/// attributes.path("/").http_only().same_site(SameSite::Lax);
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CookieAttributes {
+    /// The path the cookie is sent for, `Path`
+    pub path: Option<String>,
+    /// For how long the cookie is kept, `Max-Age`
+    pub max_age: Option<Duration>,
+    /// Whether scripts are kept from reading the cookie, `HttpOnly`
+    pub http_only: bool,
+    /// Whether the cookie is only sent over https, `Secure`
+    pub secure: bool,
+    /// Whether the cookie is sent with requests from other sites, `SameSite`
+    pub same_site: Option<SameSite>,
+}
+
+impl CookieAttributes {
+    /// Returns attributes which are all unset
+    pub fn new() -> Self {
+        CookieAttributes {
+            path: None,
+            max_age: None,
+            http_only: false,
+            secure: false,
+            same_site: None,
+        }
+    }
+    /// Sets the path the cookie is sent for
+    pub fn path(&mut self, path: &str) -> &mut Self {
+        self.path = Some(path.to_owned());
+        self
+    }
+    /// Sets for how long the cookie is kept, a zero duration removes it
+    pub fn max_age(&mut self, max_age: Duration) -> &mut Self {
+        self.max_age = Some(max_age);
+        self
+    }
+    /// Keeps scripts from reading the cookie
+    pub fn http_only(&mut self) -> &mut Self {
+        self.http_only = true;
+        self
+    }
+    /// Only sends the cookie over https
+    pub fn secure(&mut self) -> &mut Self {
+        self.secure = true;
+        self
+    }
+    /// Sets whether the cookie is sent with requests from other sites
+    pub fn same_site(&mut self, same_site: SameSite) -> &mut Self {
+        self.same_site = Some(same_site);
+        self
+    }
+    // Formats the attributes as they follow the value in `Set-Cookie`
+    fn serialise(&self) -> String {
+        let mut attributes = String::new();
+        if let Some(path) = &self.path {
+            attributes.push_str(&format!("; Path={}", path));
+        }
+        if let Some(max_age) = self.max_age {
+            attributes.push_str(&format!("; Max-Age={}", max_age.as_secs()));
+        }
+        if self.http_only {
+            attributes.push_str("; HttpOnly");
+        }
+        if self.secure {
+            attributes.push_str("; Secure");
+        }
+        match self.same_site {
+            Some(SameSite::Strict) => attributes.push_str("; SameSite=Strict"),
+            Some(SameSite::Lax) => attributes.push_str("; SameSite=Lax"),
+            Some(SameSite::None) => attributes.push_str("; SameSite=None"),
+            None => (),
+        }
+        attributes
+    }
+}
+
+default!(CookieAttributes);
+
 deref!(Cookies, HashMap<String, String>, cookies);
 
 default!(Cookies);
@@ -118,4 +239,25 @@ mod test {
         // Set-Cookie:forth_key=value\r\nSet-Cookie:first_key=value\r\nSet-Cookie:third_key=value\r\nSet-Cookie:second_key=value\r\n
         assert_eq!(113, cookies.serialise().len());
     }
+
+    #[test]
+    pub fn cookie_attributes() {
+        // attributes should follow the value of the cookie
+        let mut cookies = Cookies::new();
+        let mut attributes = CookieAttributes::new();
+        attributes
+            .path("/")
+            .max_age(Duration::from_secs(60))
+            .http_only()
+            .secure()
+            .same_site(SameSite::Lax);
+        cookies.set_with("sid", "abc", &attributes);
+        assert_eq!(
+            cookies.serialise(),
+            "Set-Cookie:sid=abc; Path=/; Max-Age=60; HttpOnly; Secure; SameSite=Lax\r\n"
+        );
+        // setting it again without attributes drops them
+        cookies.set("sid", "abc");
+        assert_eq!(cookies.serialise(), "Set-Cookie:sid=abc\r\n");
+    }
 }
@@ -0,0 +1,109 @@
+use crate::default;
+use crate::request::{MatchedRequest, RequestMethod};
+use crate::responder::Response;
+use crate::router::Flow;
+use std::time::Duration;
+
+/// The settings of the CORS middleware returned by
+/// [`Octane::cors()`](../struct.Octane.html#method.cors). By default
+/// every origin is allowed with the common methods, the headers a
+/// preflight asks for are allowed and preflights aren't cached
+///
+/// # Example
+///
+/// ```
+/// use octane::cors::CorsConfig;
+/// use std::time::Duration;
+///
+/// let mut cors = CorsConfig::new();
+/// cors.origin("https://example.com")
+///     .max_age(Duration::from_secs(600));
+/// ```
+#[derive(Debug, Clone)]
+pub struct CorsConfig {
+    /// The origins which may make requests, any origin may if it is empty
+    pub origins: Vec<String>,
+    /// The methods allowed in preflight responses
+    pub methods: Vec<String>,
+    /// The request headers allowed in preflight responses, the headers
+    /// the preflight asks for are allowed if it is empty
+    pub headers: Vec<String>,
+    /// For how long browsers may cache a preflight response, sent as
+    /// `Access-Control-Max-Age`. It is `None` by default so the header
+    /// isn't sent
+    pub max_age: Option<Duration>,
+}
+
+impl CorsConfig {
+    /// Returns the default cors settings
+    pub fn new() -> Self {
+        CorsConfig {
+            origins: Vec::new(),
+            methods: ["GET", "HEAD", "POST", "PUT", "PATCH", "DELETE"]
+                .iter()
+                .map(|m| (*m).to_owned())
+                .collect(),
+            headers: Vec::new(),
+            max_age: None,
+        }
+    }
+    /// Adds an origin which may make requests, once one is added the
+    /// other origins aren't allowed anymore
+    pub fn origin(&mut self, origin: &str) -> &mut Self {
+        self.origins.push(origin.to_owned());
+        self
+    }
+    /// Sets the methods allowed in preflight responses
+    pub fn methods(&mut self, methods: &[&str]) -> &mut Self {
+        self.methods = methods.iter().map(|m| (*m).to_owned()).collect();
+        self
+    }
+    /// Sets the request headers allowed in preflight responses
+    pub fn headers(&mut self, headers: &[&str]) -> &mut Self {
+        self.headers = headers.iter().map(|h| (*h).to_owned()).collect();
+        self
+    }
+    /// Sets for how long browsers may cache preflight responses
+    pub fn max_age(&mut self, max_age: Duration) -> &mut Self {
+        self.max_age = Some(max_age);
+        self
+    }
+    // Sets the cors headers for requests from an allowed origin and
+    // answers preflights, which are OPTIONS requests naming the method
+    // of the actual request
+    pub(crate) fn apply(&self, req: &MatchedRequest, res: &mut Response) -> Flow {
+        let origin = match req.headers.get("origin") {
+            Some(origin) => origin,
+            None => return Flow::Next,
+        };
+        if self.origins.is_empty() {
+            res.set("Access-Control-Allow-Origin", "*");
+        } else if self.origins.contains(origin) {
+            res.set("Access-Control-Allow-Origin", origin)
+                .set("Vary", "Origin");
+        } else {
+            return Flow::Next;
+        }
+        let preflight = req.request_line.method == RequestMethod::Options
+            && req.headers.get("access-control-request-method").is_some();
+        if !preflight {
+            return Flow::Next;
+        }
+        res.set("Access-Control-Allow-Methods", &self.methods.join(", "));
+        let headers = if self.headers.is_empty() {
+            req.headers.get("access-control-request-headers").cloned()
+        } else {
+            Some(self.headers.join(", "))
+        };
+        if let Some(headers) = headers {
+            res.set("Access-Control-Allow-Headers", &headers);
+        }
+        if let Some(max_age) = self.max_age {
+            res.set("Access-Control-Max-Age", &max_age.as_secs().to_string());
+        }
+        res.no_content();
+        Flow::Stop
+    }
+}
+
+default!(CorsConfig);
@@ -1,4 +1,4 @@
-use crate::constants::NOT_FOUND;
+use crate::request::{RequestLine, RequestMethod};
 use crate::responder::Response;
 use crate::responder::StatusCode;
 use crate::Octane;
@@ -19,6 +19,10 @@ use tokio::io::AsyncWrite;
 /// `res.status(status_code).send("")`.
 pub struct Error {
     kind: StatusCode,
+    reason: Option<String>,
+    json: bool,
+    path: Option<String>,
+    head: bool,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -28,36 +32,179 @@ pub struct InvalidPathError;
 // Custom error type for invalid SSL certificates
 pub struct InvalidCertError;
 
-/// Takes in a http stream and a error code and sends to the client
+/// Takes in a http stream and a error code and sends to the client.
+/// The connection is kept alive if `ret`, the keep alive duration
+/// which is returned, is some and closed otherwise
 #[macro_export]
 #[doc(hidden)]
 macro_rules! declare_error {
-    ($stream : expr, $error_type : expr) => {
-        Error::err($error_type, $stream).await?;
-        return Ok(());
+    ($stream : expr, $error_type : expr, $ret : expr, $server : expr) => {
+        declare_error!($stream, $error_type, $ret, $server, None, false, None);
+    };
+    ($stream : expr, $error_type : expr, $ret : expr, $server : expr, $reason : expr) => {
+        declare_error!(
+            $stream,
+            $error_type,
+            $ret,
+            $server,
+            Some($reason),
+            false,
+            None
+        );
+    };
+    // sends the error as json instead of html if json is true
+    ($stream : expr, $error_type : expr, $ret : expr, $server : expr, $reason : expr, $json : expr) => {
+        declare_error!($stream, $error_type, $ret, $server, $reason, $json, None);
+    };
+    // the request line gives the path to the error template and leaves
+    // the body out of the responses to HEAD requests
+    ($stream : expr, $error_type : expr, $ret : expr, $server : expr, $reason : expr, $json : expr, $request_line : expr) => {
+        let keep_alive: Option<std::time::Duration> = $ret;
+        let headers: &[(&str, &str)] = match keep_alive {
+            Some(_) => &[("Connection", "keep-alive")],
+            None => &[],
+        };
+        let sent = Error::respond(
+            $server,
+            $error_type,
+            $reason,
+            $json,
+            $request_line,
+            headers,
+            $stream,
+        )
+        .await?;
+        if let Some(metrics) = $server.metrics() {
+            metrics.response($error_type, sent);
+        }
+        #[cfg(feature = "tracing")]
+        crate::trace::responded($error_type, sent);
+        return Ok(keep_alive);
     };
 }
 
 impl Error {
-    pub async fn err<S>(status_code: StatusCode, stream: S) -> Result<(), Box<dyn error::Error>>
+    // Sends the error as a json envelope if the client prefers json,
+    // see Response::send_json_error, and as the html page otherwise,
+    // rendered from the app's error template if it has one. Responses
+    // to HEAD requests keep the length of the body they leave out
+    pub async fn respond<S>(
+        server: &Octane,
+        status_code: StatusCode,
+        reason: Option<&str>,
+        json: bool,
+        request_line: Option<&RequestLine>,
+        headers: &[(&str, &str)],
+        stream: S,
+    ) -> Result<u64, Box<dyn error::Error>>
     where
         S: AsyncWrite + Unpin,
     {
-        Error { kind: status_code }.send(stream).await
+        let error = Error {
+            kind: status_code,
+            reason: reason.map(str::to_owned),
+            json,
+            path: request_line.map(|line| format!("/{}", line.path.chunks.join("/"))),
+            head: request_line.map_or(false, |line| line.method == RequestMethod::Head),
+        };
+        let template = server.settings.error_template.as_deref();
+        error.send(template, headers, stream).await
     }
-    async fn send<S>(self, stream: S) -> Result<(), Box<dyn error::Error>>
+    // Renders the html page sent along with the error
+    fn page(&self, template: Option<&str>) -> String {
+        let code: i32 = self.kind.into();
+        if let Some(template) = template {
+            return render(template, code, self);
+        }
+        let reason = match &self.reason {
+            Some(reason) => format!(
+                "<p style=\"text-align: center;\">{}</p>",
+                escape_html(reason)
+            ),
+            None => String::new(),
+        };
+        format!(
+            r#"<!DOCTYPE html><html><head><title>{0} {1} - OCTANE</title></head><body style="padding: 20px;"><h2 style="text-align: center;">{0} {1}</h2>{2}<hr><h5>OCTANE - 0.1</h5></body></html>"#,
+            code, self.kind, reason
+        )
+    }
+    async fn send<S>(
+        self,
+        template: Option<&str>,
+        headers: &[(&str, &str)],
+        stream: S,
+    ) -> Result<u64, Box<dyn error::Error>>
     where
         S: AsyncWrite + Unpin,
     {
-        let mut res = Response::new_from_slice(NOT_FOUND.as_bytes());
-        res.status(self.kind)
-            .default_headers()
-            .set("Content-Type", "text/html");
+        let mut res = if self.json {
+            let mut res = Response::new_empty();
+            let message = self.reason.as_deref().unwrap_or(self.kind.reason_phrase());
+            res.send_json_error(self.kind, message);
+            res
+        } else {
+            let mut res = Response::new_from_slice(self.page(template).as_bytes());
+            res.status(self.kind)
+                .default_headers()
+                .set("Content-Type", "text/html; charset=utf-8");
+            res
+        };
+        // the connection is closed after an error unless the headers
+        // say otherwise
+        res.set("Connection", "close");
+        for (name, value) in headers {
+            res.set(name, value);
+        }
+
+        let (head, mut body) = res.get_data();
+        if self.head {
+            body = Box::new(tokio::io::empty());
+        }
+        Octane::send((head, body), stream, false).await
+    }
+}
+
+// Substitutes the placeholders of an error template in one pass, so
+// that placeholders inside the values are left alone. The values are
+// escaped as the reason and the path come from the request
+fn render(template: &str, code: i32, error: &Error) -> String {
+    let mut page = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        page.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let end = match rest.find("}}") {
+            Some(end) => end,
+            None => break,
+        };
+        match &rest[2..end] {
+            "code" => page.push_str(&code.to_string()),
+            "status" => page.push_str(&escape_html(&error.kind.to_string())),
+            "reason" => page.push_str(&escape_html(error.reason.as_deref().unwrap_or(""))),
+            "path" => page.push_str(&escape_html(error.path.as_deref().unwrap_or(""))),
+            _ => page.push_str(&rest[..end + 2]),
+        }
+        rest = &rest[end + 2..];
+    }
+    page.push_str(rest);
+    page
+}
 
-        let response = res.get_data();
-        Octane::send(response, stream).await?;
-        Ok(())
+// Escapes the characters which have a meaning in html, reasons
+// can contain parts of the request
+pub(crate) fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
     }
+    escaped
 }
 
 impl Display for InvalidPathError {
@@ -1,56 +1,51 @@
-use crate::util::AsyncReader;
 use std::error::Error;
 use std::ffi::OsStr;
-use std::fmt;
-use std::fmt::{Display, Formatter};
-use std::fs::{File, Metadata};
+use std::fs::{self, File, Metadata};
+use std::io::ErrorKind;
 use std::path::PathBuf;
 
 /// The FileHandler structure is a helper struct
 /// to manage files, contents and extensions also
-/// to decide their mime types accordingly
+/// to decide their mime types accordingly. The file
+/// is read asynchronously so it can be streamed to the
+/// client without being loaded into memory
 pub struct FileHandler {
     pub file_name: String,
-    pub file: AsyncReader<File>,
+    pub file: tokio::fs::File,
     pub extension: String,
     pub meta: Metadata,
 }
 
-#[derive(Debug, Copy, Clone)]
-/// Custom error type for file handling errors
-struct FileHandlerError {
-    err_type: u8,
-}
-
-impl FileHandlerError {
-    pub fn new(err_type: u8) -> Self {
-        FileHandlerError { err_type }
-    }
-}
-
 impl FileHandler {
-    /// Takes a Pathbuf and returns a FileHandler struct
-    pub fn handle_file(path: &PathBuf) -> Result<Self, Box<dyn Error>> {
-        if let Ok(file) = File::open(path) {
-            let meta = file.metadata()?;
-            if meta.is_file() {
-                let extension = path
-                    .as_path()
-                    .extension()
-                    .and_then(OsStr::to_str)
-                    .unwrap_or("");
-                Ok(FileHandler {
-                    file_name: path.file_name().and_then(OsStr::to_str).unwrap().to_owned(),
-                    file: AsyncReader::new(file),
-                    extension: extension.to_owned(),
-                    meta,
-                })
-            } else {
-                Err(Box::new(FileHandlerError::new(1)))
-            }
-        } else {
-            Err(Box::new(FileHandlerError::new(0)))
+    /// Takes a Pathbuf and returns a FileHandler struct, or None if
+    /// the path isn't a regular file. Directories, dangling symlinks
+    /// and missing files are all treated the same way
+    pub fn handle_file(path: &PathBuf) -> Result<Option<Self>, Box<dyn Error>> {
+        // metadata follows symlinks, check it before opening so that
+        // directories and special files (like fifos) are never opened
+        match fs::metadata(path) {
+            Ok(meta) if meta.is_file() => (),
+            Ok(_) => return Ok(None),
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(Box::new(e)),
         }
+        let file = File::open(path)?;
+        let meta = file.metadata()?;
+        // the path could have been swapped since the first check
+        if !meta.is_file() {
+            return Ok(None);
+        }
+        let extension = path
+            .as_path()
+            .extension()
+            .and_then(OsStr::to_str)
+            .unwrap_or("");
+        Ok(Some(FileHandler {
+            file_name: path.file_name().and_then(OsStr::to_str).unwrap().to_owned(),
+            file: tokio::fs::File::from_std(file),
+            extension: extension.to_owned(),
+            meta,
+        }))
     }
     /// A helper method to get extension from a
     /// PathBuf
@@ -64,9 +59,17 @@ impl FileHandler {
         )
     }
     /// Perform a match on the extension and
-    /// return the mime type accordingly
+    /// return the mime type accordingly, unknown
+    /// extensions are `application/octet-stream`
     pub fn mime_type(extension: String) -> String {
-        match extension.to_lowercase().as_str() {
+        Self::known_mime_type(&extension)
+            .unwrap_or("application/octet-stream")
+            .to_owned()
+    }
+    /// Returns the mime type of the extension, or None
+    /// if the extension isn't known
+    pub fn known_mime_type(extension: &str) -> Option<&'static str> {
+        let mime = match extension.to_lowercase().as_str() {
             // text types
             "css" => "text/css",
             "csv" => "text/csv",
@@ -142,21 +145,56 @@ impl FileHandler {
             "ttf" => "font/ttf",
             "woff" => "font/woff",
             "woff2" => "font/woff2",
-            _ => "text/plain",
-        }
-        .to_owned()
+            _ => return None,
+        };
+        Some(mime)
     }
 }
 
-impl Display for FileHandlerError {
-    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), fmt::Error> {
-        let err_string = match self.err_type {
-            0 => "File not found",
-            1 => "Not a file",
-            _ => "Unknown error",
-        };
-        write!(f, "File Handling error, {}", err_string)
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn success_regular_file() {
+        // Regular files should be handled.
+        let file = FileHandler::handle_file(&PathBuf::from("templates/test.html")).unwrap();
+        assert_eq!(file.unwrap().extension, "html");
+    }
+
+    #[test]
+    fn mime_types() {
+        // Known extensions should map to their type and others to bytes.
+        assert_eq!(FileHandler::mime_type("HTML".to_owned()), "text/html");
+        assert_eq!(
+            FileHandler::mime_type("unknown".to_owned()),
+            "application/octet-stream"
+        );
+        assert_eq!(FileHandler::known_mime_type("unknown"), None);
+    }
+
+    #[test]
+    fn fail_directory() {
+        // Directories aren't files.
+        let file = FileHandler::handle_file(&PathBuf::from("templates/test")).unwrap();
+        assert!(file.is_none());
+    }
+
+    #[test]
+    fn fail_missing() {
+        // Missing files should not be an error.
+        let file = FileHandler::handle_file(&PathBuf::from("templates/missing.html")).unwrap();
+        assert!(file.is_none());
     }
-}
 
-impl Error for FileHandlerError {}
+    #[test]
+    #[cfg(unix)]
+    fn fail_dangling_symlink() {
+        // Symlinks pointing nowhere should be treated as missing.
+        let link = std::env::temp_dir().join(format!("octane-dangling-{}", std::process::id()));
+        std::os::unix::fs::symlink("/nonexistent/octane", &link).unwrap();
+        let file = FileHandler::handle_file(&link);
+        fs::remove_file(&link).unwrap();
+        assert!(file.unwrap().is_none());
+    }
+}
@@ -3,17 +3,19 @@ use crate::request::KeepAlive;
 use std::time::Duration;
 
 pub fn http10_check(validator: &mut Http) {
-    if let Some(connection_type) = validator.request.headers.get("connection") {
-        if connection_type == "keep-alive" {
-            if let Some(keep_alive_header) = validator.request.headers.get("keep-alive") {
-                let header_details = KeepAlive::parse(keep_alive_header);
-
-                validator.set_keepalive(KeepAliveState::Particular(Duration::from_secs(
-                    header_details.timeout().unwrap_or(0),
-                )));
-            }
-        } else if connection_type == "close" {
-            validator.set_keepalive(KeepAliveState::Close)
+    // http 1.0 connections are closed unless kept alive explicitly
+    if validator.connection_has("keep-alive") {
+        let timeout = validator
+            .request
+            .headers
+            .get("keep-alive")
+            .and_then(|header| KeepAlive::parse(header).timeout());
+        if let Some(timeout) = timeout {
+            validator.set_keepalive(KeepAliveState::Particular(Duration::from_secs(timeout)));
+        } else {
+            validator.set_keepalive(KeepAliveState::UserDefined);
         }
+    } else {
+        validator.set_keepalive(KeepAliveState::Close)
     }
 }
@@ -2,15 +2,15 @@ use crate::http::{Http, KeepAliveState};
 use crate::responder::StatusCode;
 
 pub fn http11_check(validator: &mut Http) {
-    if validator.request.headers.get("host").is_none() {
+    // a missing host header is an error for http 1.1 requests
+    if cfg!(feature = "faithful") && validator.request.headers.get("host").is_none() {
         validator.set(StatusCode::BadRequest)
     }
-    if let Some(connection_type) = validator.request.headers.get("connection") {
-        if connection_type == "keep-alive" {
-            validator.set_keepalive(KeepAliveState::UserDefined);
-        } else if connection_type == "close" {
-            validator.set_keepalive(KeepAliveState::Close)
-        }
+    // http 1.1 connections are persistent unless closed explicitly
+    if validator.connection_has("close") {
+        validator.set_keepalive(KeepAliveState::Close)
+    } else {
+        validator.set_keepalive(KeepAliveState::UserDefined);
     }
     // Check for http2 connection header here, if found then call a http2 parse
     // function that will parse http2 frames and parse the request from that
@@ -40,6 +40,13 @@ impl<'a> Http<'a> {
     pub fn set_keepalive(&mut self, keep_alive: KeepAliveState) {
         self.keep_alive = keep_alive
     }
+    // Returns true if the comma separated Connection header
+    // contains the given option, options are case-insensitive
+    pub fn connection_has(&self, option: &str) -> bool {
+        self.request.headers.get("connection").map_or(false, |v| {
+            v.split(',').any(|x| x.trim().eq_ignore_ascii_case(option))
+        })
+    }
     pub fn is_malformed(&self) -> bool {
         self.err_code.is_some()
     }
@@ -56,6 +56,9 @@
 //! - `raw_headers`: To have access to original, un-normalized headers.
 //! - `rustls`: To use rustls for ssl.
 //! - `openSSL`: To use openssl for ssl.
+//! - `compression`: To transparently decompress gzip/deflate encoded request bodies.
+//! - `tracing`: To serve every request in a [tracing](https://docs.rs/tracing) span
+//! carrying its id, method and path, with events once it is parsed, routed and responded to.
 //! - `default`: The default set includes faithful, query_strings, cookies,
 //! url_variables, raw_headers.
 //!
@@ -67,15 +70,22 @@
 //! to enable the feature.
 #[macro_use]
 extern crate lazy_static;
+pub(crate) mod chunked;
+#[cfg(feature = "compression")]
+pub(crate) mod compression;
 /// Configurations for Octane web server
 pub mod config;
 pub(crate) mod constants;
 #[cfg(feature = "cookies")]
 /// Module for cookie parsing and handling
 pub mod cookies;
+/// Cors module holds the settings of the CORS middleware
+pub mod cors;
 pub(crate) mod error;
 pub(crate) mod file_handler;
 pub(crate) mod http;
+/// Metrics module contains the counters collected by the server
+pub mod metrics;
 pub(crate) mod middlewares;
 pub(crate) mod path;
 pub(crate) mod query;
@@ -89,9 +99,22 @@ pub(crate) mod server;
 /// Server struct that manages request/response and allows the routes to enter in
 pub use crate::server::Octane;
 pub(crate) mod server_builder;
+#[cfg(feature = "cookies")]
+/// Session module holds the server side sessions and their stores
+pub mod session;
+/// Shutdown module contains the handle to drain the server
+pub mod shutdown;
+/// Server-sent events support, see [`Response::sse()`](responder/struct.Response.html#method.sse)
+pub mod sse;
+/// Testing module contains a client to send requests to an app in tests
+pub mod testing;
 pub(crate) mod time;
 pub(crate) mod tls;
+#[cfg(feature = "tracing")]
+pub(crate) mod trace;
 pub(crate) mod util;
+/// Writer module holds the handle to write response bodies incrementally
+pub mod writer;
 
 // convenient aliasing for octane_json
 pub use octane_json as json;
@@ -104,6 +127,7 @@ pub use octane_macros::test;
 /// Prelude here brings in scope, the [`Route`](router/trait.Route.html) and
 /// [`Config`](config/trait.Config.html) trait, [`Octane`](struct.Octane.html) main server
 /// and [`Router`](router/struct.Router.html) struct with the [`Flow`](router/enum.Flow.html)
+/// and [`StatusCode`](responder/enum.StatusCode.html) enums
 /// and the [`Value`](../octane_json/enum.Value.html) enum, the [`route`](macro.route.html),
 /// [`route_next`](macro.route_next.html), [`path`](macro.path.html), [`route_stop`](macro.route_stop.html)
 /// macros with the [`ToJSON`](../octane_json/convert/trait.ToJSON.html)
@@ -111,6 +135,7 @@ pub use octane_macros::test;
 pub mod prelude {
     pub use crate::config::Config;
     pub use crate::json::{FromJSON, ToJSON};
+    pub use crate::responder::StatusCode;
     pub use crate::Octane;
     pub use crate::{
         route, route_next, route_stop,
@@ -0,0 +1,163 @@
+use crate::responder::StatusCode;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+
+/// Counters collected by the server when the `metrics` setting is
+/// enabled. They are plain atomics as they are touched on every
+/// request, a consistent view can be taken with `snapshot()`
+#[derive(Debug, Default)]
+pub(crate) struct Metrics {
+    requests: AtomicU64,
+    // indexed by the first digit of the status code
+    responses: [AtomicU64; 6],
+    bytes_sent: AtomicU64,
+    active_connections: AtomicUsize,
+}
+
+// Decrements the active connections when the connection is done
+pub(crate) struct ConnectionGuard<'a>(&'a Metrics);
+
+impl Drop for ConnectionGuard<'_> {
+    fn drop(&mut self) {
+        self.0.active_connections.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl Metrics {
+    pub(crate) fn new() -> Self {
+        Default::default()
+    }
+    // Counts a connection as active until the guard is dropped
+    pub(crate) fn connection(&self) -> ConnectionGuard<'_> {
+        self.active_connections.fetch_add(1, Ordering::Relaxed);
+        ConnectionGuard(self)
+    }
+    pub(crate) fn request(&self) {
+        self.requests.fetch_add(1, Ordering::Relaxed);
+    }
+    pub(crate) fn response(&self, status: StatusCode, bytes: u64) {
+        let code: i32 = status.into();
+        if let Some(class) = self.responses.get(code as usize / 100) {
+            class.fetch_add(1, Ordering::Relaxed);
+        }
+        self.bytes_sent.fetch_add(bytes, Ordering::Relaxed);
+    }
+    pub(crate) fn snapshot(&self) -> MetricsSnapshot {
+        let class = |n: usize| self.responses[n].load(Ordering::Relaxed);
+        MetricsSnapshot {
+            requests: self.requests.load(Ordering::Relaxed),
+            responses_2xx: class(2),
+            responses_3xx: class(3),
+            responses_4xx: class(4),
+            responses_5xx: class(5),
+            bytes_sent: self.bytes_sent.load(Ordering::Relaxed),
+            active_connections: self.active_connections.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// A point in time copy of the server metrics, returned by
+/// [`Octane::metrics_snapshot()`](../struct.Octane.html#method.metrics_snapshot)
+///
+/// # Example
+///
+/// ```
+/// use octane::prelude::*;
+///
+/// let mut app = Octane::new();
+/// app.settings.metrics = true;
+/// let snapshot = app.metrics_snapshot();
+/// assert_eq!(snapshot.requests, 0);
+/// ```
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct MetricsSnapshot {
+    /// Total number of requests received
+    pub requests: u64,
+    /// Number of responses with a 2xx status
+    pub responses_2xx: u64,
+    /// Number of responses with a 3xx status
+    pub responses_3xx: u64,
+    /// Number of responses with a 4xx status
+    pub responses_4xx: u64,
+    /// Number of responses with a 5xx status
+    pub responses_5xx: u64,
+    /// Total number of bytes sent, including the headers
+    pub bytes_sent: u64,
+    /// Number of connections currently open
+    pub active_connections: usize,
+}
+
+impl MetricsSnapshot {
+    /// Renders the metrics in the Prometheus text exposition format
+    pub fn to_prometheus(&self) -> String {
+        let mut ret = String::new();
+        ret.push_str("# TYPE octane_requests_total counter\n");
+        writeln!(ret, "octane_requests_total {}", self.requests).ok();
+        ret.push_str("# TYPE octane_responses_total counter\n");
+        let classes = [
+            ("2xx", self.responses_2xx),
+            ("3xx", self.responses_3xx),
+            ("4xx", self.responses_4xx),
+            ("5xx", self.responses_5xx),
+        ];
+        for (class, count) in classes.iter() {
+            writeln!(
+                ret,
+                "octane_responses_total{{class=\"{}\"}} {}",
+                class, count
+            )
+            .ok();
+        }
+        ret.push_str("# TYPE octane_bytes_sent_total counter\n");
+        writeln!(ret, "octane_bytes_sent_total {}", self.bytes_sent).ok();
+        ret.push_str("# TYPE octane_active_connections gauge\n");
+        writeln!(ret, "octane_active_connections {}", self.active_connections).ok();
+        ret
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn success_counters() {
+        // Counters should be sorted by status class.
+        let metrics = Metrics::new();
+        metrics.request();
+        metrics.response(StatusCode::Ok, 10);
+        metrics.response(StatusCode::NotFound, 5);
+        metrics.response(StatusCode::NotFound, 5);
+        let snapshot = metrics.snapshot();
+        assert_eq!(snapshot.requests, 1);
+        assert_eq!(snapshot.responses_2xx, 1);
+        assert_eq!(snapshot.responses_4xx, 2);
+        assert_eq!(snapshot.bytes_sent, 20);
+    }
+
+    #[test]
+    fn success_connection_guard() {
+        // Active connections should go down when the guard is dropped.
+        let metrics = Metrics::new();
+        let guard = metrics.connection();
+        assert_eq!(metrics.snapshot().active_connections, 1);
+        drop(guard);
+        assert_eq!(metrics.snapshot().active_connections, 0);
+    }
+
+    #[test]
+    fn success_prometheus() {
+        // The exposition format should have one sample per line.
+        let snapshot = MetricsSnapshot {
+            requests: 3,
+            responses_2xx: 2,
+            responses_4xx: 1,
+            ..Default::default()
+        };
+        let text = snapshot.to_prometheus();
+        assert!(text.contains("octane_requests_total 3\n"));
+        assert!(text.contains("octane_responses_total{class=\"2xx\"} 2\n"));
+        assert!(text.contains("octane_responses_total{class=\"4xx\"} 1\n"));
+        assert!(text.contains("octane_active_connections 0\n"));
+    }
+}
@@ -1,8 +1,10 @@
 use crate::error::InvalidPathError;
 use crate::{default, deref};
+use std::borrow::Borrow;
 use std::collections::{hash_map, HashMap};
 use std::convert::TryFrom;
 use std::fmt;
+use std::hash::{Hash, Hasher};
 use std::iter::{FromIterator, Iterator, Map};
 use std::path::PathBuf as StdPathBuf;
 use std::str::FromStr;
@@ -41,6 +43,9 @@ impl PathBuf {
         StdPathBuf::from(path_string)
     }
 
+    /// Parses a path, normalizing it on the way: empty chunks from
+    /// duplicate slashes and `.` chunks are dropped and `..` removes the
+    /// chunk before it. A `..` which would go above the root is an error
     pub fn parse(path: &str) -> Result<Self, InvalidPathError> {
         let mut chunks = Vec::new();
         for chunk in path.split('/') {
@@ -153,15 +158,67 @@ pub struct MatchedPath<'a, T> {
     #[cfg(feature = "url_variables")]
     pub vars: HashMap<&'a str, &'a str>,
     pub data: &'a T,
+    // the path the data was inserted with, None if it wasn't
+    // matched by path
+    pub orig_path: Option<&'a PathBuf>,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum PathChunk {
     Chunk(String),
     CatchAll,
     End,
 }
 
+// Lets the tree be searched with the chunks of a request path
+// directly, without building a PathChunk (and cloning the chunk)
+// for every level of the lookup
+trait ChunkKey {
+    fn key(&self) -> (u8, &str);
+}
+
+impl ChunkKey for PathChunk {
+    fn key(&self) -> (u8, &str) {
+        match self {
+            PathChunk::Chunk(chunk) => (0, chunk),
+            PathChunk::CatchAll => (1, ""),
+            PathChunk::End => (2, ""),
+        }
+    }
+}
+
+impl ChunkKey for String {
+    fn key(&self) -> (u8, &str) {
+        (0, self)
+    }
+}
+
+impl Hash for PathChunk {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.key().hash(state)
+    }
+}
+
+impl Hash for dyn ChunkKey + '_ {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.key().hash(state)
+    }
+}
+
+impl PartialEq for dyn ChunkKey + '_ {
+    fn eq(&self, other: &Self) -> bool {
+        self.key() == other.key()
+    }
+}
+
+impl Eq for dyn ChunkKey + '_ {}
+
+impl<'a> Borrow<dyn ChunkKey + 'a> for PathChunk {
+    fn borrow(&self) -> &(dyn ChunkKey + 'a) {
+        self
+    }
+}
+
 #[derive(Debug, Clone)]
 pub enum PathNode<T> {
     Node(HashMap<PathChunk, PathNode<T>>),
@@ -231,43 +288,64 @@ impl<T> PathNode<T> {
             });
     }
 
-    fn dfs(&self, chunks: &[String]) -> Vec<&PathData<T>> {
+    // Collects the leaves matching the chunks into found, this runs
+    // on every request so nothing is allocated on the way down. When
+    // the case is ignored the children have to be compared one by one
+    fn dfs<'a>(&'a self, chunks: &[String], ignore_case: bool, found: &mut Vec<&'a PathData<T>>) {
         let cur = self.unwrap_node();
         if chunks.is_empty() {
-            return cur
-                .get(&PathChunk::End)
-                .map(|v| v.unwrap_leaf().iter().collect())
-                .unwrap_or_default();
+            if let Some(v) = cur.get(&PathChunk::End) {
+                found.extend(v.unwrap_leaf());
+            }
+            return;
         }
-        let mut ret = vec![];
-        if let Some(v) = cur.get(&PathChunk::Chunk(chunks[0].clone())) {
-            let found = v.dfs(&chunks[1..]);
-            ret = found;
+        if ignore_case {
+            for (chunk, v) in cur.iter() {
+                if let PathChunk::Chunk(chunk) = chunk {
+                    if chunk.eq_ignore_ascii_case(&chunks[0]) {
+                        v.dfs(&chunks[1..], ignore_case, found);
+                    }
+                }
+            }
+        } else if let Some(v) = cur.get(&chunks[0] as &dyn ChunkKey) {
+            v.dfs(&chunks[1..], ignore_case, found);
         }
         #[cfg(feature = "url_variables")]
         {
             if let Some(v) = cur.get(&PathChunk::CatchAll) {
-                let found = v.dfs(&chunks[1..]);
-                ret.extend(found);
+                v.dfs(&chunks[1..], ignore_case, found);
             }
         }
-        ret
     }
 
     pub fn get<'a>(&'a self, path: &'a PathBuf) -> Vec<MatchedPath<'a, T>> {
-        let matched = self.dfs(path.chunks.as_slice());
+        self.get_matching(path, false)
+    }
+
+    // Like get, but the static chunks of the paths may also match in
+    // another case. Url variables keep the case of the request
+    pub fn get_matching<'a>(
+        &'a self,
+        path: &'a PathBuf,
+        ignore_case: bool,
+    ) -> Vec<MatchedPath<'a, T>> {
+        let mut matched = Vec::new();
+        self.dfs(path.chunks.as_slice(), ignore_case, &mut matched);
         matched
             .into_iter()
             .map(|data| MatchedPath {
+                // the paths have the same length as they matched, so the
+                // variables are the chunks of the request at their places
                 #[cfg(feature = "url_variables")]
                 vars: data
                     .orig_path
-                    .check_matches(path)
-                    .unwrap()
-                    .into_iter()
-                    .map(|(k, v)| (k, v))
+                    .iter()
+                    .zip(path.iter())
+                    .filter(|(chunk, _)| chunk.starts_with(':'))
+                    .map(|(chunk, value)| (&chunk[1..], &value[..]))
                     .collect(),
                 data: &data.data,
+                orig_path: Some(&data.orig_path),
             })
             .collect()
     }
@@ -498,6 +576,19 @@ mod test {
         assert!(path1.subtract(&path4).is_none());
     }
 
+    #[test]
+    fn success_normalize() {
+        // Duplicate slashes, dots and dot dots should be resolved.
+        let expected = PathBuf::parse("/foo/bar").unwrap();
+        assert_eq!(PathBuf::parse("/foo//bar").unwrap(), expected);
+        assert_eq!(PathBuf::parse("//foo/bar//").unwrap(), expected);
+        assert_eq!(PathBuf::parse("/foo/./bar").unwrap(), expected);
+        assert_eq!(PathBuf::parse("/foo/baz/../bar").unwrap(), expected);
+        assert_eq!(*PathBuf::parse("/a/../b").unwrap().chunks(), vec!["b"]);
+        assert!(PathBuf::parse("/a/..").unwrap().chunks().is_empty());
+        assert!(PathBuf::parse("/../a").is_err());
+    }
+
     #[test]
     #[should_panic]
     fn fail_traversal() {
@@ -530,6 +621,63 @@ mod test {
         assert!(matched.vars.is_empty());
     }
 
+    #[test]
+    #[cfg(feature = "url_variables")]
+    fn success_tree_mixed() {
+        // Static and variable routes should both match the same path.
+        let mut node: PathNode<i32> = PathNode::new();
+        node.insert(PathBuf::parse("/users/:id").unwrap(), 1);
+        node.insert(PathBuf::parse("/users/me").unwrap(), 2);
+        node.insert(PathBuf::parse("/users/:id/posts/:post").unwrap(), 3);
+        let path = PathBuf::parse("/users/me").unwrap();
+        let mut matched = node.get(&path);
+        matched.sort_by_key(|m| *m.data);
+        assert_eq!(matched.len(), 2);
+        assert_eq!(matched[0].vars.get("id"), Some(&"me"));
+        assert!(matched[1].vars.is_empty());
+        let path = PathBuf::parse("/users/1/posts/2").unwrap();
+        let matched = node.get(&path);
+        assert_eq!(matched.len(), 1);
+        assert_eq!(matched[0].vars.get("id"), Some(&"1"));
+        assert_eq!(matched[0].vars.get("post"), Some(&"2"));
+        assert!(node.get(&PathBuf::parse("/users").unwrap()).is_empty());
+    }
+
+    // Rough timing of lookups in a large route table, run with
+    // `cargo test --release -- --ignored bench_ --nocapture`
+    #[test]
+    #[ignore]
+    fn bench_lookup() {
+        let mut node: PathNode<usize> = PathNode::new();
+        for i in 0..500 {
+            node.insert(
+                PathBuf::parse(&format!("/api/v1/resource{}/list", i)).unwrap(),
+                i,
+            );
+        }
+        node.insert(PathBuf::parse("/api/v1/:resource/:id").unwrap(), 500);
+        let paths: Vec<_> = [
+            "/api/v1/resource250/list",
+            "/api/v1/users/42",
+            "/missing/path",
+        ]
+        .iter()
+        .map(|path| PathBuf::parse(path).unwrap())
+        .collect();
+        let rounds = 100_000;
+        for path in &paths {
+            let start = std::time::Instant::now();
+            for _ in 0..rounds {
+                std::hint::black_box(node.get(std::hint::black_box(path)));
+            }
+            println!(
+                "{:>30}: {:?} per lookup",
+                path.to_string(),
+                start.elapsed() / rounds
+            );
+        }
+    }
+
     #[test]
     #[cfg(not(feature = "url_variables"))]
     fn success_tree() {
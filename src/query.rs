@@ -1,3 +1,5 @@
+#[cfg(feature = "extended_queries")]
+use std::collections::hash_map::Entry;
 use std::collections::HashMap;
 
 pub fn unescape_hex(string: &str) -> String {
@@ -154,7 +156,10 @@ pub fn parse_query(query: &str) -> HashMap<String, String> {
 ///     let value = parsed_extended_query.get("value");
 /// }));
 /// ```
-/// An example of an extended query could be `a[]=2&a[x]=3&a=1&b[]=1&b[x]=2&c[x]=1&c[]=2`
+/// An example of an extended query could be `a[]=2&a[x]=3&a=1&b[]=1&b[x]=2&c[x]=1&c[]=2`.
+/// Keys ending with `[]` and keys which are repeated, like
+/// `tag=a&tag=b`, are collected in an `Arr` while `key[name]`
+/// keys make up an `Obj`
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum QueryValue {
     /// A parsed String
@@ -201,62 +206,64 @@ pub fn parse_extended_query(query: &str) -> HashMap<String, QueryValue> {
         if cfg!(feature = "faithful") && tok.is_empty() {
             continue;
         }
-        match tok.find('=') {
+        let (name, value) = match tok.find('=') {
             Some(v) => {
                 let (name, val) = tok.split_at(v);
-                let unescaped_val = unescape_hex(&val[1..]);
                 if name.is_empty() {
                     continue;
                 }
-                let bytes = name.as_bytes();
-                let bytelen = bytes.len();
-                if bytes[bytelen - 1] == b']' {
-                    if let Some(i) = bytes.iter().rev().position(|&v| v == b'[') {
-                        let ind = bytelen - i - 1;
-                        let outside = match String::from_utf8((&bytes[0..ind]).to_vec()) {
-                            Ok(v) => v,
-                            Err(_) => continue,
-                        };
-                        let inside =
-                            match String::from_utf8((&bytes[ind + 1..bytelen - 1]).to_vec()) {
-                                Ok(v) => v,
-                                Err(_) => continue,
-                            };
-                        if inside.is_empty() {
-                            if ret.get(&outside).is_none() {
-                                ret.insert(outside.clone(), QueryValue::Arr(Vec::new()));
-                            }
-                            match ret.get_mut(&outside) {
-                                Some(QueryValue::Arr(v)) => {
-                                    v.push(unescaped_val);
-                                    continue;
-                                }
-                                _ => continue,
-                            }
-                        } else {
-                            if ret.get(&outside).is_none() {
-                                ret.insert(outside.clone(), QueryValue::Obj(HashMap::new()));
-                            }
-                            match ret.get_mut(&outside) {
-                                Some(QueryValue::Obj(v)) => {
-                                    v.insert(inside, unescaped_val);
-                                    continue;
-                                }
-                                _ => continue,
-                            }
-                        }
-                    }
+                (unescape_hex(name), unescape_hex(&val[1..]))
+            }
+            None => (unescape_hex(tok), "".to_owned()),
+        };
+        // brackets are often percent encoded by clients so they
+        // are looked for after unescaping the name
+        match split_brackets(&name) {
+            Some((outside, "")) => {
+                let entry = ret
+                    .entry(outside.to_owned())
+                    .or_insert_with(|| QueryValue::Arr(Vec::new()));
+                if let QueryValue::Arr(v) = entry {
+                    v.push(value);
                 }
-                ret.insert(unescape_hex(name), QueryValue::Str(unescaped_val));
             }
-            None => {
-                ret.insert(unescape_hex(tok), QueryValue::Str("".to_owned()));
+            Some((outside, inside)) => {
+                let entry = ret
+                    .entry(outside.to_owned())
+                    .or_insert_with(|| QueryValue::Obj(HashMap::new()));
+                if let QueryValue::Obj(v) = entry {
+                    v.insert(inside.to_owned(), value);
+                }
             }
+            None => match ret.entry(name) {
+                // repeated keys collect their values
+                Entry::Occupied(mut e) => match e.get_mut() {
+                    QueryValue::Str(first) => {
+                        let first = std::mem::take(first);
+                        e.insert(QueryValue::Arr(vec![first, value]));
+                    }
+                    QueryValue::Arr(v) => v.push(value),
+                    QueryValue::Obj(_) => {
+                        e.insert(QueryValue::Str(value));
+                    }
+                },
+                Entry::Vacant(e) => {
+                    e.insert(QueryValue::Str(value));
+                }
+            },
         }
     }
     ret
 }
 
+// Splits `name[key]` into the name and the key
+#[cfg(feature = "extended_queries")]
+fn split_brackets(name: &str) -> Option<(&str, &str)> {
+    let inner = name.strip_suffix(']')?;
+    let i = inner.rfind('[')?;
+    Some((&inner[..i], &inner[i + 1..]))
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -286,9 +293,13 @@ mod test {
     #[test]
     #[cfg(feature = "extended_queries")]
     fn success_ignore_incompatible() {
-        // If different types are specified, strings take precedence, then the one coming first takes precedence.
-        let query = parse_extended_query("a[]=2&a[x]=3&a=1&b[]=1&b[x]=2&c[x]=1&c[]=2");
-        assert_eq!(query["a"], QueryValue::Str("1".to_string()));
+        // If different types are specified, strings are added to arrays and replace objects, then the one coming first takes precedence.
+        let query = parse_extended_query("a[]=2&a[x]=3&a=1&b[]=1&b[x]=2&c[x]=1&c[]=2&d[x]=1&d=2");
+        assert_eq!(
+            query["a"],
+            QueryValue::Arr(vec!["2".to_string(), "1".to_string()])
+        );
+        assert_eq!(query["d"], QueryValue::Str("2".to_string()));
         assert_eq!(query["b"], QueryValue::Arr(vec!["1".to_string()]));
         let obj = match query["c"].clone() {
             QueryValue::Obj(v) => v,
@@ -297,6 +308,30 @@ mod test {
         assert_eq!(obj["x"], "1".to_string());
     }
 
+    #[test]
+    #[cfg(feature = "extended_queries")]
+    fn success_repeated_extended_queries() {
+        // Repeated keys should be collected in order.
+        let query = parse_extended_query("tag=a&tag=b&tag=c&one=x");
+        assert_eq!(
+            query["tag"],
+            QueryValue::Arr(["a", "b", "c"].iter().map(|v| v.to_string()).collect())
+        );
+        assert_eq!(query["one"], QueryValue::Str("x".to_string()));
+    }
+
+    #[test]
+    #[cfg(feature = "extended_queries")]
+    fn success_encoded_brackets() {
+        // Percent encoded brackets should work like plain ones.
+        let query = parse_extended_query("p%5Bpage%5D=2&ids%5B%5D=1&ids[]=2");
+        assert_eq!(query["p"].get_obj().unwrap()["page"], "2");
+        assert_eq!(
+            query["ids"],
+            QueryValue::Arr(vec!["1".to_string(), "2".to_string()])
+        );
+    }
+
     #[test]
     fn success_standard() {
         // Parsing should work as expected.
@@ -7,17 +7,33 @@ use crate::path::PathBuf;
 use crate::query::parse_query;
 #[cfg(feature = "extended_queries")]
 use crate::query::{parse_extended_query, QueryValue};
-use crate::util::Spliterator;
+use crate::responder::StatusCode;
+#[cfg(feature = "cookies")]
+use crate::session::Session;
+use crate::time::Time;
+use crate::util::{random_hex, Spliterator};
+use std::any::Any;
+use std::borrow::Cow;
 use std::cfg;
 use std::collections::HashMap;
+use std::env;
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io;
 #[cfg(not(feature = "raw_headers"))]
 use std::marker::PhantomData;
+#[cfg(unix)]
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf as StdPathBuf};
 use std::str;
 use std::string::ToString;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 /// Holds the type of request method, like GET,
-/// POST etc.
-#[derive(Debug, PartialEq, Eq, Clone, Hash, Copy)]
+/// POST etc. Methods which don't have a variant of
+/// their own, like the WebDAV ones, are held by `Other`
+#[derive(Debug, PartialEq, Eq, Clone, Hash)]
 pub enum RequestMethod {
     #[doc(hidden)]
     Options,
@@ -39,6 +55,8 @@ pub enum RequestMethod {
     Patch,
     #[doc(hidden)]
     All,
+    /// Any other method, like `PROPFIND`
+    Other(String),
     #[doc(hidden)]
     None,
 }
@@ -48,6 +66,62 @@ impl RequestMethod {
     pub fn is_some(&self) -> bool {
         !matches!(self, Self::None)
     }
+    /// Parses a method token, methods are case sensitive. Returns
+    /// `None` if the token isn't a valid method name
+    ///
+    /// ```
+    /// use octane::request::RequestMethod;
+    ///
+    /// assert_eq!(RequestMethod::parse("GET"), RequestMethod::Get);
+    /// assert_eq!(RequestMethod::parse("MKCOL"), RequestMethod::Other("MKCOL".to_owned()));
+    /// assert_eq!(RequestMethod::parse("BAD METHOD"), RequestMethod::None);
+    /// ```
+    pub fn parse(method: &str) -> Self {
+        match method {
+            "POST" => Self::Post,
+            "GET" => Self::Get,
+            "DELETE" => Self::Delete,
+            "PUT" => Self::Put,
+            "OPTIONS" => Self::Options,
+            "HEAD" => Self::Head,
+            "TRACE" => Self::Trace,
+            "CONNECT" => Self::Connect,
+            "PATCH" => Self::Patch,
+            _ if !method.is_empty() && method.chars().all(is_tchar) => {
+                Self::Other(method.to_owned())
+            }
+            _ => Self::None,
+        }
+    }
+    /// Returns the name of the method as it is sent, the name is
+    /// empty for `All` and `None`
+    ///
+    /// ```
+    /// use octane::request::RequestMethod;
+    ///
+    /// assert_eq!(RequestMethod::Get.as_str(), "GET");
+    /// assert_eq!(RequestMethod::parse("MKCOL").as_str(), "MKCOL");
+    /// ```
+    pub fn as_str(&self) -> &str {
+        match self {
+            Self::Get => "GET",
+            Self::Post => "POST",
+            Self::Put => "PUT",
+            Self::Delete => "DELETE",
+            Self::Head => "HEAD",
+            Self::Options => "OPTIONS",
+            Self::Connect => "CONNECT",
+            Self::Patch => "PATCH",
+            Self::Trace => "TRACE",
+            Self::Other(method) => method,
+            Self::All | Self::None => "",
+        }
+    }
+}
+
+// Returns true for the characters allowed in a method token
+fn is_tchar(c: char) -> bool {
+    c.is_ascii_alphanumeric() || "!#$%&'*+-.^_`|~".contains(c)
 }
 /// Holds the http versions. You can match the
 /// variants by doing a comparison with the version
@@ -118,24 +192,105 @@ impl ToString for HttpVersion {
 pub struct RequestLine {
     /// The HTTP method which the request was made in
     pub method: RequestMethod,
-    /// The path of the request
+    /// The path of the request, for absolute-form targets this is the
+    /// path part of the url and it is empty for authority-form and
+    /// asterisk-form targets
     pub path: PathBuf,
+    /// The form of the request target
+    pub target: RequestTarget,
+    /// The query string of the target without the leading `?`,
+    /// it is empty if the target has none
+    pub query: String,
     /// Http version of the request
     pub version: HttpVersion,
 }
 
+/// The form of the request target in the request line
+///
+/// # Example
+///
+/// ```
+/// use octane::prelude::*;
+/// use octane::request::RequestTarget;
+///
+/// let mut app = Octane::new();
+/// app.add(route_next!(|req, res| {
+///     if req.request_line.target == RequestTarget::Asterisk {
+///         // the request is about the server as a whole
+///         res.set("Allow", "GET, POST");
+///     }
+/// }));
+/// ```
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum RequestTarget {
+    /// A path like `/index.html`, used by most requests
+    Origin,
+    /// A full url like `http://example.com/index.html`, used when
+    /// talking to proxies. Holds the authority (host and port) of the url
+    Absolute(String),
+    /// Just the host and port like `example.com:443`, used by CONNECT
+    Authority(String),
+    /// The `*` target, used by OPTIONS to refer to the whole server
+    Asterisk,
+}
+
+impl RequestTarget {
+    // Detects the form of the target and returns it with the path
+    // part of it. Forms which don't fit the method are rejected when
+    // being faithful to the spec
+    fn parse<'a>(target: &'a str, method: &str) -> Option<(Self, &'a str)> {
+        let faithful = cfg!(feature = "faithful");
+        if target == "*" {
+            if faithful && method != "OPTIONS" {
+                return None;
+            }
+            return Some((Self::Asterisk, ""));
+        }
+        if method == "CONNECT" {
+            if !target.starts_with('/') && !target.contains("://") && !target.is_empty() {
+                return Some((Self::Authority(target.to_owned()), ""));
+            }
+            if faithful {
+                return None;
+            }
+        }
+        if target.starts_with('/') {
+            return Some((Self::Origin, target));
+        }
+        if let Some(i) = target.find("://") {
+            let scheme = &target[..i];
+            let rest = &target[i + 3..];
+            let end = rest.find(|c| c == '/' || c == '?').unwrap_or(rest.len());
+            let (authority, path) = rest.split_at(end);
+            let http = scheme.eq_ignore_ascii_case("http") || scheme.eq_ignore_ascii_case("https");
+            if faithful && (!http || authority.is_empty()) {
+                return None;
+            }
+            return Some((Self::Absolute(authority.to_owned()), path));
+        }
+        if faithful {
+            return None;
+        }
+        Some((Self::Origin, target))
+    }
+}
+
 impl RequestLine {
     /// Parses a request line str and returns a
     /// request line struct.
     pub(crate) fn parse(request_line: &str) -> Option<Self> {
         let mut toks = request_line.split(SP);
         let method = toks.next()?;
-        let path = match PathBuf::parse(toks.next()?) {
-            Ok(val) => val,
-            Err(e) => panic!("{:?}", e),
+        let (target, target_path) = RequestTarget::parse(toks.next()?, method)?;
+        // the query isn't part of the path routes are matched against
+        let (path, query) = match target_path.find('?') {
+            Some(i) => (&target_path[..i], &target_path[i + 1..]),
+            None => (target_path, ""),
         };
+        let path = PathBuf::parse(path).ok()?;
         let version = toks.next()?;
-        let (first, ver) = version.split_at(5);
+        let first = version.get(..5)?;
+        let ver = &version[5..];
         let enum_ver = match ver {
             "1.1" => HttpVersion::Http11,
             "1.0" => HttpVersion::Http10,
@@ -147,21 +302,12 @@ impl RequestLine {
         if cfg!(feature = "faithful") && (first != "HTTP/" || toks.next().is_some()) {
             return None;
         }
-        let request_method = match method {
-            "POST" => RequestMethod::Post,
-            "GET" => RequestMethod::Get,
-            "DELETE" => RequestMethod::Delete,
-            "PUT" => RequestMethod::Put,
-            "OPTIONS" => RequestMethod::Options,
-            "HEAD" => RequestMethod::Head,
-            "TRACE" => RequestMethod::Trace,
-            "CONNECT" => RequestMethod::Connect,
-            "PATCH" => RequestMethod::Patch,
-            _ => RequestMethod::None,
-        };
+        let request_method = RequestMethod::parse(method);
         Some(Self {
             method: request_method,
             path,
+            target,
+            query: query.to_owned(),
             version: enum_ver,
         })
     }
@@ -233,7 +379,7 @@ impl Header {
 ///     }),
 /// );
 /// ```
-#[derive(Debug, PartialEq, Eq, Clone)]
+#[derive(Debug, Default, PartialEq, Eq, Clone)]
 pub struct Headers {
     /// Headers, serialized to a HashMap
     pub parsed: HashMap<String, String>,
@@ -256,9 +402,12 @@ impl Headers {
                 Ok(s) => s.to_owned(),
                 Err(_) => return None,
             })?;
+            let name = parsed.name.to_ascii_lowercase();
+            // cookie pairs are separated by semicolons rather than commas
+            let separator = if name == "cookie" { "; " } else { ", " };
             headers
-                .entry(parsed.name.to_ascii_lowercase())
-                .and_modify(|v| *v = format!("{}, {}", v, parsed.value))
+                .entry(name)
+                .and_modify(|v| *v = format!("{}{}{}", v, separator, parsed.value))
                 .or_insert_with(|| parsed.value.to_owned());
             #[cfg(feature = "raw_headers")]
             raw_headers.push(parsed);
@@ -273,13 +422,15 @@ impl Headers {
     }
 }
 
-// Helper function for extracting some headers
-pub(crate) fn parse_without_body(data: &str) -> Option<(RequestLine, Headers)> {
-    let n = data.find("\r\n")?;
+// Helper function for extracting some headers, the error
+// is a short reason for the client
+pub(crate) fn parse_without_body(data: &str) -> Result<(RequestLine, Headers), &'static str> {
+    let n = data.find("\r\n").ok_or("The request has no headers")?;
     let (line, rest) = data.split_at(n);
-    let request_line = RequestLine::parse(line)?;
-    let headers = Headers::parse((&rest[2..]).to_owned())?;
-    Some((request_line, headers))
+    let request_line = RequestLine::parse(line).ok_or("The request line is malformed")?;
+    let headers =
+        Headers::parse((&rest[2..]).to_owned()).ok_or("The request headers are malformed")?;
+    Ok((request_line, headers))
 }
 
 /// Represents a single request
@@ -307,6 +458,12 @@ pub(crate) fn parse_without_body(data: &str) -> Option<(RequestLine, Headers)> {
 /// The request struct holds cookies (if enabled
 /// in features) headers, the request body, the
 /// request_line
+///
+/// The body is always read in full before the closures run, as they
+/// are synchronous and can't wait on the connection. Bodies larger
+/// than `spool_threshold` are written to a file instead of memory,
+/// which closures can read incrementally through
+/// [`SpooledBody::reader()`](struct.SpooledBody.html#method.reader)
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub struct Request<'a> {
     /// The request_line is the first line of the http request, it has the version,
@@ -314,11 +471,23 @@ pub struct Request<'a> {
     pub request_line: RequestLine,
     /// Headers in the request
     pub headers: Headers,
-    /// The body of the request
+    /// The body of the request, empty if the body was spooled to a file
     pub body: &'a [u8],
+    /// The body of the request if it was larger than the configured
+    /// `spool_threshold` and was written to a temporary file
+    pub spooled_body: Option<SpooledBody>,
+    /// The instant at which the server started receiving the request
+    pub started: Instant,
+    pub(crate) state: AppState,
+    pub(crate) secure: bool,
+    pub(crate) alpn: Option<String>,
+    pub(crate) trailers: Headers,
+    pub(crate) raw_head: Option<Vec<u8>>,
     #[cfg(feature = "cookies")]
     /// Cookies in the request
     pub cookies: Cookies,
+    #[cfg(feature = "cookies")]
+    pub(crate) session: Option<Session>,
 }
 
 impl<'a> Request<'a> {
@@ -343,9 +512,287 @@ impl<'a> Request<'a> {
             #[cfg(feature = "cookies")]
             cookies,
             body,
+            spooled_body: None,
+            started: Instant::now(),
+            state: AppState::default(),
+            secure: false,
+            alpn: None,
+            trailers: Headers::default(),
+            raw_head: None,
+            #[cfg(feature = "cookies")]
+            session: None,
         })
     }
 
+    // Moves the request over to another body, which lets it outlive the
+    // buffer its body was read into
+    pub(crate) fn with_body(self, body: &[u8]) -> Request<'_> {
+        Request {
+            request_line: self.request_line,
+            headers: self.headers,
+            #[cfg(feature = "cookies")]
+            cookies: self.cookies,
+            body,
+            spooled_body: self.spooled_body,
+            started: self.started,
+            state: self.state,
+            secure: self.secure,
+            alpn: self.alpn,
+            trailers: self.trailers,
+            raw_head: self.raw_head,
+            #[cfg(feature = "cookies")]
+            session: self.session,
+        }
+    }
+
+    /// Returns the value of a cookie sent by the client, names are
+    /// case sensitive. This requires the `cookies` feature
+    ///
+    /// ```
+    /// use octane::prelude::*;
+    ///
+    /// let mut app = Octane::new();
+    ///
+    /// app.get("/", route_stop!(|req, res| {
+    ///     match req.cookie("session") {
+    ///         Some(session) => res.send(format!("session {}", session)),
+    ///         None => res.send("no session"),
+    ///     }
+    /// }));
+    /// ```
+    #[cfg(feature = "cookies")]
+    pub fn cookie(&self, name: &str) -> Option<&str> {
+        self.cookies.get(name).map(String::as_str)
+    }
+
+    /// Returns all the cookies sent by the client. This requires
+    /// the `cookies` feature
+    #[cfg(feature = "cookies")]
+    pub fn cookies(&self) -> &Cookies {
+        &self.cookies
+    }
+
+    /// Returns the session of the request, `None` unless sessions
+    /// are enabled with
+    /// [`Octane::with_sessions()`](../struct.Octane.html#method.with_sessions).
+    /// This requires the `cookies` feature
+    ///
+    /// ```
+    /// use octane::prelude::*;
+    ///
+    /// let mut app = Octane::new();
+    ///
+    /// app.get("/visits", route_stop!(|req, res| {
+    ///     let session = req.session().unwrap();
+    ///     let visits = session.get("visits").map_or(0, |v| v.parse().unwrap_or(0)) + 1;
+    ///     session.set("visits", &visits.to_string());
+    ///     res.send(format!("{} visits", visits));
+    /// }));
+    /// ```
+    #[cfg(feature = "cookies")]
+    pub fn session(&self) -> Option<&Session> {
+        self.session.as_ref()
+    }
+
+    /// Returns the application state set with
+    /// [`Octane::with_state()`](../struct.Octane.html#method.with_state),
+    /// `None` if there is no state or it isn't a `T`
+    ///
+    /// ```
+    /// use octane::prelude::*;
+    /// use std::sync::atomic::{AtomicUsize, Ordering};
+    ///
+    /// let mut app = Octane::with_state(AtomicUsize::new(0));
+    ///
+    /// app.get("/", route_stop!(|req, res| {
+    ///     let hits = req.state::<AtomicUsize>().unwrap();
+    ///     let count = hits.fetch_add(1, Ordering::SeqCst) + 1;
+    ///     res.send(count.to_string());
+    /// }));
+    /// ```
+    pub fn state<T: Any>(&self) -> Option<&T> {
+        self.state.0.as_ref()?.downcast_ref()
+    }
+
+    /// Returns the time elapsed since the server started receiving
+    /// the request, useful for logging and metrics middlewares
+    ///
+    /// ```
+    /// use octane::prelude::*;
+    ///
+    /// let mut app = Octane::new();
+    ///
+    /// app.add(route_next!(|req, res| {
+    ///     println!("{:?} took {:?}", req.request_line.path, req.elapsed());
+    /// }));
+    /// ```
+    pub fn elapsed(&self) -> Duration {
+        self.started.elapsed()
+    }
+
+    /// Returns true if the request arrived over ssl. When the
+    /// `trust_proxy` setting is enabled, a `X-Forwarded-Proto: https`
+    /// header set by the proxy counts as well
+    ///
+    /// ```
+    /// use octane::prelude::*;
+    ///
+    /// let mut app = Octane::new();
+    ///
+    /// app.get("/login", route_stop!(|req, res| {
+    ///     if req.is_secure() {
+    ///         res.send("Welcome");
+    ///     } else {
+    ///         res.redirect("https://example.com/login");
+    ///     }
+    /// }));
+    /// ```
+    pub fn is_secure(&self) -> bool {
+        self.secure
+    }
+
+    /// Returns the protocol negotiated with ALPN during the ssl
+    /// handshake, like `http/1.1`. It is `None` for plain connections
+    /// and when the client and the
+    /// [`alpn`](../config/struct.Ssl.html#structfield.alpn) setting
+    /// have no protocol in common
+    ///
+    /// ```
+    /// use octane::prelude::*;
+    ///
+    /// let mut app = Octane::new();
+    ///
+    /// app.get("/", route_stop!(|req, res| {
+    ///     res.send(req.alpn_protocol().unwrap_or("none"));
+    /// }));
+    /// ```
+    pub fn alpn_protocol(&self) -> Option<&str> {
+        self.alpn.as_deref()
+    }
+
+    /// Returns the trailer fields sent after the last chunk of a
+    /// chunked body, they are empty for other requests. If the
+    /// request announced its trailers with a `Trailer` header,
+    /// only the announced ones are kept
+    ///
+    /// ```
+    /// use octane::prelude::*;
+    ///
+    /// let mut app = Octane::new();
+    ///
+    /// app.post("/upload", route_stop!(|req, res| {
+    ///     match req.trailers().get("x-checksum") {
+    ///         Some(checksum) => res.send(checksum.clone()),
+    ///         None => res.send("no checksum"),
+    ///     }
+    /// }));
+    /// ```
+    pub fn trailers(&self) -> &Headers {
+        &self.trailers
+    }
+
+    /// Returns the head of the request, the request line and the headers
+    /// up to the blank line, exactly as the client sent it. It is only
+    /// kept when the `capture_raw_head` setting is on, as it copies the
+    /// head of every request, and `None` otherwise. The body as received
+    /// is in [`body`](#structfield.body) unless it was chunked or spooled
+    ///
+    /// ```
+    /// use octane::prelude::*;
+    ///
+    /// let mut app = Octane::new();
+    /// app.settings.capture_raw_head = true;
+    /// app.add(route_next!(|req, res| {
+    ///     if let Some(head) = req.raw_head() {
+    ///         println!("{}", String::from_utf8_lossy(head));
+    ///     }
+    /// }));
+    /// ```
+    pub fn raw_head(&self) -> Option<&[u8]> {
+        self.raw_head.as_deref()
+    }
+
+    /// Returns the decoded body as bytes, without requiring it to be
+    /// text. The chunked coding is always undone, and compressed codings
+    /// are too when the `compression` feature is enabled. The body is
+    /// empty if it was spooled to a file, see
+    /// [`spooled_body`](#structfield.spooled_body)
+    ///
+    /// ```
+    /// use octane::prelude::*;
+    ///
+    /// let mut app = Octane::new();
+    ///
+    /// app.post("/upload", route_stop!(|req, res| {
+    ///     res.send(format!("received {} bytes", req.body_bytes().len()));
+    /// }));
+    /// ```
+    pub fn body_bytes(&self) -> &'a [u8] {
+        self.body
+    }
+
+    /// Returns the body as a string, or None if it isn't valid UTF-8.
+    /// The body is empty if it was spooled to a file
+    ///
+    /// ```
+    /// use octane::prelude::*;
+    ///
+    /// let mut app = Octane::new();
+    ///
+    /// app.post("/echo", route_stop!(|req, res| {
+    ///     match req.body_str() {
+    ///         Some(text) => res.send(text.to_owned()),
+    ///         None => res.status(StatusCode::BadRequest).send("expected text"),
+    ///     }
+    /// }));
+    /// ```
+    pub fn body_str(&self) -> Option<&str> {
+        str::from_utf8(self.body_bytes()).ok()
+    }
+
+    /// Returns the body as a string, replacing invalid UTF-8
+    /// sequences with `U+FFFD`, see [`body_str()`](#method.body_str)
+    pub fn body_str_lossy(&self) -> Cow<'a, str> {
+        String::from_utf8_lossy(self.body_bytes())
+    }
+
+    /// Returns the path of the request as a string, like `/a/b`. The
+    /// path is normalized so it has no trailing slash and no `.` or
+    /// `..` segments, and it isn't percent-decoded
+    ///
+    /// ```
+    /// use octane::prelude::*;
+    ///
+    /// let mut app = Octane::new();
+    ///
+    /// app.add(route_next!(|req, res| {
+    ///     println!("{} {}", req.request_line.method.as_str(), req.path());
+    /// }));
+    /// ```
+    pub fn path(&self) -> String {
+        format!("/{}", self.request_line.path.chunks.join("/"))
+    }
+
+    /// Returns the query string without the leading `?`, or `None`
+    /// if the target has none
+    pub fn query_string(&self) -> Option<&str> {
+        let query = &self.request_line.query;
+        if query.is_empty() {
+            None
+        } else {
+            Some(query)
+        }
+    }
+
+    /// Returns the path along with the query string, like `/a/b?x=1`,
+    /// see [`path()`](#method.path)
+    pub fn full_path(&self) -> String {
+        match self.query_string() {
+            Some(query) => format!("{}?{}", self.path(), query),
+            None => self.path(),
+        }
+    }
+
     /// Parse the query and return the key value pairs in the form
     /// of an HashMap
     ///
@@ -360,17 +807,394 @@ impl<'a> Request<'a> {
     /// }));
     /// ```
     pub fn get_query(&self) -> HashMap<String, String> {
-        parse_query(&self.request_line.path.to_string())
+        parse_query(&self.request_line.query)
     }
 
     /// Parse the extended query and return the key value pairs in the form
     /// of an HashMap. See [`QueryValue`](../enum.QueryValue.html)
     #[cfg(feature = "extended_queries")]
     pub fn get_extended_query(&self) -> HashMap<String, QueryValue> {
-        parse_extended_query(&self.request_line.path.to_string())
+        parse_extended_query(&self.request_line.query)
+    }
+
+    /// Returns the length announced by the `Content-Length` header,
+    /// `None` if there is no such header or it isn't a number
+    pub fn content_length(&self) -> Option<u64> {
+        self.headers.get("content-length")?.trim().parse().ok()
+    }
+
+    /// Returns the parsed `Content-Type` header, `None` if there is
+    /// no such header or it isn't a media type
+    ///
+    /// ```
+    /// use octane::prelude::*;
+    ///
+    /// let mut app = Octane::new();
+    ///
+    /// app.post("/", route_stop!(|req, res| {
+    ///     match req.content_type() {
+    ///         Some(kind) if kind.mime == "application/json" => res.send("json"),
+    ///         _ => res.status(StatusCode::UnsupportedMediaType).send(""),
+    ///     }
+    /// }));
+    /// ```
+    pub fn content_type(&self) -> Option<ContentType> {
+        ContentType::parse(self.headers.get("content-type")?)
+    }
+
+    /// Returns true if the `Accept` header of the request allows
+    /// the content type, wildcards like `*/*` and `text/*` are
+    /// supported. A request without an `Accept` header accepts
+    /// everything
+    ///
+    /// ```
+    /// use octane::prelude::*;
+    ///
+    /// let mut app = Octane::new();
+    ///
+    /// app.get("/", route_stop!(|req, res| {
+    ///     if req.accepts("application/json") {
+    ///         res.with_type("application/json").send("{}");
+    ///     } else {
+    ///         res.send("<p>Hello</p>");
+    ///     }
+    /// }));
+    /// ```
+    pub fn accepts(&self, content_type: &str) -> bool {
+        self.accept_quality(content_type) > 0.0
+    }
+
+    /// Returns the content type out of the offered ones which the
+    /// client prefers, according to the q-values of the `Accept`
+    /// header. Ties go to the type offered first, None is returned
+    /// if none of them are acceptable
+    ///
+    /// ```
+    /// use octane::prelude::*;
+    /// use octane::responder::StatusCode;
+    ///
+    /// let mut app = Octane::new();
+    ///
+    /// app.get("/", route_stop!(|req, res| {
+    ///     match req.preferred(&["text/html", "application/json"]) {
+    ///         Some("application/json") => res.with_type("application/json").send("{}"),
+    ///         Some(_) => res.send("<p>Hello</p>"),
+    ///         None => res.status(StatusCode::NotAcceptable).send(""),
+    ///     }
+    /// }));
+    /// ```
+    pub fn preferred<'b>(&self, offers: &[&'b str]) -> Option<&'b str> {
+        let mut ret = None;
+        let mut best = 0.0;
+        for offer in offers {
+            let quality = self.accept_quality(offer);
+            if quality > best {
+                best = quality;
+                ret = Some(*offer);
+            }
+        }
+        ret
+    }
+
+    /// Evaluates the `If-Match` and `If-Unmodified-Since` headers
+    /// against the current `ETag` and modification time of the
+    /// resource, for writes which shouldn't overwrite changes the
+    /// client hasn't seen. Returns `Err(StatusCode::PreconditionFailed)`
+    /// when a condition fails. `If-Unmodified-Since` is ignored when
+    /// the request has an `If-Match` header, and so are invalid dates
+    ///
+    /// ```
+    /// use octane::prelude::*;
+    /// use octane::responder::StatusCode;
+    ///
+    /// let mut app = Octane::new();
+    ///
+    /// app.put("/doc", route_stop!(|req, res| {
+    ///     match req.precondition_check(Some("\"v2\""), None) {
+    ///         Ok(()) => res.send("saved"),
+    ///         Err(status) => res.status(status).send("the document has changed"),
+    ///     }
+    /// }));
+    /// ```
+    pub fn precondition_check(
+        &self,
+        etag: Option<&str>,
+        modified: Option<SystemTime>,
+    ) -> Result<(), StatusCode> {
+        let passed = if let Some(tags) = self.headers.get("if-match") {
+            let tags = tags.trim();
+            if tags == "*" {
+                etag.is_some() || modified.is_some()
+            } else {
+                etag.map_or(false, |etag| etag_matches(tags, etag, false))
+            }
+        } else if let Some(since) = self.headers.get("if-unmodified-since") {
+            match (Time::parse(since), modified) {
+                (Some(since), Some(modified)) => {
+                    // dates only have a precision of seconds
+                    let secs =
+                        |t: SystemTime| t.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+                    secs(modified) <= secs(since)
+                }
+                _ => true,
+            }
+        } else {
+            true
+        };
+        if passed {
+            Ok(())
+        } else {
+            Err(StatusCode::PreconditionFailed)
+        }
+    }
+
+    /// Evaluates the `If-None-Match` and `If-Modified-Since` headers
+    /// of a GET or HEAD request against the current version of the
+    /// resource, returning true when the client's cached copy is still
+    /// fresh and can be answered with
+    /// [`res.not_modified()`](../responder/struct.Response.html#method.not_modified).
+    /// Entity tags are compared weakly, so `W/"x"` matches `"x"`, and
+    /// `If-Modified-Since` is ignored when the request has an
+    /// `If-None-Match` header
+    ///
+    /// ```
+    /// use octane::prelude::*;
+    ///
+    /// let mut app = Octane::new();
+    ///
+    /// app.get("/doc", route_stop!(|req, res| {
+    ///     res.set("ETag", "\"v2\"");
+    ///     if req.fresh(Some("\"v2\""), None) {
+    ///         res.not_modified();
+    ///     } else {
+    ///         res.send("the document");
+    ///     }
+    /// }));
+    /// ```
+    pub fn fresh(&self, etag: Option<&str>, modified: Option<SystemTime>) -> bool {
+        let method = &self.request_line.method;
+        if *method != RequestMethod::Get && *method != RequestMethod::Head {
+            return false;
+        }
+        if let Some(tags) = self.headers.get("if-none-match") {
+            let tags = tags.trim();
+            if tags == "*" {
+                etag.is_some()
+            } else {
+                etag.map_or(false, |etag| etag_matches(tags, etag, true))
+            }
+        } else if let Some(since) = self.headers.get("if-modified-since") {
+            match (Time::parse(since), modified) {
+                (Some(since), Some(modified)) => {
+                    let secs =
+                        |t: SystemTime| t.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+                    secs(modified) <= secs(since)
+                }
+                _ => false,
+            }
+        } else {
+            false
+        }
+    }
+
+    // Returns the q-value of the most specific range in the Accept
+    // header which matches the content type, 0 if none of them do
+    fn accept_quality(&self, content_type: &str) -> f32 {
+        let accept = match self.headers.get("accept") {
+            Some(v) => v,
+            None => return 1.0,
+        };
+        let content_type = content_type.split(';').next().unwrap_or("").trim();
+        let (kind, sub) = match content_type.find('/') {
+            Some(i) => (&content_type[..i], &content_type[i + 1..]),
+            None => return 0.0,
+        };
+        let mut quality = 0.0;
+        let mut specificity = None;
+        for range in accept.split(',') {
+            let mut params = range.split(';');
+            let media = params.next().unwrap_or("").trim();
+            let matched = match media.find('/') {
+                Some(_) if media == "*/*" => 0,
+                Some(i) if &media[i..] == "/*" && media[..i].eq_ignore_ascii_case(kind) => 1,
+                Some(_) if media.eq_ignore_ascii_case(content_type) && !sub.is_empty() => 2,
+                _ => continue,
+            };
+            if specificity.map_or(false, |s| s >= matched) {
+                continue;
+            }
+            specificity = Some(matched);
+            quality = params
+                .filter_map(|param| {
+                    let param = param.trim();
+                    param
+                        .strip_prefix("q=")
+                        .or_else(|| param.strip_prefix("Q="))
+                })
+                .next()
+                .and_then(|q| q.trim().parse::<f32>().ok())
+                .map_or(1.0, |q| q.max(0.0).min(1.0));
+        }
+        quality
+    }
+}
+
+/// A parsed `Content-Type` header, see
+/// [`Request::content_type()`](struct.Request.html#method.content_type)
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct ContentType {
+    /// The media type like `text/html`, in lowercase
+    pub mime: String,
+    /// The parameters like `charset=utf-8` in the order they were
+    /// sent. Names are lowercase and values are unquoted
+    pub params: Vec<(String, String)>,
+}
+
+impl ContentType {
+    // Parses a media type followed by `;` separated parameters,
+    // malformed parameters are skipped
+    pub(crate) fn parse(header: &str) -> Option<Self> {
+        let mut parts = header.split(';');
+        let mime = parts.next()?.trim().to_ascii_lowercase();
+        match mime.find('/') {
+            Some(i) if i > 0 && i < mime.len() - 1 => (),
+            _ => return None,
+        }
+        let params = parts
+            .filter_map(|param| {
+                let (name, value) = param.split_at(param.find('=')?);
+                let value = value[1..].trim();
+                let value = value
+                    .strip_prefix('"')
+                    .and_then(|v| v.strip_suffix('"'))
+                    .unwrap_or(value);
+                Some((name.trim().to_ascii_lowercase(), value.to_owned()))
+            })
+            .collect();
+        Some(ContentType { mime, params })
+    }
+    /// Returns the value of a parameter, names are case insensitive
+    pub fn param(&self, name: &str) -> Option<&str> {
+        self.params
+            .iter()
+            .find(|(param, _)| param.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+    /// Returns the `charset` parameter
+    pub fn charset(&self) -> Option<&str> {
+        self.param("charset")
+    }
+}
+
+// A temporary file which is removed when dropped
+#[derive(Debug, PartialEq, Eq)]
+struct TempPath(StdPathBuf);
+
+impl Drop for TempPath {
+    fn drop(&mut self) {
+        fs::remove_file(&self.0).ok();
+    }
+}
+
+/// A request body which was larger than the configured `spool_threshold`
+/// and was streamed to a temporary file instead of being kept in memory.
+/// The file is removed once the request is dropped
+///
+/// # Example
+///
+/// ```
+/// use octane::prelude::*;
+/// use std::io::Read;
+///
+/// let mut app = Octane::new();
+/// app.settings.spool_threshold = Some(1024 * 1024);
+/// app.post("/upload",
+///     route_stop!(|req, res| {
+///         if let Some(spooled) = &req.spooled_body {
+///             let mut file = spooled.reader().expect("cannot open spooled body");
+///             let mut contents = Vec::new();
+///             file.read_to_end(&mut contents).ok();
+///         }
+///     }),
+/// );
+/// ```
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct SpooledBody {
+    path: Arc<TempPath>,
+    len: usize,
+}
+
+impl SpooledBody {
+    // Returns a spooled body for a file which is already written
+    pub(crate) fn new(path: StdPathBuf, len: usize) -> Self {
+        Self {
+            path: Arc::new(TempPath(path)),
+            len,
+        }
+    }
+    // Returns the spooled body with its final length, for bodies whose
+    // length wasn't known when the file was created
+    pub(crate) fn with_len(self, len: usize) -> Self {
+        Self { len, ..self }
+    }
+    // Creates a temporary file to spool a body to. The name is random
+    // and the file has to be new, so that a file or a symlink planted
+    // in the temp dir is never written to, and only the owner may read it
+    pub(crate) fn create() -> io::Result<(File, Self)> {
+        loop {
+            let path = env::temp_dir().join(format!("octane-body-{}", random_hex(16)?));
+            let mut options = OpenOptions::new();
+            options.write(true).create_new(true);
+            #[cfg(unix)]
+            options.mode(0o600);
+            match options.open(&path) {
+                Ok(file) => return Ok((file, Self::new(path, 0))),
+                Err(err) if err.kind() == io::ErrorKind::AlreadyExists => continue,
+                Err(err) => return Err(err),
+            }
+        }
+    }
+    /// Returns the location of the temporary file holding the body
+    pub fn path(&self) -> &Path {
+        &self.path.0
+    }
+    /// Returns the length of the body in bytes
+    pub fn len(&self) -> usize {
+        self.len
+    }
+    /// Opens the temporary file to read the body
+    pub fn reader(&self) -> io::Result<File> {
+        File::open(self.path())
     }
 }
 
+// The state shared by every request, it is compared by pointer
+// as the value itself is opaque
+#[derive(Clone, Default)]
+pub(crate) struct AppState(pub(crate) Option<Arc<dyn Any + Send + Sync>>);
+
+impl fmt::Debug for AppState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(if self.0.is_some() {
+            "AppState(Some(..))"
+        } else {
+            "AppState(None)"
+        })
+    }
+}
+
+impl PartialEq for AppState {
+    fn eq(&self, other: &Self) -> bool {
+        match (&self.0, &other.0) {
+            (Some(a), Some(b)) => Arc::ptr_eq(a, b),
+            (None, None) => true,
+            _ => false,
+        }
+    }
+}
+
+impl Eq for AppState {}
+
 /// The KeepAlive struct represents the value
 /// parsed in the KeepAlive header. It holds the
 /// timeout and max duration as a u64, (only http 1.0 and below)
@@ -445,8 +1269,9 @@ impl KeepAlive {
 /// requires the feature `url_variables` to be enabled.
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub struct MatchedRequest<'a> {
-    /// The request coming from the client
-    pub request: Request<'a>,
+    /// The request coming from the client, it is borrowed by every
+    /// closure which runs on the request
+    pub request: &'a Request<'a>,
     #[cfg(feature = "url_variables")]
     /// A Hashmap containing the variables specified in the url with their
     /// respective keys.
@@ -470,6 +1295,27 @@ pub struct MatchedRequest<'a> {
 deref!(MatchedRequest<'a>, Request<'a>, request);
 deref!(Headers, HashMap<String, String>, parsed);
 
+// Checks whether a comma separated list of entity tags matches the
+// tag of the resource. The weak comparison ignores the W/ prefix of
+// both sides, while the strong one needs both tags to be strong and
+// equal, as required for writes and ranges
+pub(crate) fn etag_matches(tags: &str, etag: &str, weak: bool) -> bool {
+    let opaque = |tag: &str| {
+        let tag = tag.trim();
+        match tag.strip_prefix("W/") {
+            Some(tag) if weak => Some(tag.to_owned()),
+            Some(_) => None,
+            None => Some(tag.to_owned()),
+        }
+    };
+    let etag = match opaque(etag) {
+        Some(etag) => etag,
+        None => return false,
+    };
+    tags.split(',')
+        .any(|tag| opaque(tag).map_or(false, |tag| tag == etag))
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -495,7 +1341,7 @@ mod test {
     fn sucess_non_documented() {
         // Non-documented methods should also work.
         let req = RequestLine::parse("XYZ /abc/def HTTP/1.1").unwrap();
-        assert_eq!(req.method, RequestMethod::None);
+        assert_eq!(req.method, RequestMethod::Other("XYZ".to_owned()));
         assert_eq!(req.path, PathBuf::parse("/abc/def").ok().unwrap());
         assert_eq!(req.version, HttpVersion::Http11);
     }
@@ -523,6 +1369,67 @@ mod test {
         RequestLine::parse("POST /abc/def HTDP/1.1").unwrap();
     }
 
+    #[test]
+    fn success_origin_form() {
+        // Paths should be origin-form targets.
+        let req = RequestLine::parse("GET /a/b?c=d HTTP/1.1").unwrap();
+        assert_eq!(req.target, RequestTarget::Origin);
+        assert_eq!(req.path, PathBuf::parse("/a/b").unwrap());
+        assert_eq!(req.query, "c=d");
+    }
+
+    #[test]
+    fn success_absolute_form() {
+        // Full urls should be normalized to their path.
+        let req = RequestLine::parse("GET http://example.com:8080/a/b?c=d HTTP/1.1").unwrap();
+        assert_eq!(
+            req.target,
+            RequestTarget::Absolute("example.com:8080".to_owned())
+        );
+        assert_eq!(req.path, PathBuf::parse("/a/b").unwrap());
+        assert_eq!(req.query, "c=d");
+        let req = RequestLine::parse("GET https://example.com HTTP/1.1").unwrap();
+        assert_eq!(req.path, PathBuf::new());
+    }
+
+    #[test]
+    fn success_authority_form() {
+        // CONNECT should take a host and port.
+        let req = RequestLine::parse("CONNECT example.com:443 HTTP/1.1").unwrap();
+        assert_eq!(req.method, RequestMethod::Connect);
+        assert_eq!(
+            req.target,
+            RequestTarget::Authority("example.com:443".to_owned())
+        );
+    }
+
+    #[test]
+    fn success_asterisk_form() {
+        // OPTIONS * should refer to the whole server.
+        let req = RequestLine::parse("OPTIONS * HTTP/1.1").unwrap();
+        assert_eq!(req.method, RequestMethod::Options);
+        assert_eq!(req.target, RequestTarget::Asterisk);
+        assert_eq!(req.path, PathBuf::new());
+    }
+
+    #[test]
+    #[cfg_attr(not(feature = "faithful"), ignore)]
+    fn fail_invalid_targets() {
+        // Targets which don't fit the method should be rejected.
+        assert_eq!(RequestLine::parse("GET * HTTP/1.1"), None);
+        assert_eq!(RequestLine::parse("GET example.com HTTP/1.1"), None);
+        assert_eq!(RequestLine::parse("CONNECT /a HTTP/1.1"), None);
+        assert_eq!(RequestLine::parse("GET ftp://example.com/ HTTP/1.1"), None);
+        assert_eq!(RequestLine::parse("GET http:///a HTTP/1.1"), None);
+    }
+
+    #[test]
+    fn fail_invalid_path() {
+        // Escaping the root or short versions shouldn't panic.
+        assert_eq!(RequestLine::parse("GET /../a HTTP/1.1"), None);
+        assert_eq!(RequestLine::parse("GET / HTTP"), None);
+    }
+
     #[test]
     #[should_panic]
     fn fail_missing_clause() {
@@ -610,6 +1517,35 @@ mod test {
         Header::parse("Test Header: test".to_string()).unwrap();
     }
 
+    #[test]
+    fn success_spooled_body_cleanup() {
+        // The spooled file should be removed once the last handle is dropped.
+        let (mut file, spooled) = SpooledBody::create().unwrap();
+        io::Write::write_all(&mut file, b"body").unwrap();
+        let path = spooled.path().to_owned();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+        let cloned = spooled.clone();
+        drop(spooled);
+        assert!(path.exists());
+        drop(cloned);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn success_spooled_body_names() {
+        // Spool files should get distinct names which aren't guessable.
+        let (_, first) = SpooledBody::create().unwrap();
+        let (_, second) = SpooledBody::create().unwrap();
+        assert_ne!(first.path(), second.path());
+        let name = first.path().file_name().unwrap().to_str().unwrap();
+        assert_eq!(name.len(), "octane-body-".len() + 32);
+    }
+
     #[test]
     fn success_keepalive() {
         // Parsing should work as expected.
@@ -625,4 +1561,212 @@ mod test {
         assert_eq!(req.timeout(), Some(5));
         assert_eq!(req.max(), None);
     }
+
+    #[test]
+    fn success_query_split() {
+        // The query should not be part of the path.
+        let line = RequestLine::parse("GET /search/?q=a/b&x HTTP/1.1").unwrap();
+        assert_eq!(line.path, PathBuf::parse("/search").unwrap());
+        assert_eq!(line.query, "q=a/b&x");
+        let headers = Headers::parse("Host: localhost".to_owned()).unwrap();
+        let req = Request::parse(line, headers, b"").unwrap();
+        assert_eq!(req.get_query()["q"], "a/b");
+    }
+
+    #[test]
+    fn success_path_accessors() {
+        // The path and query should be rebuilt from the request line.
+        let line = RequestLine::parse("GET /a/./b/?x=1 HTTP/1.1").unwrap();
+        let headers = Headers::parse("Host: localhost".to_owned()).unwrap();
+        let req = Request::parse(line, headers, b"").unwrap();
+        assert_eq!(req.path(), "/a/b");
+        assert_eq!(req.query_string(), Some("x=1"));
+        assert_eq!(req.full_path(), "/a/b?x=1");
+        let line = RequestLine::parse("GET / HTTP/1.1").unwrap();
+        let headers = Headers::parse("Host: localhost".to_owned()).unwrap();
+        let req = Request::parse(line, headers, b"").unwrap();
+        assert_eq!(req.path(), "/");
+        assert_eq!(req.query_string(), None);
+        assert_eq!(req.full_path(), "/");
+    }
+
+    #[test]
+    fn success_body_str() {
+        // Valid bodies should be strings and invalid ones only lossy strings.
+        let line = RequestLine::parse("POST / HTTP/1.1").unwrap();
+        let headers = Headers::parse("Host: localhost".to_owned()).unwrap();
+        let req = Request::parse(line, headers, "héllo".as_bytes()).unwrap();
+        assert_eq!(req.body_str(), Some("héllo"));
+        assert_eq!(req.body_str_lossy(), "héllo");
+        let line = RequestLine::parse("POST / HTTP/1.1").unwrap();
+        let headers = Headers::parse("Host: localhost".to_owned()).unwrap();
+        let req = Request::parse(line, headers, b"h\xffllo").unwrap();
+        assert_eq!(req.body_str(), None);
+        assert_eq!(req.body_str_lossy(), "h\u{fffd}llo");
+    }
+
+    #[test]
+    fn success_content_type() {
+        // The media type and its parameters should be parsed.
+        let req = request_with("Content-Type: Text/HTML; Charset=\"UTF-8\"; bad");
+        let content_type = req.content_type().unwrap();
+        assert_eq!(content_type.mime, "text/html");
+        assert_eq!(content_type.charset(), Some("UTF-8"));
+        assert_eq!(content_type.params.len(), 1);
+        assert_eq!(request_with("Content-Type: html").content_type(), None);
+    }
+
+    #[test]
+    fn success_content_length() {
+        // The length should only be there if the header is a number.
+        let req = request_with("Content-Length: 42");
+        assert_eq!(req.content_length(), Some(42));
+        assert_eq!(request_with("Accept: */*").content_length(), None);
+        assert_eq!(request_with("Content-Length: 4x").content_length(), None);
+        assert_eq!(request_with("Accept: */*").content_type(), None);
+    }
+
+    fn request_with(header: &str) -> Request<'static> {
+        let line = RequestLine::parse("PUT / HTTP/1.1").unwrap();
+        let headers = Headers::parse(format!("Host: localhost\r\n{}", header)).unwrap();
+        Request::parse(line, headers, b"").unwrap()
+    }
+
+    #[test]
+    fn success_precondition_check() {
+        // Matching tags and dates should let the write proceed.
+        let etag = Some("\"v2\"");
+        let modified = Some(UNIX_EPOCH + Duration::from_secs(333452334));
+        let req = request_with("If-Match: \"v1\", \"v2\"");
+        assert_eq!(req.precondition_check(etag, modified), Ok(()));
+        let req = request_with("If-Match: *");
+        assert_eq!(req.precondition_check(etag, None), Ok(()));
+        let req = request_with("If-Unmodified-Since: Sat, 26 Jul 1980 09:38:54 GMT");
+        assert_eq!(req.precondition_check(etag, modified), Ok(()));
+        let req = request_with("If-Unmodified-Since: yesterday");
+        assert_eq!(req.precondition_check(etag, modified), Ok(()));
+        let req = request_with("Accept: */*");
+        assert_eq!(req.precondition_check(None, None), Ok(()));
+    }
+
+    #[test]
+    fn fail_precondition_check() {
+        // Stale tags and dates should fail with 412.
+        let failed = Err(StatusCode::PreconditionFailed);
+        let modified = Some(UNIX_EPOCH + Duration::from_secs(333452335));
+        let req = request_with("If-Match: \"v1\"");
+        assert_eq!(req.precondition_check(Some("\"v2\""), modified), failed);
+        let req = request_with("If-Match: W/\"v2\"");
+        assert_eq!(req.precondition_check(Some("W/\"v2\""), None), failed);
+        let req = request_with("If-Match: *");
+        assert_eq!(req.precondition_check(None, None), failed);
+        let req = request_with("If-Unmodified-Since: Sat, 26 Jul 1980 09:38:54 GMT");
+        assert_eq!(req.precondition_check(None, modified), failed);
+    }
+
+    #[test]
+    fn success_etag_comparison() {
+        // Weak tags should only match under the weak comparison.
+        assert!(etag_matches("W/\"x\"", "\"x\"", true));
+        assert!(etag_matches("\"x\"", "W/\"x\"", true));
+        assert!(etag_matches("W/\"x\"", "W/\"x\"", true));
+        assert!(!etag_matches("W/\"x\"", "\"x\"", false));
+        assert!(!etag_matches("\"x\"", "W/\"x\"", false));
+        assert!(!etag_matches("W/\"x\"", "W/\"x\"", false));
+        assert!(etag_matches("\"y\", \"x\"", "\"x\"", false));
+        assert!(!etag_matches("\"y\"", "\"x\"", true));
+        let req = request_with("If-Match: \"x\"");
+        assert_eq!(req.precondition_check(Some("\"x\""), None), Ok(()));
+        let req = request_with("If-Match: W/\"x\"");
+        let failed = Err(StatusCode::PreconditionFailed);
+        assert_eq!(req.precondition_check(Some("\"x\""), None), failed);
+    }
+
+    #[test]
+    fn success_fresh() {
+        // Cached copies should be fresh when their tag matches weakly.
+        let get = |header: &str| {
+            let line = RequestLine::parse("GET / HTTP/1.1").unwrap();
+            let headers = Headers::parse(format!("Host: localhost\r\n{}", header)).unwrap();
+            Request::parse(line, headers, b"").unwrap()
+        };
+        let modified = Some(UNIX_EPOCH + Duration::from_secs(333452334));
+        assert!(get("If-None-Match: W/\"x\"").fresh(Some("\"x\""), None));
+        assert!(get("If-None-Match: \"x\"").fresh(Some("W/\"x\""), None));
+        assert!(get("If-None-Match: \"y\", \"x\"").fresh(Some("\"x\""), None));
+        assert!(get("If-None-Match: *").fresh(Some("\"x\""), None));
+        assert!(!get("If-None-Match: \"y\"").fresh(Some("\"x\""), modified));
+        let since = "If-Modified-Since: Sat, 26 Jul 1980 09:38:54 GMT";
+        assert!(get(since).fresh(None, modified));
+        assert!(!get(since).fresh(None, modified.map(|m| m + Duration::from_secs(1))));
+        assert!(!get("Accept: */*").fresh(Some("\"x\""), modified));
+        // writes are never answered with 304
+        assert!(!request_with("If-None-Match: \"x\"").fresh(Some("\"x\""), None));
+    }
+
+    fn request_accepting(accept: &str) -> Request<'static> {
+        let line = RequestLine::parse("GET / HTTP/1.1").unwrap();
+        let headers = Headers::parse(format!("Accept: {}", accept)).unwrap();
+        Request::parse(line, headers, b"").unwrap()
+    }
+
+    #[test]
+    fn success_preferred() {
+        // The type with the highest q-value should be preferred.
+        let req = request_accepting("application/json;q=0.9, text/html");
+        assert_eq!(
+            req.preferred(&["application/json", "text/html"]),
+            Some("text/html")
+        );
+        assert!(req.accepts("application/json"));
+        assert!(!req.accepts("image/png"));
+        assert_eq!(req.preferred(&["image/png"]), None);
+    }
+
+    #[test]
+    fn success_accepts_wildcards() {
+        // Wildcards should match, the most specific range decides the q-value.
+        let req = request_accepting("text/*;q=0.5, text/plain;q=0, */*;q=0.1");
+        assert!(req.accepts("text/html"));
+        assert!(!req.accepts("text/plain"));
+        assert!(req.accepts("image/png; charset=utf-8"));
+        assert_eq!(req.preferred(&["image/png", "text/css"]), Some("text/css"));
+    }
+
+    #[test]
+    fn success_accepts_without_header() {
+        // Everything is acceptable when there is no Accept header.
+        let line = RequestLine::parse("GET / HTTP/1.1").unwrap();
+        let headers = Headers::parse("Host: localhost".to_string()).unwrap();
+        let req = Request::parse(line, headers, b"").unwrap();
+        assert!(req.accepts("application/json"));
+        assert_eq!(req.preferred(&["text/html", "text/css"]), Some("text/html"));
+    }
+
+    #[test]
+    #[cfg(feature = "cookies")]
+    fn success_cookie() {
+        // Cookies should be read by their exact name.
+        let line = RequestLine::parse("GET / HTTP/1.1").unwrap();
+        let headers = Headers::parse("Cookie: a=1; b=2".to_string()).unwrap();
+        let req = Request::parse(line, headers, b"").unwrap();
+        assert_eq!(req.cookie("a"), Some("1"));
+        assert_eq!(req.cookie("b"), Some("2"));
+        assert_eq!(req.cookie("A"), None);
+        assert_eq!(req.cookies().len(), 2);
+    }
+
+    #[test]
+    #[cfg(feature = "cookies")]
+    fn success_split_cookie_headers() {
+        // Cookies split over several headers should all be parsed.
+        let line = RequestLine::parse("GET / HTTP/1.1").unwrap();
+        let headers =
+            Headers::parse("Cookie: a=1; b=2\r\nAccept: */*\r\ncookie: c=3".to_string()).unwrap();
+        assert_eq!(headers.get("cookie").unwrap(), "a=1; b=2; c=3");
+        let req = Request::parse(line, headers, b"").unwrap();
+        assert_eq!(req.cookie("b"), Some("2"));
+        assert_eq!(req.cookie("c"), Some("3"));
+        assert_eq!(req.cookies().len(), 3);
+    }
 }
@@ -1,17 +1,22 @@
 use crate::constants::*;
 #[cfg(feature = "cookies")]
-use crate::cookies::Cookies;
+use crate::cookies::{CookieAttributes, Cookies};
+use crate::error::escape_html;
 use crate::file_handler::FileHandler;
-use crate::request::HttpVersion;
+use crate::request::{etag_matches, HttpVersion};
+use crate::sse::{EventSender, EventStream};
 use crate::time::Time;
+use crate::util::{encode_segment, Skip};
+use crate::writer::BodyWriter;
 use octane_json::convert::ToJSON;
+use octane_json::Value;
 use octane_macros::status_codes;
-use std::collections::HashMap;
 use std::error::Error;
 use std::fmt;
-use std::io::Cursor;
-use std::path::PathBuf;
-use tokio::io::AsyncRead;
+use std::io::{Cursor, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+use tokio::io::{AsyncRead, AsyncReadExt};
 
 pub(crate) type BoxReader = Box<dyn AsyncRead + Unpin + Send>;
 
@@ -32,6 +37,178 @@ impl ResBody {
         !matches!(self, ResBody::None)
     }
 }
+/// Types which can be passed to [`Response::send()`](struct.Response.html#method.send).
+/// Text bodies get the default html `Content-Type` while byte
+/// bodies are sent as `application/octet-stream`
+///
+/// # Example
+///
+/// ```
+/// use octane::prelude::*;
+///
+/// let mut app = Octane::new();
+/// app.get(
+///     "/",
+///     route_stop!(|req, res| {
+///         res.send(vec![0xde, 0xad, 0xbe, 0xef]);
+///         assert_eq!(
+///             res.get("Content-Type"),
+///             Some(&"application/octet-stream".to_owned())
+///         );
+///     }),
+/// );
+/// ```
+pub trait IntoBody {
+    /// Returns true if the body is text
+    fn is_text() -> bool;
+    /// Converts the body to the bytes which will be sent
+    fn into_body(self) -> Vec<u8>;
+}
+
+macro_rules! into_body {
+    ($text: expr, $($ty: ty),*) => {
+        $(
+            impl IntoBody for $ty {
+                fn is_text() -> bool {
+                    $text
+                }
+                fn into_body(self) -> Vec<u8> {
+                    self.to_owned().into()
+                }
+            }
+        )*
+    };
+}
+
+into_body!(true, &str, String, &&str, &String);
+into_body!(false, &[u8], Vec<u8>, &Vec<u8>);
+
+impl<const N: usize> IntoBody for &[u8; N] {
+    fn is_text() -> bool {
+        false
+    }
+    fn into_body(self) -> Vec<u8> {
+        self.to_vec()
+    }
+}
+
+// A single byte range from the Range header, the ends are inclusive
+#[derive(Debug, PartialEq, Eq)]
+enum ByteRange {
+    Satisfiable(usize, usize),
+    Unsatisfiable,
+}
+
+impl ByteRange {
+    // Returns None if the header should be ignored, which is the case
+    // for malformed headers and for several ranges as multipart
+    // responses aren't supported
+    fn parse(header: &str, len: usize) -> Option<Self> {
+        let spec = header.trim().strip_prefix("bytes=")?;
+        if spec.contains(',') {
+            return None;
+        }
+        let (start, end) = spec.split_at(spec.find('-')?);
+        let end = end[1..].trim();
+        let range = if start.trim().is_empty() {
+            // suffix range, the last n bytes
+            let suffix: usize = end.parse().ok()?;
+            if suffix == 0 || len == 0 {
+                return Some(ByteRange::Unsatisfiable);
+            }
+            (len.saturating_sub(suffix), len - 1)
+        } else {
+            let start: usize = start.trim().parse().ok()?;
+            let end = if end.is_empty() {
+                usize::MAX
+            } else {
+                end.parse().ok()?
+            };
+            if end < start {
+                return None;
+            }
+            if start >= len {
+                return Some(ByteRange::Unsatisfiable);
+            }
+            (start, end.min(len - 1))
+        };
+        Some(ByteRange::Satisfiable(range.0, range.1))
+    }
+}
+
+/// The headers of a response. Unlike request headers, they are
+/// kept in the order they were first set and with the casing they
+/// were first set with, so that the serialized response is
+/// deterministic. Lookups are case insensitive and setting a header
+/// again replaces its value in place
+///
+/// # Example
+///
+/// ```
+/// use octane::responder::ResponseHeaders;
+///
+/// let mut headers = ResponseHeaders::new();
+/// headers.insert("ETag", "\"abc\"");
+/// headers.insert("Cache-Control", "no-cache");
+/// headers.insert("etag", "\"def\"");
+/// assert_eq!(headers.get("ETAG"), Some(&"\"def\"".to_owned()));
+/// let names: Vec<&str> = headers.iter().map(|(name, _)| name).collect();
+/// assert_eq!(names, vec!["ETag", "Cache-Control"]);
+/// ```
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct ResponseHeaders {
+    entries: Vec<(String, String)>,
+}
+
+impl ResponseHeaders {
+    /// Creates an empty set of headers
+    pub fn new() -> Self {
+        Default::default()
+    }
+    /// Sets a header, returning the previous value if there was one
+    pub fn insert(&mut self, name: impl Into<String>, value: impl Into<String>) -> Option<String> {
+        let (name, value) = (name.into(), value.into());
+        match self.position(&name) {
+            Some(i) => Some(std::mem::replace(&mut self.entries[i].1, value)),
+            None => {
+                self.entries.push((name, value));
+                None
+            }
+        }
+    }
+    /// Returns the value of a header
+    pub fn get(&self, name: &str) -> Option<&String> {
+        self.position(name).map(|i| &self.entries[i].1)
+    }
+    /// Removes a header and returns its value
+    pub fn remove(&mut self, name: &str) -> Option<String> {
+        self.position(name).map(|i| self.entries.remove(i).1)
+    }
+    /// Returns true if the header is set
+    pub fn contains_key(&self, name: &str) -> bool {
+        self.position(name).is_some()
+    }
+    /// Returns the number of headers
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+    /// Returns true if no headers are set
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+    /// Iterates over the headers as (name, value) pairs in order
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.entries
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.as_str()))
+    }
+    fn position(&self, name: &str) -> Option<usize> {
+        self.entries
+            .iter()
+            .position(|(key, _)| key.eq_ignore_ascii_case(name))
+    }
+}
+
 /// The response struct contains the data which is
 /// to be send on a request. The struct has several
 /// methods to modify the contents.
@@ -59,9 +236,19 @@ pub struct Response {
     /// Http version which the response will use
     pub http_version: String,
     /// Custom headers which will be sent with the response
-    pub headers: HashMap<String, String>,
+    pub headers: ResponseHeaders,
     /// Content-Type charset
     pub charset: Option<String>,
+    // The type of files with an unknown extension, from the config
+    pub(crate) unknown_type: Option<String>,
+    // The Cache-Control of static files, from the config
+    pub(crate) static_cache_control: Option<String>,
+    // Whether static directories are listed, from the config
+    pub(crate) autoindex: bool,
+    // Whether paths match without regard to case, from the config
+    pub(crate) case_insensitive_paths: bool,
+    // A status line set with set_status_line, sent as it is
+    status_line: Option<String>,
     #[cfg(feature = "cookies")]
     /// Cookies that will be sent with the response
     pub cookies: Cookies,
@@ -106,7 +293,7 @@ impl Response {
     ///     "/",
     ///     route!(|req, res| {
     ///         res.send("Hello, world");
-    ///         assert_eq!(res.get("Content-Type"),  Some(&"text/html".to_owned()));
+    ///         assert_eq!(res.get("Content-Type"),  Some(&"text/html; charset=utf-8".to_owned()));
     ///         Flow::Stop
     ///     }),
     /// );
@@ -114,8 +301,9 @@ impl Response {
     pub fn get(&mut self, field: &'static str) -> Option<&String> {
         self.headers.get(field)
     }
-    /// Puts the given text to the body and send it
-    /// as html by default
+    /// Puts the given body to the response. Strings are sent
+    /// as utf-8 html and bytes as `application/octet-stream` unless
+    /// a `Content-Type` has already been set, see [`IntoBody`](trait.IntoBody.html)
     ///
     /// # Example
     /// ```
@@ -133,11 +321,16 @@ impl Response {
     /// );
     ///
     /// ```
-    pub fn send<T: AsRef<[u8]>>(&mut self, body: T) {
-        let body_slice = body.as_ref();
-        let len = body_slice.len();
-        self.body = ResBody::Sized(len, Box::new(Cursor::new(body_slice.to_vec())) as BoxReader);
-        self.content_len = Some(body_slice.len());
+    pub fn send<T: IntoBody>(&mut self, body: T) {
+        if !T::is_text() {
+            self.default_type("application/octet-stream");
+        }
+        let body = body.into_body();
+        let len = body.len();
+        // ranges are only served for files
+        self.headers.remove("Accept-Ranges");
+        self.body = ResBody::Sized(len, Box::new(Cursor::new(body)) as BoxReader);
+        self.content_len = Some(len);
         self.default_headers();
     }
     /// Automatically set headers like date, content
@@ -151,14 +344,24 @@ impl Response {
         if let Some(date) = Time::now() {
             self.headers.insert("Date".to_string(), date.format());
         }
+        self.default_type("text/html");
+        self
+    }
+    // Formats a mime type for the Content-Type header, text types are
+    // utf-8 unless another charset was set and other types only get
+    // a charset if one was set explicitly
+    fn format_type(&self, mime: &str) -> String {
+        match &self.charset {
+            Some(charset) => format!("{}; charset={}", mime, charset),
+            None if mime.starts_with("text/") => format!("{}; charset=utf-8", mime),
+            None => mime.to_owned(),
+        }
+    }
+    // Sets the Content-Type unless one was already set
+    fn default_type(&mut self, mime: &str) {
         if self.headers.get("Content-Type").is_none() {
-            let mut format = String::from("text/html");
-            if let Some(charset) = &self.charset {
-                format.push_str(&format!(" ;charset={:?}", charset));
-            }
-            self.set("Content-Type", &format);
+            self.content_type(mime);
         }
-        self
     }
     /// Modify the `Content-Type` header as passed
     /// in the argument
@@ -186,6 +389,28 @@ impl Response {
         self.set("Content-Type", _type);
         self
     }
+    /// Sets the `Content-Type` header to the mime type, adding the
+    /// charset for text types or if one was set with the `charset`
+    /// field, see [`with_type()`](#method.with_type) to set the header
+    /// exactly as given
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use octane::prelude::*;
+    ///
+    /// let mut app = Octane::new();
+    /// app.get(
+    ///     "/feed",
+    ///     route_stop!(|req, res| {
+    ///         res.content_type("application/xml").send("<feed></feed>");
+    ///     }),
+    /// );
+    /// ```
+    pub fn content_type(&mut self, mime: &str) -> &mut Self {
+        let content_type = self.format_type(mime);
+        self.set("Content-Type", &content_type)
+    }
     /// Consume the response and get the final formed http
     /// response that the server will send in bytes
     pub fn get_data(self) -> (String, BoxReader) {
@@ -195,7 +420,9 @@ impl Response {
         )
     }
     /// Send a file as the response, automatically detect the
-    /// mime type and set the headers accordingly
+    /// mime type and set the headers accordingly. Returns `None`
+    /// and leaves the response untouched if the path isn't a
+    /// regular file, like a directory or a dangling symlink
     ///
     /// # Example
     ///
@@ -208,8 +435,8 @@ impl Response {
     ///     "/",
     ///     route!(
     ///         |req, res| {
-    ///             res.send_file("templates/index.html").expect("file not found");
-    ///             assert_eq!(res.get("Content-Type"),  Some(&"text/html".to_owned()));
+    ///             res.send_file("templates/index.html").expect("cannot read file");
+    ///             assert_eq!(res.get("Content-Type"),  Some(&"text/html; charset=utf-8".to_owned()));
     ///             Flow::Stop
     ///         }
     ///     ),
@@ -217,17 +444,238 @@ impl Response {
     ///
     /// ```
     pub fn send_file(&mut self, file: &str) -> Result<Option<()>, Box<dyn Error>> {
-        let file = FileHandler::handle_file(&PathBuf::from(file))?;
-        self.headers.insert(
-            "Content-Type".to_string(),
-            FileHandler::mime_type(file.extension),
-        );
+        let file = match FileHandler::handle_file(&PathBuf::from(file))? {
+            Some(file) => file,
+            None => return Ok(None),
+        };
+        let mime = match FileHandler::known_mime_type(&file.extension) {
+            Some(mime) => mime,
+            None => self
+                .unknown_type
+                .as_deref()
+                .unwrap_or("application/octet-stream"),
+        };
+        let content_type = self.format_type(mime);
+        self.set("Content-Type", &content_type);
         let len = file.meta.len() as usize;
+        if let Ok(modified) = file.meta.modified() {
+            let secs = modified
+                .duration_since(std::time::UNIX_EPOCH)
+                .map_or(0, |d| d.as_secs());
+            self.set("ETag", &format!("\"{:x}-{:x}\"", len, secs));
+            if let Some(time) = Time::from_system(modified) {
+                self.set("Last-Modified", &time.format());
+            }
+        }
+        self.set("Accept-Ranges", "bytes");
         self.content_len = Some(len);
         self.body = ResBody::Sized(len, Box::new(file.file) as BoxReader);
+        self.default_headers();
         Ok(Some(()))
     }
 
+    // Lists the entries of a directory requested on the path, as an html
+    // page with links or as a json array of names. The directory is read
+    // on a blocking thread and the listing streamed once it is ready
+    pub(crate) fn send_listing(&mut self, dir: PathBuf, path: &str, json: bool) {
+        let content_type = if json {
+            "application/json"
+        } else {
+            "text/html"
+        };
+        let mut body = self
+            .content_type(content_type)
+            .set("Vary", "Accept")
+            .body_writer();
+        let path = path.to_owned();
+        tokio::task::spawn_blocking(move || {
+            if let Ok(listing) = listing(&dir, &path, json) {
+                body.write_all(listing.as_bytes()).ok();
+            }
+        });
+    }
+
+    // Adds the configured Cache-Control to a static file response,
+    // unless a closure chose one for it already
+    pub(crate) fn cache_static(&mut self) {
+        if let Some(value) = self.static_cache_control.clone() {
+            if !self.headers.contains_key("Cache-Control") {
+                self.set("Cache-Control", &value);
+            }
+        }
+    }
+
+    // Narrows a file response down to the byte range the client asked
+    // for. The full response is kept when the range is ignored or when
+    // the If-Range validator doesn't match the file anymore
+    pub(crate) fn range(&mut self, range: &str, if_range: Option<&str>) {
+        let ranged = self.status_code == StatusCode::Ok
+            && self.headers.get("Accept-Ranges").map(String::as_str) == Some("bytes");
+        if !ranged || !if_range.map_or(true, |validator| self.validates(validator)) {
+            return;
+        }
+        let len = match &self.body {
+            ResBody::Sized(len, _) => *len,
+            _ => return,
+        };
+        match ByteRange::parse(range, len) {
+            Some(ByteRange::Satisfiable(start, end)) => {
+                let part = end - start + 1;
+                let body = std::mem::replace(&mut self.body, ResBody::None).get_reader();
+                let body = Skip::new(body, start).take(part as u64);
+                self.body = ResBody::Sized(part, Box::new(body) as BoxReader);
+                self.content_len = Some(part);
+                self.status(StatusCode::PartialContent)
+                    .set("Content-Range", &format!("bytes {}-{}/{}", start, end, len))
+                    .set("Content-Length", &part.to_string());
+            }
+            Some(ByteRange::Unsatisfiable) => {
+                self.body = ResBody::None;
+                self.content_len = None;
+                self.headers.remove("Content-Length");
+                self.status(StatusCode::RangeNotSatisfiable)
+                    .set("Content-Range", &format!("bytes */{}", len));
+            }
+            None => (),
+        }
+    }
+    // Checks an If-Range validator, entity tags have to match strongly
+    // and dates have to be the exact modification date
+    fn validates(&self, validator: &str) -> bool {
+        let validator = validator.trim();
+        if validator.starts_with('"') || validator.starts_with("W/") {
+            self.headers
+                .get("ETag")
+                .map_or(false, |etag| etag_matches(validator, etag, false))
+        } else {
+            self.headers.get("Last-Modified").map(String::as_str) == Some(validator)
+        }
+    }
+
+    /// Turns the response into a server-sent events stream with
+    /// the `text/event-stream` content type and returns the sender
+    /// to push events with. The connection is kept open until every
+    /// sender is dropped, and closed afterwards
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use octane::prelude::*;
+    ///
+    /// let mut app = Octane::new();
+    /// app.get(
+    ///     "/events",
+    ///     route_stop!(|req, res| {
+    ///         let events = res.sse();
+    ///         events.send("hello");
+    ///     }),
+    /// );
+    /// ```
+    pub fn sse(&mut self) -> EventSender {
+        let (sender, stream) = EventStream::new();
+        self.body = ResBody::Unsized(Box::new(stream) as BoxReader);
+        self.content_len = None;
+        self.headers.remove("Content-Length");
+        self.headers.remove("Accept-Ranges");
+        self.set("Content-Type", "text/event-stream")
+            .set("Cache-Control", "no-cache")
+            // ask proxies like nginx not to buffer the events
+            .set("X-Accel-Buffering", "no")
+            .default_headers();
+        sender
+    }
+
+    /// Streams the body from a writer instead of a buffer, for large
+    /// documents which are generated as they are sent. The returned
+    /// [`BodyWriter`](../writer/struct.BodyWriter.html) implements
+    /// `std::io::Write` and can be moved to another thread, the body
+    /// ends once it is dropped and the connection is closed afterwards
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use octane::prelude::*;
+    /// use std::io::Write;
+    ///
+    /// let mut app = Octane::new();
+    /// app.get(
+    ///     "/report",
+    ///     route_stop!(|req, res| {
+    ///         let mut body = res.with_type("text/plain").body_writer();
+    ///         writeln!(body, "line 1").ok();
+    ///         writeln!(body, "line 2").ok();
+    ///     }),
+    /// );
+    /// ```
+    pub fn body_writer(&mut self) -> BodyWriter {
+        let (writer, stream) = BodyWriter::new();
+        self.body = ResBody::Unsized(Box::new(stream) as BoxReader);
+        self.content_len = None;
+        self.headers.remove("Content-Length");
+        self.headers.remove("Accept-Ranges");
+        self.default_headers();
+        writer
+    }
+
+    /// Sends the response of an upstream server, for proxies and
+    /// gateways. The body is copied from the reader as it is sent and
+    /// the headers are kept, except the hop-by-hop ones like
+    /// `Connection` which only applied to the upstream connection.
+    /// The `Content-Length` of the headers is used as the length of
+    /// the body, anything the upstream sends past it is left unread and
+    /// the connection is closed if it ends short of it. Without one the
+    /// connection is closed after the body
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use octane::prelude::*;
+    ///
+    /// let mut app = Octane::new();
+    /// app.get(
+    ///     "/proxied",
+    ///     route_stop!(|req, res| {
+    ///         // the body would usually come from an upstream connection
+    ///         let body: &'static [u8] = b"{}";
+    ///         let headers = [("Content-Type", "application/json"), ("Content-Length", "2")];
+    ///         res.pipe(StatusCode::Ok, &headers, body);
+    ///     }),
+    /// );
+    /// ```
+    pub fn pipe<R>(&mut self, status: StatusCode, headers: &[(&str, &str)], body: R) -> &mut Self
+    where
+        R: AsyncRead + Unpin + Send + 'static,
+    {
+        const HOP_BY_HOP: [&str; 9] = [
+            "connection",
+            "keep-alive",
+            "proxy-authenticate",
+            "proxy-authorization",
+            "te",
+            "trailer",
+            "transfer-encoding",
+            "upgrade",
+            "content-length",
+        ];
+        let mut len = None;
+        for (name, value) in headers {
+            if name.eq_ignore_ascii_case("content-length") {
+                len = value.trim().parse().ok();
+            }
+            if !HOP_BY_HOP.iter().any(|hop| name.eq_ignore_ascii_case(hop)) {
+                self.set(name, value);
+            }
+        }
+        self.headers.remove("Accept-Ranges");
+        self.headers.remove("Content-Length");
+        self.body = match len {
+            Some(len) => ResBody::Sized(len, Box::new(body.take(len as u64)) as BoxReader),
+            None => ResBody::Unsized(Box::new(body) as BoxReader),
+        };
+        self.content_len = len;
+        self.status(status).default_headers()
+    }
+
     /// Converts the structure to a json string and sends
     /// it as the response with the mime type `application/json`.
     /// The structure which will be passed should implement
@@ -247,8 +695,8 @@ impl Response {
     ///     "/",
     ///     route!(
     ///         |req, res| {
-    ///             // add example here
-    ///             // assert_eq!(res.get("Content-Type"),  Some(&"application/json".to_owned()));
+    ///             res.json(vec![1u64, 2, 3]);
+    ///             assert_eq!(res.get("Content-Type"),  Some(&"application/json".to_owned()));
     ///             Flow::Stop
     ///         }
     ///     ),
@@ -263,9 +711,38 @@ impl Response {
                 .as_bytes()
                 .to_vec(),
         )) as BoxReader);
-        self.with_type("application/json");
+        self.headers.remove("Accept-Ranges");
+        self.content_type("application/json");
         self.default_headers();
     }
+    /// Sends an error in the json envelope
+    /// `{"error": {"code": <status>, "message": "..."}}` with the given
+    /// status and the `application/json` type. The server sends its own
+    /// errors, like the 404 page, in the same shape to clients which
+    /// prefer json in their `Accept` header
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use octane::prelude::*;
+    ///
+    /// let mut app = Octane::new();
+    /// app.get(
+    ///     "/users/:id",
+    ///     route_stop!(|req, res| {
+    ///         res.send_json_error(StatusCode::NotFound, "No such user");
+    ///     }),
+    /// );
+    /// ```
+    pub fn send_json_error(&mut self, status: StatusCode, message: &str) {
+        let body = format!(
+            r#"{{"error":{{"code":{},"message":{}}}}}"#,
+            status.as_u16(),
+            Value::String(message.to_owned())
+        );
+        self.status(status).content_type("application/json");
+        self.send(body);
+    }
     /// Set the status code from the status code enum
     ///
     /// # Example
@@ -288,12 +765,57 @@ impl Response {
     /// ```
     pub fn status(&mut self, code: StatusCode) -> &mut Self {
         self.status_code = code;
+        self.status_line = None;
         self
     }
     /// Sets the http version specified, to specify a version
     /// the version type should be variant of HttpVersion
     pub fn http_version(&mut self, version: HttpVersion) -> &mut Self {
         self.http_version = version.to_string();
+        self.status_line = None;
+        self
+    }
+    /// Sets the whole status line, for responses which need a reason
+    /// phrase of their own or a status code which isn't listed. The
+    /// line is sent as it is given, except for line breaks which are
+    /// dropped so that it can't add headers. Calling
+    /// [`status()`](#method.status) or
+    /// [`http_version()`](#method.http_version) afterwards goes back
+    /// to the usual status line. Informational `1xx` responses get an
+    /// empty body without a length, as they can't have one.
+    ///
+    /// Note that the connection is still served as http after a
+    /// `101 Switching Protocols`, it isn't handed over to the closure
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use octane::prelude::*;
+    /// use octane::request::HttpVersion;
+    ///
+    /// let mut app = Octane::new();
+    /// app.get(
+    ///     "/legacy",
+    ///     route_stop!(|req, res| {
+    ///         res.set_status_line(HttpVersion::Http11, 299, "Deprecated But Fine")
+    ///             .send("ok");
+    ///     }),
+    /// );
+    /// ```
+    pub fn set_status_line(&mut self, version: HttpVersion, code: u16, reason: &str) -> &mut Self {
+        self.http_version = version.to_string();
+        self.status_code = StatusCode::Other(code as i32, "");
+        let reason: String = reason
+            .chars()
+            .filter(|c| *c != '\r' && *c != '\n')
+            .collect();
+        self.status_line = Some(format!(
+            "HTTP/{}{}{}{}{}{}",
+            self.http_version, SP, code, SP, reason, CRLF
+        ));
+        if code < 200 {
+            self.empty_body(false);
+        }
         self
     }
     /// Tells if the headers are sent or not
@@ -387,6 +909,57 @@ impl Response {
         self.set("Location", location);
         self
     }
+    /// Ends the response with `204 No Content` and an empty body,
+    /// dropping any body and `Content-Type` which were set before.
+    /// The response counts as produced, so later closures like
+    /// [`static_dir()`](../struct.Octane.html#method.static_dir) leave it alone
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use octane::prelude::*;
+    ///
+    /// let mut app = Octane::new();
+    /// app.delete(
+    ///     "/users/1",
+    ///     route_stop!(|req, res| {
+    ///         res.no_content();
+    ///     }),
+    /// );
+    /// ```
+    pub fn no_content(&mut self) -> &mut Self {
+        self.status(StatusCode::NoContent).empty_body(false)
+    }
+    /// Ends the response with `205 Reset Content` and an empty body,
+    /// telling the client to reset the form it submitted, see
+    /// [`no_content()`](#method.no_content)
+    pub fn reset_content(&mut self) -> &mut Self {
+        self.status(StatusCode::ResetContent).empty_body(true)
+    }
+    /// Ends the response with `304 Not Modified` and an empty body,
+    /// for conditional requests whose cached copy is still fresh.
+    /// Validators like `ETag` are kept, see [`no_content()`](#method.no_content)
+    pub fn not_modified(&mut self) -> &mut Self {
+        self.status(StatusCode::NotModified).empty_body(false)
+    }
+    // Replaces the body with an empty one which still counts as a
+    // body. 204 and 304 responses must not announce a length while
+    // 205 has to say its body is empty
+    fn empty_body(&mut self, with_length: bool) -> &mut Self {
+        self.body = ResBody::Sized(0, Box::new(tokio::io::empty()) as BoxReader);
+        self.content_len = None;
+        self.headers.remove("Content-Type");
+        self.headers.remove("Accept-Ranges");
+        if let Some(date) = Time::now() {
+            self.set("Date", &date.format());
+        }
+        if with_length {
+            self.set("Content-Length", "0");
+        } else {
+            self.headers.remove("Content-Length");
+        }
+        self
+    }
     /// Creates a cookie with the specified name
     /// and value. This method requires `cookies`
     /// feature, which is enabled in default feature
@@ -413,7 +986,71 @@ impl Response {
         self.cookies.set(name, value);
         self
     }
-    /// Sets the content type charset
+    /// Sets a cookie along with attributes like `Path`, `HttpOnly`
+    /// or `SameSite`, see [`CookieAttributes`](../cookies/struct.CookieAttributes.html).
+    /// This method requires the `cookies` feature
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use octane::cookies::{CookieAttributes, SameSite};
+    /// use octane::prelude::*;
+    ///
+    /// let mut app = Octane::new();
+    /// app.get(
+    ///     "/",
+    ///     route_stop!(|req, res| {
+    ///         let mut attributes = CookieAttributes::new();
+    ///         attributes.path("/").http_only().same_site(SameSite::Strict);
+    ///         res.cookie_with("name", "value", &attributes)
+    ///             .send("Cookie has been set!");
+    ///     }),
+    /// );
+    /// ```
+    #[cfg(feature = "cookies")]
+    pub fn cookie_with(
+        &mut self,
+        name: &str,
+        value: &str,
+        attributes: &CookieAttributes,
+    ) -> &mut Self {
+        self.cookies.set_with(name, value, attributes);
+        self
+    }
+    /// Sets the `Retry-After` header to a delay in seconds, telling
+    /// the client how long to wait before trying again after a `503`
+    /// or a `429`. Fractions of a second are rounded up
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use octane::prelude::*;
+    /// use std::time::Duration;
+    ///
+    /// let mut app = Octane::new();
+    /// app.post(
+    ///     "/jobs",
+    ///     route_stop!(|req, res| {
+    ///         res.status(StatusCode::TooManyRequests)
+    ///             .retry_after(Duration::from_secs(30))
+    ///             .send("slow down");
+    ///     }),
+    /// );
+    /// ```
+    pub fn retry_after(&mut self, delay: Duration) -> &mut Self {
+        self.set("Retry-After", &delay_seconds(delay))
+    }
+    /// Sets the `Retry-After` header to the date after which the
+    /// client can try again, see [`retry_after()`](#method.retry_after)
+    pub fn retry_after_date(&mut self, date: SystemTime) -> &mut Self {
+        match Time::from_system(date) {
+            Some(time) => self.set("Retry-After", &time.format()),
+            None => self,
+        }
+    }
+    /// Sets the content type charset, text responses are
+    /// `utf-8` by default and other types only get a charset
+    /// if one is set
     ///
     /// # Example
     ///
@@ -424,7 +1061,7 @@ impl Response {
     /// app.get(
     ///     "/",
     ///     route!(|req, res| {
-    ///         res.charset("utf-8").send("Hello"); // the header is now Content-Type: text/html; charset=utf-8
+    ///         res.charset("iso-8859-1").send("Hello"); // the header is now Content-Type: text/html; charset=iso-8859-1
     ///         Flow::Stop
     ///     }),
     /// );
@@ -433,9 +1070,40 @@ impl Response {
         self.charset = Some(charset.to_owned());
         self
     }
+    /// Sets `Connection: close` so that the server closes the
+    /// connection once the response is sent, even if the client
+    /// asked for it to be kept alive
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use octane::prelude::*;
+    ///
+    /// let mut app = Octane::new();
+    /// app.get(
+    ///     "/bye",
+    ///     route_stop!(|req, res| {
+    ///         res.close_connection().send("Goodbye");
+    ///     }),
+    /// );
+    /// ```
+    pub fn close_connection(&mut self) -> &mut Self {
+        self.set("Connection", "close")
+    }
+    // Returns true if the connection has to be closed after the response
+    pub(crate) fn closes_connection(&self) -> bool {
+        self.headers
+            .get("Connection")
+            .map_or(false, |value| value.eq_ignore_ascii_case("close"))
+    }
     pub(crate) fn has_body(&self) -> bool {
         self.body.is_some()
     }
+    // Returns true if the length of the body isn't known in advance,
+    // the end of such bodies is marked by closing the connection
+    pub(crate) fn is_unsized(&self) -> bool {
+        matches!(self.body, ResBody::Unsized(_))
+    }
     // Creates a new response from a slice
     pub(crate) fn new_from_slice<T: AsRef<[u8]>>(body: T) -> Self {
         let body_slice = body.as_ref();
@@ -451,8 +1119,13 @@ impl Response {
             body: ResBody::None,
             content_len: None,
             http_version: "1.1".to_owned(),
-            headers: HashMap::new(),
+            headers: ResponseHeaders::new(),
             charset: None,
+            unknown_type: None,
+            static_cache_control: None,
+            autoindex: false,
+            case_insensitive_paths: false,
+            status_line: None,
             #[cfg(feature = "cookies")]
             cookies: Cookies::new(),
         }
@@ -470,8 +1143,13 @@ impl Response {
             body: body_res,
             content_len,
             http_version: "1.1".to_owned(),
-            headers: HashMap::new(),
+            headers: ResponseHeaders::new(),
             charset: None,
+            unknown_type: None,
+            static_cache_control: None,
+            autoindex: false,
+            case_insensitive_paths: false,
+            status_line: None,
             #[cfg(feature = "cookies")]
             cookies: Cookies::new(),
         }
@@ -483,6 +1161,9 @@ impl Response {
         self.status_code.into()
     }
     fn status_line(&self) -> String {
+        if let Some(line) = &self.status_line {
+            return line.clone();
+        }
         format!(
             "{}/{}{}{}{}{}{}",
             "HTTP",
@@ -497,9 +1178,9 @@ impl Response {
     fn headers(&self) -> String {
         let mut headers_str = String::new();
         // push normal headers
-        self.headers
-            .iter()
-            .for_each(|data| headers_str.push_str(&format!("{}:{}{}{}", data.0, SP, data.1, CRLF)));
+        self.headers.iter().for_each(|(name, value)| {
+            headers_str.push_str(&format!("{}:{}{}{}", name, SP, value, CRLF))
+        });
         // push cookies
         #[cfg(feature = "cookies")]
         {
@@ -573,6 +1254,34 @@ status_codes! {
     511 "Network Authentication Required"
 }
 
+impl StatusCode {
+    /// Returns the number of the status code
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use octane::prelude::*;
+    ///
+    /// assert_eq!(StatusCode::NotFound.as_u16(), 404);
+    /// ```
+    pub fn as_u16(&self) -> u16 {
+        self.fetch().0 as u16
+    }
+    /// Returns the reason phrase which is sent along with the
+    /// status code
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use octane::prelude::*;
+    ///
+    /// assert_eq!(StatusCode::NotFound.reason_phrase(), "Not Found");
+    /// ```
+    pub fn reason_phrase(&self) -> &'static str {
+        self.fetch().1
+    }
+}
+
 impl Into<i32> for StatusCode {
     fn into(self) -> i32 {
         let (n, _) = self.fetch();
@@ -603,6 +1312,63 @@ impl fmt::Debug for Response {
     }
 }
 
+// Formats a delay as whole seconds for a `Retry-After` header, rounded
+// up so that clients don't come back too early
+pub(crate) fn delay_seconds(delay: Duration) -> String {
+    let secs = delay.as_secs() + if delay.subsec_nanos() > 0 { 1 } else { 0 };
+    secs.to_string()
+}
+
+// Builds the listing of a directory, directories get a trailing
+// slash and hidden entries are left out. The names are escaped for
+// the page and percent-encoded in the links
+fn listing(dir: &Path, path: &str, json: bool) -> std::io::Result<String> {
+    let mut names = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if name.starts_with('.') {
+            continue;
+        }
+        names.push((name, entry.path().is_dir()));
+    }
+    names.sort();
+    if json {
+        let names: Vec<String> = names
+            .into_iter()
+            .map(|(name, dir)| {
+                let name = if dir { name + "/" } else { name };
+                Value::String(name).to_string()
+            })
+            .collect();
+        return Ok(format!("[{}]", names.join(",")));
+    }
+    let title = escape_html(path);
+    let base: String = path
+        .split('/')
+        .filter(|segment| !segment.is_empty())
+        .map(|segment| format!("/{}", encode_segment(segment)))
+        .collect();
+    let mut page = format!(
+        "<!DOCTYPE html><html><head><title>Index of {0}</title></head>\
+         <body><h1>Index of {0}</h1><ul>",
+        title
+    );
+    for (name, dir) in names {
+        let slash = if dir { "/" } else { "" };
+        page.push_str(&format!(
+            "<li><a href=\"{}/{}{}\">{}{}</a></li>",
+            base,
+            encode_segment(&name),
+            slash,
+            escape_html(&name),
+            slash
+        ));
+    }
+    page.push_str("</ul></body></html>");
+    Ok(page)
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -649,4 +1415,318 @@ mod test {
             "HTTP/1.0 201 CREATED\r\n\r\n"
         );
     }
+
+    #[crate::test]
+    async fn set_status_line() {
+        // The status line should be sent exactly as it was set.
+        let mut res = Response::new_empty();
+        res.set_status_line(HttpVersion::Http11, 101, "Switching Protocols")
+            .set("Upgrade", "websocket")
+            .set("Connection", "Upgrade");
+        assert_eq!(res.status_code.as_u16(), 101);
+        assert!(res.has_body());
+        let data = data_to_string(res.get_data()).await;
+        assert!(data.starts_with("HTTP/1.1 101 Switching Protocols\r\n"));
+        assert!(data.ends_with("\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\r\n"));
+        assert!(!data.contains("Content-Length"));
+        let mut res = Response::new_empty();
+        res.set_status_line(HttpVersion::Http10, 299, "Odd\r\nX-Injected: 1");
+        assert_eq!(
+            data_to_string(res.get_data()).await,
+            "HTTP/1.0 299 OddX-Injected: 1\r\n\r\n"
+        );
+        let mut res = Response::new_empty();
+        res.set_status_line(HttpVersion::Http11, 299, "Deprecated")
+            .status(StatusCode::Ok);
+        assert_eq!(
+            data_to_string(res.get_data()).await,
+            "HTTP/1.1 200 OK\r\n\r\n"
+        );
+    }
+
+    #[test]
+    fn success_reason_phrases() {
+        // Status codes should map to their number and reason phrase
+        let codes = [
+            (StatusCode::Ok, 200, "OK"),
+            (StatusCode::NotFound, 404, "Not Found"),
+            (StatusCode::ImATeapot, 418, "I'm a teapot"),
+            (StatusCode::Other(299, "Custom"), 299, "Custom"),
+        ];
+        for (code, number, phrase) in codes.iter() {
+            assert_eq!(code.as_u16(), *number);
+            assert_eq!(code.reason_phrase(), *phrase);
+        }
+    }
+
+    #[test]
+    fn send_str() {
+        // String slices should be sent as html
+        let mut res = Response::new_empty();
+        res.send("Hello");
+        assert_eq!(
+            res.get("Content-Type"),
+            Some(&"text/html; charset=utf-8".to_owned())
+        );
+        assert_eq!(res.get("Content-Length"), Some(&"5".to_owned()));
+    }
+
+    #[test]
+    fn send_string() {
+        // Owned strings should be sent as html
+        let mut res = Response::new_empty();
+        res.send(String::from("Hello"));
+        assert_eq!(
+            res.get("Content-Type"),
+            Some(&"text/html; charset=utf-8".to_owned())
+        );
+        assert_eq!(res.get("Content-Length"), Some(&"5".to_owned()));
+    }
+
+    #[test]
+    fn send_vec() {
+        // Byte vectors should be sent as binary data
+        let mut res = Response::new_empty();
+        res.send(vec![0u8, 1, 2]);
+        assert_eq!(
+            res.get("Content-Type"),
+            Some(&"application/octet-stream".to_owned())
+        );
+        assert_eq!(res.get("Content-Length"), Some(&"3".to_owned()));
+    }
+
+    #[test]
+    fn send_charset() {
+        // An explicit charset should replace utf-8
+        let mut res = Response::new_empty();
+        res.charset("iso-8859-1").send("Hello");
+        assert_eq!(
+            res.get("Content-Type"),
+            Some(&"text/html; charset=iso-8859-1".to_owned())
+        );
+    }
+
+    #[test]
+    fn json_content_type() {
+        // Json has no charset parameter
+        let mut res = Response::new_empty();
+        res.json(vec![1u64, 2]);
+        assert_eq!(
+            res.get("Content-Type"),
+            Some(&"application/json".to_owned())
+        );
+    }
+
+    #[test]
+    fn send_file_content_type() {
+        // Text files should be sent as utf-8
+        let mut res = Response::new_empty();
+        res.send_file("templates/test/file.txt").unwrap().unwrap();
+        assert_eq!(
+            res.get("Content-Type"),
+            Some(&"text/plain; charset=utf-8".to_owned())
+        );
+    }
+
+    #[test]
+    fn send_after_file() {
+        // A generated body shouldn't advertise ranges from a file sent before.
+        let mut res = Response::new_empty();
+        res.send_file("templates/test.html").unwrap().unwrap();
+        assert_eq!(res.get("Accept-Ranges"), Some(&"bytes".to_owned()));
+        res.send("generated");
+        assert_eq!(res.get("Accept-Ranges"), None);
+        res.send_file("templates/test.html").unwrap().unwrap();
+        res.json(vec![1u64]);
+        assert_eq!(res.get("Accept-Ranges"), None);
+    }
+
+    #[crate::test]
+    async fn send_slice() {
+        // Byte slices should be sent as is and keep an explicit type
+        let mut res = Response::new_empty();
+        res.with_type("image/png").send(&[0x89u8, b'P'][..]);
+        assert_eq!(res.get("Content-Type"), Some(&"image/png".to_owned()));
+        let (_, mut body) = res.get_data();
+        let mut bytes = Vec::new();
+        body.read_to_end(&mut bytes).await.unwrap();
+        assert_eq!(bytes, [0x89, b'P']);
+    }
+
+    #[test]
+    fn content_type() {
+        // The mime type should be set with a charset for text types.
+        let mut res = Response::new_empty();
+        res.content_type("application/xml").send("<feed></feed>");
+        assert_eq!(res.get("Content-Type"), Some(&"application/xml".to_owned()));
+        let mut res = Response::new_empty();
+        res.content_type("text/csv").send("a,b");
+        assert_eq!(
+            res.get("Content-Type"),
+            Some(&"text/csv; charset=utf-8".to_owned())
+        );
+    }
+
+    #[crate::test]
+    async fn pipe() {
+        // The upstream body and headers should be copied without the hop-by-hop ones.
+        let mut res = Response::new_empty();
+        let upstream: &'static [u8] = b"upstream body";
+        res.pipe(
+            StatusCode::Created,
+            &[
+                ("Content-Type", "text/plain"),
+                ("Content-Length", "13"),
+                ("Connection", "keep-alive"),
+                ("X-Upstream", "1"),
+            ],
+            upstream,
+        );
+        assert!(!res.is_unsized());
+        assert_eq!(res.get("Connection"), None);
+        let data = data_to_string(res.get_data()).await;
+        assert!(data.starts_with("HTTP/1.1 201 CREATED\r\n"));
+        assert!(data.contains("Content-Type: text/plain\r\n"));
+        assert!(data.contains("Content-Length: 13\r\n"));
+        assert!(data.contains("X-Upstream: 1\r\n"));
+        assert!(data.ends_with("\r\n\r\nupstream body"));
+        let mut res = Response::new_empty();
+        res.pipe(StatusCode::Ok, &[], upstream);
+        assert!(res.is_unsized());
+        assert_eq!(res.get("Content-Length"), None);
+    }
+
+    #[crate::test]
+    async fn pipe_long_upstream() {
+        // An upstream sending more than its length should be cut off at it.
+        let mut res = Response::new_empty();
+        let upstream: &'static [u8] = b"upstream body and more";
+        res.pipe(StatusCode::Ok, &[("Content-Length", "13")], upstream);
+        let data = data_to_string(res.get_data()).await;
+        assert!(data.contains("Content-Length: 13\r\n"));
+        assert!(data.ends_with("\r\n\r\nupstream body"));
+    }
+
+    #[test]
+    fn retry_after() {
+        // Delays should be whole seconds and dates http dates.
+        let mut res = Response::new_empty();
+        res.retry_after(Duration::from_millis(1500));
+        assert_eq!(res.get("Retry-After"), Some(&"2".to_owned()));
+        res.retry_after(Duration::from_secs(120));
+        assert_eq!(res.get("Retry-After"), Some(&"120".to_owned()));
+        let date = std::time::UNIX_EPOCH + Duration::from_secs(1630800000);
+        res.retry_after_date(date);
+        assert_eq!(
+            res.get("Retry-After"),
+            Some(&"Sun, 05 Sep 2021 00:00:00 GMT".to_owned())
+        );
+    }
+
+    #[crate::test]
+    async fn send_json_error() {
+        // Errors should be sent in the json envelope with their status.
+        let mut res = Response::new_empty();
+        res.send_json_error(StatusCode::InternalServerError, "it \"broke\"");
+        assert_eq!(res.status_code, StatusCode::InternalServerError);
+        assert_eq!(
+            res.get("Content-Type"),
+            Some(&"application/json".to_owned())
+        );
+        let (_, mut body) = res.get_data();
+        let mut json = String::new();
+        body.read_to_string(&mut json).await.unwrap();
+        let value = Value::parse(&json).unwrap();
+        let error = value.as_object().unwrap()["error"].as_object().unwrap();
+        assert_eq!(error.len(), 2);
+        assert_eq!(error["code"], Value::Integer(500));
+        assert_eq!(error["message"], Value::String("it \"broke\"".to_owned()));
+    }
+
+    #[test]
+    fn success_byte_range() {
+        // Ranges should be clamped to the length of the body.
+        let parse = |header| ByteRange::parse(header, 10);
+        assert_eq!(parse("bytes=0-3"), Some(ByteRange::Satisfiable(0, 3)));
+        assert_eq!(parse("bytes=4-"), Some(ByteRange::Satisfiable(4, 9)));
+        assert_eq!(parse("bytes=-3"), Some(ByteRange::Satisfiable(7, 9)));
+        assert_eq!(parse("bytes=5-100"), Some(ByteRange::Satisfiable(5, 9)));
+        assert_eq!(parse("bytes=10-"), Some(ByteRange::Unsatisfiable));
+        assert_eq!(parse("bytes=0-1,4-5"), None);
+        assert_eq!(parse("bytes=3-1"), None);
+        assert_eq!(parse("items=0-1"), None);
+    }
+
+    #[crate::test]
+    async fn success_if_range_matching() {
+        // A current validator should get the partial content.
+        let mut res = Response::new_empty();
+        res.send_file("templates/test.html").unwrap();
+        let etag = res.get("ETag").unwrap().clone();
+        res.range("bytes=0-4", Some(&etag));
+        assert_eq!(res.status_code, StatusCode::PartialContent);
+        assert_eq!(res.get("Content-Length"), Some(&"5".to_owned()));
+        let file = std::fs::read("templates/test.html").unwrap();
+        assert_eq!(
+            res.get("Content-Range"),
+            Some(&format!("bytes 0-4/{}", file.len()))
+        );
+        let (_, mut body) = res.get_data();
+        let mut bytes = Vec::new();
+        body.read_to_end(&mut bytes).await.unwrap();
+        assert_eq!(bytes, &file[..5]);
+    }
+
+    #[crate::test]
+    async fn success_if_range_stale() {
+        // A stale validator should get the whole file.
+        let file = std::fs::read("templates/test.html").unwrap();
+        for validator in &["\"stale\"", "Thu, 01 Jan 1970 00:00:00 GMT"] {
+            let mut res = Response::new_empty();
+            res.send_file("templates/test.html").unwrap();
+            res.range("bytes=0-4", Some(validator));
+            assert_eq!(res.status_code, StatusCode::Ok);
+            assert_eq!(res.get("Content-Range"), None);
+            let (_, mut body) = res.get_data();
+            let mut bytes = Vec::new();
+            body.read_to_end(&mut bytes).await.unwrap();
+            assert_eq!(bytes, file);
+        }
+    }
+
+    #[test]
+    fn send_file_directory() {
+        // Sending a directory should leave the response untouched
+        let mut res = Response::new_empty();
+        assert_eq!(res.send_file("templates/test").unwrap(), None);
+        assert!(!res.has_body());
+        assert_eq!(res.get("Content-Type"), None);
+    }
+
+    #[crate::test]
+    async fn response_header_order() {
+        // Headers should be serialized in the order and casing they were set
+        let mut res = Response::new_from_slice(b"");
+        res.set("ETag", "\"v1\"")
+            .set("WWW-Authenticate", "Basic realm=\"octane\"")
+            .set("Cache-Control", "no-cache")
+            .set("etag", "\"v2\"");
+        assert_eq!(
+            data_to_string(res.get_data()).await,
+            "HTTP/1.1 200 OK\r\n\
+            ETag: \"v2\"\r\n\
+            WWW-Authenticate: Basic realm=\"octane\"\r\n\
+            Cache-Control: no-cache\r\n\r\n"
+        );
+    }
+
+    #[test]
+    fn response_headers_case_insensitive() {
+        // Lookups and removals shouldn't depend on the casing
+        let mut headers = ResponseHeaders::new();
+        headers.insert("Content-Type", "text/html");
+        assert_eq!(headers.get("content-type"), Some(&"text/html".to_owned()));
+        assert_eq!(headers.remove("CONTENT-TYPE"), Some("text/html".to_owned()));
+        assert!(headers.is_empty());
+    }
 }
@@ -1,8 +1,8 @@
 use crate::default;
 use crate::error::InvalidPathError;
 use crate::middlewares::Closures;
-use crate::path::{MatchedPath, PathNode};
-use crate::request::{MatchedRequest, Request, RequestMethod};
+use crate::path::{MatchedPath, PathBuf, PathNode};
+use crate::request::{MatchedRequest, Request, RequestLine, RequestMethod};
 use crate::responder::Response;
 use std::collections::HashMap;
 use std::result::Result;
@@ -11,6 +11,12 @@ use std::result::Result;
 pub(crate) type Paths = HashMap<RequestMethod, PathNode<Closures>>;
 /// The Closure type is a type alias for the type
 /// that the routes should return
+///
+/// Closures are synchronous and run on the task serving the
+/// connection, so a closure which blocks, say on a slow upstream call,
+/// holds its connection and a worker thread until it returns. Setting
+/// the `route_timeout` of the config answers such requests with
+/// `504 Gateway Timeout` instead
 pub type Closure = Box<dyn for<'a> Fn(&'a MatchedRequest, &'a mut Response) -> Flow + Send + Sync>;
 // RouterResult is the type which the app.METHOD methods return
 pub(crate) type RouterResult = Result<(), InvalidPathError>;
@@ -48,6 +54,35 @@ impl Flow {
     pub fn should_continue(self) -> bool {
         matches!(self, Self::Next)
     }
+    /// Returns `Flow::Next` if the condition holds and `Flow::Stop`
+    /// otherwise
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use octane::prelude::*;
+    ///
+    /// let mut app = Octane::new();
+    /// app.add(route!(|req, res| {
+    ///     let authorized = req.headers.get("authorization").is_some();
+    ///     if !authorized {
+    ///         res.status(octane::responder::StatusCode::Unauthorized);
+    ///     }
+    ///     Flow::next_if(authorized)
+    /// }));
+    /// ```
+    pub fn next_if(cond: bool) -> Self {
+        if cond {
+            Self::Next
+        } else {
+            Self::Stop
+        }
+    }
+    /// Returns `Flow::Stop` if the condition holds and `Flow::Next`
+    /// otherwise
+    pub fn stop_if(cond: bool) -> Self {
+        Self::next_if(!cond)
+    }
 }
 /// The route trait adds the app.METHOD behaviour
 /// to the router/Octane structures along with some
@@ -81,7 +116,9 @@ pub trait Route {
     /// It runs on the given path and on all types of requests
     fn add_route(&mut self, path: &str, closure: Closure) -> RouterResult;
     /// Part of app.METHOD, runs on when the request is on the
-    /// path given and the request method is HEAD
+    /// path given and the request method is HEAD. Registering
+    /// one is optional, HEAD requests which match no HEAD route
+    /// run the GET routes instead, and their body is never sent
     ///
     /// # Example
     ///
@@ -202,8 +239,40 @@ pub trait Route {
     fn patch(&mut self, path: &str, closure: Closure) -> RouterResult;
     /// add() is like `app.use` in express, it runs on all the
     /// paths and all types of valid methods, the request comes
-    /// on
+    /// on. It runs in order with the other routes like a middleware,
+    /// for a handler which answers whatever they left over see
+    /// [`Octane::any()`](../struct.Octane.html#method.any)
     fn add(&mut self, entity: Closure) -> RouterResult;
+    /// Serves a single file on `GET` (and so `HEAD`) requests for the
+    /// path, with the same content type and caching headers as the
+    /// files of a static directory. The request 404s if the file is
+    /// missing
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use octane::prelude::*;
+    ///
+    /// let mut app = Octane::new();
+    /// app.file("/favicon.ico", "public/favicon.ico");
+    /// app.file("/robots.txt", "public/robots.txt");
+    /// ```
+    fn file<P: Into<std::path::PathBuf>>(&mut self, path: &str, file: P) -> RouterResult
+    where
+        Self: Sized,
+    {
+        let file = file.into();
+        self.get(
+            path,
+            Box::new(move |_, res| match file.to_str() {
+                Some(file) if res.send_file(file).ok().flatten().is_some() => {
+                    res.cache_static();
+                    Flow::Stop
+                }
+                _ => Flow::Next,
+            }),
+        )
+    }
 }
 
 /// The router structure defines the routes and stores them along with
@@ -216,6 +285,9 @@ pub struct Router {
     pub middlewares: Vec<Closures>,
     /// The router paths which are to be executed on requests
     pub paths: Paths,
+    // the routes added with default_route, which run on any method
+    // when no route for the method matched
+    pub(crate) defaults: PathNode<Closures>,
 }
 
 impl Router {
@@ -228,8 +300,75 @@ impl Router {
             route_counter: 0,
             middlewares: Vec::new(),
             paths: HashMap::new(),
+            defaults: PathNode::new(),
         }
     }
+    /// Runs on the path for any method, but only when no route for
+    /// the method of the request matched it. Unlike `add_route`, it
+    /// lets a closure dispatch the methods of a path itself while the
+    /// routes for specific methods take precedence
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use octane::prelude::*;
+    ///
+    /// let mut router = Router::new();
+    /// router.get("/items", route_stop!(|req, res| res.send("items")));
+    /// router.default_route(
+    ///     "/items",
+    ///     route_stop!(|req, res| {
+    ///         res.status(StatusCode::MethodNotAllowed).send("read only");
+    ///     }),
+    /// );
+    /// ```
+    pub fn default_route(&mut self, path: &str, closure: Closure) -> RouterResult {
+        self.defaults.insert(
+            PathBuf::parse(path)?,
+            Closures {
+                closure,
+                index: self.route_counter,
+            },
+        );
+        self.route_counter += 1;
+        Ok(())
+    }
+    /// Adds a route for any method, including the ones which don't
+    /// have a method of their own like `OPTIONS` or WebDAV's `PROPFIND`.
+    /// Method names are case sensitive, and a name which isn't a valid
+    /// method token never matches a request
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use octane::prelude::*;
+    /// use octane::responder::StatusCode;
+    ///
+    /// let mut router = Router::new();
+    /// router.method(
+    ///     "PROPFIND",
+    ///     "/files",
+    ///     route_stop!(|req, res| {
+    ///         res.status(StatusCode::Multistatus)
+    ///             .with_type("application/xml")
+    ///             .send("<multistatus xmlns=\"DAV:\"/>");
+    ///     }),
+    /// );
+    /// ```
+    pub fn method(&mut self, method: &str, path: &str, closure: Closure) -> RouterResult {
+        self.paths
+            .entry(RequestMethod::parse(method))
+            .or_insert_with(PathNode::new)
+            .insert(
+                PathBuf::parse(path)?,
+                Closures {
+                    closure,
+                    index: self.route_counter,
+                },
+            );
+        self.route_counter += 1;
+        Ok(())
+    }
     // append the routes stored in a custom Router to the self Router
     pub(crate) fn append(&mut self, router: Self) {
         let self_count = self.route_counter;
@@ -245,6 +384,11 @@ impl Router {
                 self.paths.insert(methods, updated_paths.collect());
             }
         }
+        self.defaults
+            .extend(router.defaults.into_iter().map(|mut v| {
+                v.data.index += self_count;
+                v
+            }));
 
         self.middlewares
             .extend(router.middlewares.into_iter().map(|mut v| {
@@ -254,67 +398,444 @@ impl Router {
         self.route_counter += other_count;
     }
 
-    // Fetch the closure according to the request path, run that
-    // specific closure.
-    pub(crate) fn run(&self, parsed_request: Request<'_>, mut res: &mut Response) {
-        let req = &parsed_request.request_line;
-
-        let mut matches: Vec<Vec<MatchedPath<Closures>>> = Vec::new();
-        if let Some(functions) = self.paths.get(&req.method) {
-            let mut routes = functions.get(&req.path);
-            routes.sort_by_key(|v| v.index);
-            matches.push(routes);
+    /// Appends the routes of another router like
+    /// [`Octane::with_router()`](../struct.Octane.html#method.with_router)
+    /// does, but fails if both routers have a route for the same method
+    /// and path, in which case neither router is changed. Url variables
+    /// only collide by position, `/:id` and `/:name` are the same path
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use octane::prelude::*;
+    ///
+    /// let mut app_routes = Router::new();
+    /// app_routes.get("/", route_stop!(|req, res| res.send("home")));
+    /// let mut api_routes = Router::new();
+    /// api_routes.get("/", route_stop!(|req, res| res.send("api")));
+    /// assert!(app_routes.try_merge(api_routes).is_err());
+    /// ```
+    pub fn try_merge(&mut self, router: Self) -> RouterResult {
+        let shape = |path: &PathBuf| -> Vec<String> {
+            path.chunks
+                .iter()
+                .map(|chunk| {
+                    if chunk.starts_with(':') {
+                        String::from(":")
+                    } else {
+                        chunk.clone()
+                    }
+                })
+                .collect()
         };
-        // run RequestMethod::All regardless of the request method
-        if let Some(functions) = self.paths.get(&RequestMethod::All) {
-            let mut routes = functions.get(&req.path);
-            routes.sort_by_key(|v| v.index);
-            matches.push(routes);
-        }
-
-        matches.push(
-            self.middlewares
+        let collides = |existing: &PathNode<Closures>, paths: &PathNode<Closures>| {
+            let existing: Vec<_> = existing.iter().map(|v| shape(&v.orig_path)).collect();
+            paths
                 .iter()
-                .map(|c| MatchedPath {
-                    data: c,
-                    #[cfg(feature = "url_variables")]
-                    vars: HashMap::new(),
+                .any(|v| existing.contains(&shape(&v.orig_path)))
+        };
+        for (method, paths) in router.paths.iter() {
+            if let Some(existing) = self.paths.get(method) {
+                if collides(existing, paths) {
+                    return Err(InvalidPathError);
+                }
+            }
+        }
+        if collides(&self.defaults, &router.defaults) {
+            return Err(InvalidPathError);
+        }
+        self.append(router);
+        Ok(())
+    }
+    /// Adds the routes and middlewares which the closure registers on
+    /// a fresh router under the prefix. Middlewares added with `add`
+    /// inside the group only run for requests under the prefix, in the
+    /// same order relative to the routes as they were added. Groups can
+    /// be nested, and url variables in the prefix are passed on to the
+    /// routes
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use octane::prelude::*;
+    ///
+    /// let mut router = Router::new();
+    /// router
+    ///     .group("/api", |api| {
+    ///         api.add(route_next!(|req, res| {
+    ///             res.set("X-Api-Version", "1");
+    ///         }))?;
+    ///         api.get("/users", route_stop!(|req, res| res.send("users")))
+    ///     })
+    ///     .unwrap();
+    /// ```
+    pub fn group<F>(&mut self, prefix: &str, build: F) -> RouterResult
+    where
+        F: FnOnce(&mut Router) -> RouterResult,
+    {
+        let prefix = PathBuf::parse(prefix)?;
+        let mut group = Router::new();
+        build(&mut group)?;
+        let offset = self.route_counter;
+        let prefixed = |node: &mut PathNode<Closures>, paths: PathNode<Closures>| {
+            for mut entry in paths {
+                let mut path = prefix.clone();
+                path.chunks.extend(entry.orig_path.chunks);
+                entry.data.index += offset;
+                node.insert(path, entry.data);
+            }
+        };
+        for (method, paths) in group.paths {
+            prefixed(
+                self.paths.entry(method).or_insert_with(PathNode::new),
+                paths,
+            );
+        }
+        prefixed(&mut self.defaults, group.defaults);
+        for middleware in group.middlewares {
+            let prefix = prefix.clone();
+            let closure = middleware.closure;
+            let scoped: Closure = crate::route!(|req, res| {
+                let path = &req.request_line.path.chunks;
+                let under = path.len() >= prefix.chunks.len()
+                    && prefix.chunks.iter().zip(path).all(|(p, c)| {
+                        p.starts_with(':')
+                            || p == c
+                            || res.case_insensitive_paths && p.eq_ignore_ascii_case(c)
+                    });
+                if under {
+                    closure(req, res)
+                } else {
+                    Flow::Next
+                }
+            });
+            self.middlewares.push(Closures {
+                closure: scoped,
+                index: middleware.index + offset,
+            });
+        }
+        self.route_counter += group.route_counter;
+        Ok(())
+    }
+    /// Returns the method and path of every registered route in the
+    /// order they were added, url variables keep their `:name`. Routes
+    /// added with `add_route` are listed with `RequestMethod::All`. The
+    /// ones added with `default_route`, which have no method, and the
+    /// middlewares added with `add`, which have no path, aren't listed
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use octane::prelude::*;
+    /// use octane::request::RequestMethod;
+    ///
+    /// let mut router = Router::new();
+    /// router.get("/users/:id", route_stop!(|req, res| res.send("user")));
+    /// router.post("/users", route_stop!(|req, res| res.send("created")));
+    /// assert_eq!(
+    ///     router.routes(),
+    ///     vec![
+    ///         (RequestMethod::Get, "/users/:id".to_owned()),
+    ///         (RequestMethod::Post, "/users".to_owned()),
+    ///     ]
+    /// );
+    /// ```
+    pub fn routes(&self) -> Vec<(RequestMethod, String)> {
+        let mut routes: Vec<_> = self
+            .paths
+            .iter()
+            .flat_map(|(method, paths)| {
+                paths.iter().map(move |v| {
+                    let path = format!("/{}", v.orig_path.chunks.join("/"));
+                    (v.data.index, method.clone(), path)
                 })
-                .collect(),
-        );
-
-        let mut indices = vec![0_usize; matches.len()];
-        let total: usize = matches.iter().map(Vec::len).sum();
+            })
+            .collect();
+        routes.sort_by_key(|route| route.0);
+        routes
+            .into_iter()
+            .map(|(_, method, path)| (method, path))
+            .collect()
+    }
+    /// Returns the methods the router has routes for, in the form of
+    /// an `Allow` header. `HEAD` is included when there are `GET`
+    /// routes and `OPTIONS` is always included, routes added with
+    /// `add_route` which run on every method aren't counted
+    ///
+    /// ```
+    /// use octane::prelude::*;
+    /// use octane::request::RequestMethod;
+    ///
+    /// let mut router = Router::new();
+    /// router.get("/users", route_stop!(|req, res| res.send("users")));
+    /// router.delete("/users/:id", route_stop!(|req, res| res.no_content()));
+    /// assert_eq!(
+    ///     router.methods(),
+    ///     vec![
+    ///         RequestMethod::Get,
+    ///         RequestMethod::Head,
+    ///         RequestMethod::Delete,
+    ///         RequestMethod::Options,
+    ///     ]
+    /// );
+    /// ```
+    pub fn methods(&self) -> Vec<RequestMethod> {
+        allow_list(self.paths.keys().cloned())
+    }
+    /// Returns the methods the router has routes for on the path,
+    /// like [`methods()`](#method.methods). The list is empty if no
+    /// route matches the path
+    ///
+    /// ```
+    /// use octane::prelude::*;
+    /// use octane::request::RequestMethod;
+    ///
+    /// let mut router = Router::new();
+    /// router.get("/users", route_stop!(|req, res| res.send("users")));
+    /// router.post("/posts", route_stop!(|req, res| res.send("created")));
+    /// assert_eq!(
+    ///     router.methods_for("/posts"),
+    ///     vec![RequestMethod::Post, RequestMethod::Options]
+    /// );
+    /// assert!(router.methods_for("/comments").is_empty());
+    /// ```
+    pub fn methods_for(&self, path: &str) -> Vec<RequestMethod> {
+        match PathBuf::parse(path) {
+            Ok(path) => self.allowed(&path, false),
+            Err(_) => Vec::new(),
+        }
+    }
+    pub(crate) fn allowed(&self, path: &PathBuf, ignore_case: bool) -> Vec<RequestMethod> {
+        let methods: Vec<RequestMethod> = self
+            .paths
+            .iter()
+            .filter(|(_, paths)| !paths.get_matching(path, ignore_case).is_empty())
+            .map(|(method, _)| method.clone())
+            .collect();
+        if methods.iter().all(is_catch_all) {
+            return Vec::new();
+        }
+        allow_list(methods.into_iter())
+    }
+    // Returns true if some closure could respond to the request, without
+    // running any. Middlewares can respond to any path so they always could
+    pub(crate) fn may_handle(&self, request_line: &RequestLine, ignore_case: bool) -> bool {
+        if !self.middlewares.is_empty() {
+            return true;
+        }
+        let matches = |method: &RequestMethod| {
+            self.paths.get(method).map_or(false, |paths| {
+                !paths
+                    .get_matching(&request_line.path, ignore_case)
+                    .is_empty()
+            })
+        };
+        matches(&request_line.method)
+            || matches(&RequestMethod::All)
+            || !self
+                .defaults
+                .get_matching(&request_line.path, ignore_case)
+                .is_empty()
+            || request_line.method == RequestMethod::Head && matches(&RequestMethod::Get)
+    }
+    // Returns true if there are routes for the method, not counting
+    // the ones which run on all methods
+    pub(crate) fn handles(&self, method: &RequestMethod) -> bool {
+        self.paths.contains_key(method)
+    }
+    /// Lists the closures which would run for a request with the method
+    /// and path, in the order they would run in, without running them.
+    /// Closures run in the order they were added whatever their kind,
+    /// until one stops the flow. The routes of the method are used, or
+    /// the `GET` ones for a `HEAD` request without `HEAD` routes, or the
+    /// default routes when neither matched, along with the routes added
+    /// with `add_route` and the middlewares
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use octane::prelude::*;
+    /// use octane::request::RequestMethod;
+    /// use octane::router::Dispatch;
+    ///
+    /// let mut router = Router::new();
+    /// router.add(route_next!(|req, res| {}));
+    /// router.get("/users", route_stop!(|req, res| res.send("users")));
+    /// router.add_route("/users", route_next!(|req, res| {}));
+    /// assert_eq!(
+    ///     router.explain_dispatch("HEAD", "/users"),
+    ///     vec![
+    ///         Dispatch::Middleware(0),
+    ///         Dispatch::Route(1, RequestMethod::Get, "/users".to_owned()),
+    ///         Dispatch::Route(2, RequestMethod::All, "/users".to_owned()),
+    ///     ]
+    /// );
+    /// ```
+    pub fn explain_dispatch(&self, method: &str, path: &str) -> Vec<Dispatch> {
+        self.explain(method, path, false)
+    }
+    pub(crate) fn explain(&self, method: &str, path: &str, ignore_case: bool) -> Vec<Dispatch> {
+        let path = match PathBuf::parse(path) {
+            Ok(path) => path,
+            Err(_) => return Vec::new(),
+        };
+        let method = RequestMethod::parse(method);
+        self.dispatch(&method, &path, ignore_case)
+            .into_iter()
+            .map(|(method, matched)| match (method, matched.orig_path) {
+                (Some(method), Some(path)) => Dispatch::Route(
+                    matched.index,
+                    method.clone(),
+                    format!("/{}", path.chunks.join("/")),
+                ),
+                (None, Some(path)) => {
+                    Dispatch::Default(matched.index, format!("/{}", path.chunks.join("/")))
+                }
+                _ => Dispatch::Middleware(matched.index),
+            })
+            .collect()
+    }
+    // Collects the closures which run for the request in the order
+    // they run in, along with the method of their routes, which is
+    // None for default routes and middlewares. Static path chunks match in any case when
+    // the case is ignored
+    fn dispatch<'a>(
+        &'a self,
+        method: &RequestMethod,
+        path: &'a PathBuf,
+        ignore_case: bool,
+    ) -> Vec<(Option<&'a RequestMethod>, MatchedPath<'a, Closures>)> {
+        let routes_for = |method: &RequestMethod| {
+            self.paths
+                .get_key_value(method)
+                .map_or_else(Vec::new, |(method, functions)| {
+                    functions
+                        .get_matching(path, ignore_case)
+                        .into_iter()
+                        .map(|matched| (Some(method), matched))
+                        .collect()
+                })
+        };
+        let mut routes = routes_for(method);
+        // HEAD requests are answered by the GET routes unless a HEAD
+        // route matches, the server drops the body before sending it
+        if routes.is_empty() && *method == RequestMethod::Head {
+            routes = routes_for(&RequestMethod::Get);
+        }
+        // default routes only run if no route for the method matched
+        if routes.is_empty() {
+            routes = self
+                .defaults
+                .get_matching(path, ignore_case)
+                .into_iter()
+                .map(|matched| (None, matched))
+                .collect();
+        }
+        // run RequestMethod::All regardless of the request method
+        routes.extend(routes_for(&RequestMethod::All));
+        routes.extend(self.middlewares.iter().map(|c| {
+            let matched = MatchedPath {
+                data: c,
+                #[cfg(feature = "url_variables")]
+                vars: HashMap::new(),
+                orig_path: None,
+            };
+            (None, matched)
+        }));
+        // every closure has its own index, so this is the order
+        // they were added in
+        routes.sort_by_key(|(_, matched)| matched.index);
+        routes
+    }
+    // Fetch the closure according to the request path, run that
+    // specific closure. Returns `Flow::Stop` if one of the closures
+    // stopped the execution, meaning the response is final
+    pub(crate) fn run(
+        &self,
+        parsed_request: &Request<'_>,
+        mut res: &mut Response,
+        ignore_case: bool,
+    ) -> Flow {
+        let req = &parsed_request.request_line;
         #[cfg(feature = "url_variables")]
         let mut matched = MatchedRequest {
-            request: parsed_request.clone(),
+            request: parsed_request,
             vars: HashMap::new(),
         };
         #[cfg(not(feature = "url_variables"))]
         let matched = MatchedRequest {
-            request: parsed_request.clone(),
+            request: parsed_request,
         };
-        for _ in 0..total {
-            let mut minind = 0;
-            let mut minval = usize::MAX;
-            for (n, (v, i)) in matches.iter().zip(indices.iter()).enumerate() {
-                if *i < v.len() && v[*i].index < minval {
-                    minval = v[*i].index;
-                    minind = n;
-                }
-            }
+        for (_, route) in self.dispatch(&req.method, &req.path, ignore_case) {
+            let closure = &route.data.closure;
             #[cfg(feature = "url_variables")]
             {
-                matched.vars = matches[minind][indices[minind]].vars.clone();
+                matched.vars = route.vars;
             }
-            let flow = (matches[minind][indices[minind]].closure)(&matched, &mut res);
-            indices[minind] += 1;
+            let flow = closure(&matched, &mut res);
             if !flow.should_continue() {
-                break;
+                return flow;
             }
         }
+        Flow::Next
+    }
+}
+
+/// A closure which would run for a request, as listed by
+/// [`Router::explain_dispatch()`](struct.Router.html#method.explain_dispatch)
+/// and [`Octane::explain_dispatch()`](../struct.Octane.html#method.explain_dispatch)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Dispatch {
+    /// A middleware added with `add`, with the index it was added at
+    Middleware(usize),
+    /// A route with the index it was added at, its method and its path,
+    /// the methods are the same as in [`routes()`](struct.Router.html#method.routes)
+    Route(usize, RequestMethod, String),
+    /// A route added with `default_route`, with the index it was added
+    /// at and its path
+    Default(usize, String),
+    /// The closure set with [`Octane::any()`](../struct.Octane.html#method.any)
+    Any,
+    /// The closure set with [`Octane::fallback()`](../struct.Octane.html#method.fallback)
+    Fallback,
+}
+
+// Orders the methods for an `Allow` header, the standard ones first
+// and the others by name. GET implies HEAD and OPTIONS is always
+// answered
+fn allow_list<I: Iterator<Item = RequestMethod>>(methods: I) -> Vec<RequestMethod> {
+    const ORDER: [RequestMethod; 9] = [
+        RequestMethod::Get,
+        RequestMethod::Head,
+        RequestMethod::Post,
+        RequestMethod::Put,
+        RequestMethod::Delete,
+        RequestMethod::Patch,
+        RequestMethod::Options,
+        RequestMethod::Trace,
+        RequestMethod::Connect,
+    ];
+    let mut methods: Vec<RequestMethod> = methods.filter(|method| !is_catch_all(method)).collect();
+    if methods.contains(&RequestMethod::Get) {
+        methods.push(RequestMethod::Head);
     }
+    methods.push(RequestMethod::Options);
+    methods.sort_by(|a, b| {
+        let rank = |m: &RequestMethod| ORDER.iter().position(|o| o == m).unwrap_or(ORDER.len());
+        rank(a)
+            .cmp(&rank(b))
+            .then_with(|| a.as_str().cmp(b.as_str()))
+    });
+    methods.dedup();
+    methods
+}
+
+// Whether the routes of the method run on every method, they aren't
+// listed in `Allow` headers
+fn is_catch_all(method: &RequestMethod) -> bool {
+    matches!(method, RequestMethod::All)
 }
+
 /// The route macro makes it easy to pass anonymous
 /// functions to app.METHODs.
 ///
@@ -475,6 +996,18 @@ impl Route for Router {
 mod test {
     use super::*;
     use crate::path::PathBuf;
+    use crate::request::{Headers, RequestLine};
+    use crate::responder::StatusCode;
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    pub fn flow_combinators_test() {
+        // next_if and stop_if should be each other's opposite.
+        assert!(Flow::next_if(true).should_continue());
+        assert!(!Flow::next_if(false).should_continue());
+        assert!(!Flow::stop_if(true).should_continue());
+        assert!(Flow::stop_if(false).should_continue());
+    }
 
     #[test]
     pub fn router_test() {
@@ -493,6 +1026,31 @@ mod test {
         assert_eq!(1, router.middlewares.len());
     }
 
+    #[test]
+    pub fn router_short_circuit_test() {
+        // a middleware stopping the flow should skip every closure after it
+        let mut router = Router::new();
+        router
+            .add(route!(|req, res| {
+                res.status(StatusCode::Unauthorized);
+                Flow::Stop
+            }))
+            .unwrap();
+        router
+            .get("/", route_stop!(|req, res| res.send("protected")))
+            .unwrap();
+        let request = Request::parse(
+            RequestLine::parse("GET / HTTP/1.1").unwrap(),
+            Headers::parse("Host: localhost".to_owned()).unwrap(),
+            b"",
+        )
+        .unwrap();
+        let mut res = Response::new_empty();
+        assert!(!router.run(&request, &mut res, false).should_continue());
+        assert_eq!(res.status_code, StatusCode::Unauthorized);
+        assert!(!res.has_body());
+    }
+
     #[test]
     pub fn router_append_test() {
         let mut first_router = Router::new();
@@ -581,4 +1139,300 @@ mod test {
                 .len()
         );
     }
+
+    #[test]
+    pub fn router_try_merge_test() {
+        // Merging should fail on the same method and path only.
+        let mut first_router = Router::new();
+        first_router
+            .get("/", route!(|req, res| { Flow::Next }))
+            .unwrap();
+        first_router
+            .get("/users/:id", route!(|req, res| { Flow::Next }))
+            .unwrap();
+        let mut second_router = Router::new();
+        second_router
+            .get("/", route!(|req, res| { Flow::Next }))
+            .unwrap();
+        assert_eq!(first_router.try_merge(second_router), Err(InvalidPathError));
+        assert_eq!(
+            2,
+            first_router
+                .paths
+                .get(&RequestMethod::Get)
+                .unwrap()
+                .iter()
+                .count()
+        );
+
+        let mut third_router = Router::new();
+        third_router
+            .get("/users/:name", route!(|req, res| { Flow::Next }))
+            .unwrap();
+        assert!(first_router.try_merge(third_router).is_err());
+
+        let mut fourth_router = Router::new();
+        fourth_router
+            .post("/", route!(|req, res| { Flow::Next }))
+            .unwrap();
+        fourth_router
+            .get("/users", route!(|req, res| { Flow::Next }))
+            .unwrap();
+        assert!(first_router.try_merge(fourth_router).is_ok());
+        assert_eq!(2, first_router.paths.len());
+    }
+
+    #[test]
+    pub fn router_new_method_test() {
+        // A route for a method without any routes yet should be stored.
+        let mut router = Router::new();
+        assert!(!router.handles(&RequestMethod::Patch));
+        router
+            .patch("/", route!(|req, res| { Flow::Next }))
+            .unwrap();
+        assert!(router.handles(&RequestMethod::Patch));
+        assert_eq!(
+            1,
+            router
+                .paths
+                .get(&RequestMethod::Patch)
+                .unwrap()
+                .get(&PathBuf::parse("/").unwrap())
+                .len()
+        );
+        assert_eq!(1, router.route_counter);
+    }
+
+    #[test]
+    pub fn router_group_test() {
+        // Group routes should get the prefix and keep their order.
+        let mut router = Router::new();
+        router.get("/", route!(|req, res| { Flow::Next })).unwrap();
+        router
+            .group("/api", |api| {
+                api.add(route!(|req, res| { Flow::Next }))?;
+                api.get("/users", route!(|req, res| { Flow::Next }))?;
+                api.group("/v2", |v2| {
+                    v2.post("/users", route!(|req, res| { Flow::Next }))
+                })
+            })
+            .unwrap();
+        router
+            .get("/after", route!(|req, res| { Flow::Next }))
+            .unwrap();
+        assert_eq!(
+            router.routes(),
+            vec![
+                (RequestMethod::Get, "/".to_owned()),
+                (RequestMethod::Get, "/api/users".to_owned()),
+                (RequestMethod::Post, "/api/v2/users".to_owned()),
+                (RequestMethod::Get, "/after".to_owned()),
+            ]
+        );
+        assert_eq!(router.middlewares[0].index, 1);
+        assert_eq!(router.route_counter, 5);
+    }
+
+    #[test]
+    pub fn router_routes_test() {
+        // Every route should be listed once, in the order it was added.
+        let mut router = Router::new();
+        router.add(route!(|req, res| { Flow::Next })).unwrap();
+        router.get("/", route!(|req, res| { Flow::Next })).unwrap();
+        router
+            .post("/users", route!(|req, res| { Flow::Next }))
+            .unwrap();
+        router
+            .add_route("/static", route!(|req, res| { Flow::Next }))
+            .unwrap();
+        router
+            .get("/users/:id/posts", route!(|req, res| { Flow::Next }))
+            .unwrap();
+        router
+            .method("PROPFIND", "/files", route!(|req, res| { Flow::Next }))
+            .unwrap();
+        assert_eq!(
+            router.routes(),
+            vec![
+                (RequestMethod::Get, "/".to_owned()),
+                (RequestMethod::Post, "/users".to_owned()),
+                (RequestMethod::All, "/static".to_owned()),
+                (RequestMethod::Get, "/users/:id/posts".to_owned()),
+                (
+                    RequestMethod::Other("PROPFIND".to_owned()),
+                    "/files".to_owned()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    pub fn router_methods_test() {
+        // The methods should be listed for the router and for each path.
+        let mut router = Router::new();
+        router.get("/", route!(|req, res| { Flow::Next })).unwrap();
+        router
+            .method("PROPFIND", "/files", route!(|req, res| { Flow::Next }))
+            .unwrap();
+        router
+            .post("/files", route!(|req, res| { Flow::Next }))
+            .unwrap();
+        router
+            .add_route("/static", route!(|req, res| { Flow::Next }))
+            .unwrap();
+        assert_eq!(
+            router.methods(),
+            vec![
+                RequestMethod::Get,
+                RequestMethod::Head,
+                RequestMethod::Post,
+                RequestMethod::Options,
+                RequestMethod::Other("PROPFIND".to_owned()),
+            ]
+        );
+        assert_eq!(
+            router.methods_for("/files"),
+            vec![
+                RequestMethod::Post,
+                RequestMethod::Options,
+                RequestMethod::Other("PROPFIND".to_owned()),
+            ]
+        );
+        assert!(router.methods_for("/static").is_empty());
+        assert!(router.methods_for("/missing").is_empty());
+    }
+
+    #[test]
+    pub fn router_default_route_test() {
+        // Default routes should only run when no route for the method matched.
+        let mut router = Router::new();
+        router
+            .get("/x", route_stop!(|req, res| res.send("get")))
+            .unwrap();
+        router
+            .default_route("/x", route_stop!(|req, res| res.send("default")))
+            .unwrap();
+        let run = |line| {
+            let request_line = RequestLine::parse(line).unwrap();
+            let headers = Headers::parse("Host: localhost".to_owned()).unwrap();
+            let request = Request::parse(request_line, headers, b"").unwrap();
+            let mut res = Response::new_empty();
+            router.run(&request, &mut res, false);
+            res.get_data().0
+        };
+        assert!(run("GET /x HTTP/1.1").contains("Content-Length: 3"));
+        assert!(run("POST /x HTTP/1.1").contains("Content-Length: 7"));
+        assert!(!run("POST /y HTTP/1.1").contains("Content-Length"));
+        assert!(router.methods_for("/x").contains(&RequestMethod::Get));
+        assert_eq!(
+            router.methods(),
+            vec![
+                RequestMethod::Get,
+                RequestMethod::Head,
+                RequestMethod::Options
+            ]
+        );
+        assert!(router.routes().iter().all(|(_, path)| path == "/x"));
+        assert_eq!(router.routes().len(), 1);
+    }
+
+    // Builds a closure which logs its number when it runs
+    fn logged(log: &Arc<Mutex<Vec<usize>>>, n: usize, flow: Flow) -> Closure {
+        let log = Arc::clone(log);
+        Box::new(move |_, _| {
+            log.lock().unwrap().push(n);
+            flow
+        })
+    }
+
+    #[test]
+    pub fn router_explain_dispatch_test() {
+        // The explanation should list what runs, in the order it runs in.
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let mut router = Router::new();
+        router.add(logged(&log, 0, Flow::Next)).unwrap();
+        router
+            .add_route("/users", logged(&log, 1, Flow::Next))
+            .unwrap();
+        router.get("/users", logged(&log, 2, Flow::Next)).unwrap();
+        router.post("/users", logged(&log, 3, Flow::Next)).unwrap();
+        router.add(logged(&log, 4, Flow::Next)).unwrap();
+        router
+            .default_route("/users", logged(&log, 5, Flow::Next))
+            .unwrap();
+        router.get("/users", logged(&log, 6, Flow::Stop)).unwrap();
+        router.add(logged(&log, 7, Flow::Next)).unwrap();
+        let ran = |line| {
+            log.lock().unwrap().clear();
+            let request_line = RequestLine::parse(line).unwrap();
+            let headers = Headers::parse("Host: localhost".to_owned()).unwrap();
+            let request = Request::parse(request_line, headers, b"").unwrap();
+            router.run(&request, &mut Response::new_empty(), false);
+            log.lock().unwrap().clone()
+        };
+        let users = |n, method| Dispatch::Route(n, method, "/users".to_owned());
+        assert_eq!(
+            router.explain_dispatch("GET", "/users"),
+            vec![
+                Dispatch::Middleware(0),
+                users(1, RequestMethod::All),
+                users(2, RequestMethod::Get),
+                Dispatch::Middleware(4),
+                users(6, RequestMethod::Get),
+                Dispatch::Middleware(7),
+            ]
+        );
+        // the closure which stops hides the ones after it
+        assert_eq!(ran("GET /users HTTP/1.1"), vec![0, 1, 2, 4, 6]);
+        assert_eq!(ran("HEAD /users HTTP/1.1"), vec![0, 1, 2, 4, 6]);
+        assert_eq!(
+            router.explain_dispatch("DELETE", "/users"),
+            vec![
+                Dispatch::Middleware(0),
+                users(1, RequestMethod::All),
+                Dispatch::Middleware(4),
+                Dispatch::Default(5, "/users".to_owned()),
+                Dispatch::Middleware(7),
+            ]
+        );
+        assert_eq!(ran("DELETE /users HTTP/1.1"), vec![0, 1, 4, 5, 7]);
+        assert_eq!(
+            router.explain_dispatch("POST", "/posts"),
+            vec![
+                Dispatch::Middleware(0),
+                Dispatch::Middleware(4),
+                Dispatch::Middleware(7),
+            ]
+        );
+        assert_eq!(ran("POST /posts HTTP/1.1"), vec![0, 4, 7]);
+        assert!(router.explain_dispatch("GET", "/../users").is_empty());
+    }
+
+    #[test]
+    pub fn router_custom_method_test() {
+        // Routes for custom methods should only run for that method.
+        let mut router = Router::new();
+        router
+            .method(
+                "PROPFIND",
+                "/files",
+                route!(|req, res| {
+                    res.send("propfind");
+                    Flow::Stop
+                }),
+            )
+            .unwrap();
+        let run = |line| {
+            let request_line = RequestLine::parse(line).unwrap();
+            let headers = Headers::parse("Host: localhost".to_owned()).unwrap();
+            let request = Request::parse(request_line, headers, b"").unwrap();
+            let mut res = Response::new_empty();
+            router.run(&request, &mut res, false);
+            res.has_body()
+        };
+        assert!(run("PROPFIND /files HTTP/1.1"));
+        assert!(!run("MKCOL /files HTTP/1.1"));
+        assert!(!run("GET /files HTTP/1.1"));
+        assert!(router.handles(&RequestMethod::Other("PROPFIND".to_owned())));
+    }
 }
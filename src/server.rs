@@ -1,23 +1,44 @@
+use crate::chunked::{read_chunked, BodyLimit, ChunkedError};
+#[cfg(feature = "compression")]
+use crate::compression::{decode, supports, DecodeError};
 use crate::config::{Config, OctaneConfig, Ssl};
 use crate::constants::*;
+use crate::cors::CorsConfig;
 use crate::error::Error;
-use crate::http::Http;
+use crate::http::{Http, KeepAliveState};
+use crate::metrics::{Metrics, MetricsSnapshot};
 use crate::middlewares::Closures;
-use crate::request::{parse_without_body, Headers, Request, RequestLine};
-use crate::responder::{BoxReader, Response, StatusCode};
+use crate::path::{PathBuf, PathNode};
+use crate::request::{
+    parse_without_body, AppState, Headers, HttpVersion, MatchedRequest, Request, RequestLine,
+    RequestMethod, RequestTarget, SpooledBody,
+};
+use crate::responder::{delay_seconds, BoxReader, Response, StatusCode};
 use crate::route;
-use crate::router::{Closure, Flow, Route, Router, RouterResult};
+use crate::router::{Closure, Dispatch, Flow, Route, Router, RouterResult};
 use crate::server_builder::ServerBuilder;
+#[cfg(feature = "cookies")]
+use crate::session::{SessionStore, Sessions};
+use crate::shutdown::{DrainState, ShutdownHandle};
 use crate::tls::AsMutStream;
+#[cfg(feature = "tracing")]
+use crate::trace;
 use crate::util::find_in_slice;
-use crate::{declare_error, default, route_next};
+use crate::{declare_error, default, route_next, route_stop};
+use std::collections::HashMap;
 use std::error::Error as StdError;
 use std::marker::Unpin;
+use std::net::SocketAddr;
 use std::str;
 use std::sync::Arc;
-use std::time::Duration;
-use tokio::io::{copy, split, AsyncWriteExt};
+use std::time::{Duration, Instant};
+use tokio::fs::File;
+use tokio::io::{copy, split, AsyncWriteExt, BufWriter};
 use tokio::prelude::*;
+use tokio::task::spawn_blocking;
+use tokio::time::timeout;
+#[cfg(feature = "tracing")]
+use tracing::Instrument;
 
 /// The Octane server
 ///
@@ -48,16 +69,101 @@ pub struct Octane {
     /// Some preferences which decides upon how the web server runs
     pub settings: OctaneConfig,
     router: Router,
+    hosts: HashMap<String, Router>,
+    metrics: Arc<Metrics>,
+    drain: Arc<DrainState>,
+    fallback: Option<Closure>,
+    any: Option<Closure>,
+    // runs before the routes, set by listen_tls_and_plain
+    https_redirect: Option<Closure>,
+    state: AppState,
+    body_limits: PathNode<Option<usize>>,
+    error_sink: Option<ErrorSink>,
+    rewrites: Vec<Rewrite>,
+    #[cfg(feature = "cookies")]
+    sessions: Option<Sessions>,
 }
 
+// Receives the errors which happen outside of any request
+type ErrorSink = Box<dyn Fn(&dyn StdError) + Send + Sync>;
+
+// Changes the request line before the request is routed
+type Rewrite = Box<dyn Fn(&mut RequestLine, &Headers) + Send + Sync>;
+
 impl Octane {
     /// Creates a new server instance with empty config and empty router
     pub fn new() -> Self {
         Octane {
             settings: OctaneConfig::new(),
             router: Router::new(),
+            hosts: HashMap::new(),
+            metrics: Arc::new(Metrics::new()),
+            drain: Arc::new(DrainState::default()),
+            fallback: None,
+            any: None,
+            https_redirect: None,
+            state: AppState::default(),
+            body_limits: PathNode::new(),
+            error_sink: None,
+            rewrites: Vec::new(),
+            #[cfg(feature = "cookies")]
+            sessions: None,
         }
     }
+    /// Creates a new server instance holding some application state,
+    /// like a database pool, which every handler can get with
+    /// [`req.state()`](request/struct.Request.html#method.state).
+    /// The state is shared between all the requests so it needs to
+    /// handle its own synchronization
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use octane::prelude::*;
+    /// use std::sync::Mutex;
+    ///
+    /// struct Db {
+    ///     users: Mutex<Vec<String>>,
+    /// }
+    ///
+    /// let mut app = Octane::with_state(Db { users: Mutex::new(Vec::new()) });
+    /// app.post("/users", route_stop!(|req, res| {
+    ///     let db = req.state::<Db>().unwrap();
+    ///     db.users.lock().unwrap().push(String::from_utf8_lossy(req.body).into());
+    ///     res.send("created");
+    /// }));
+    /// ```
+    pub fn with_state<T: Send + Sync + 'static>(state: T) -> Self {
+        let mut app = Self::new();
+        app.state = AppState(Some(Arc::new(state)));
+        app
+    }
+    /// Creates a new server instance with an empty router which
+    /// uses the given config as is, unlike
+    /// [`with_config()`](#method.with_config) which merges it into
+    /// the current settings
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use octane::config::OctaneConfig;
+    /// use octane::prelude::*;
+    ///
+    /// let mut config = OctaneConfig::new();
+    /// config.keep_alive = None;
+    /// config.ssl.key("key.pem").cert("cert.pem");
+    /// let app = Octane::from_config(config);
+    /// assert_eq!(app.settings.keep_alive, None);
+    /// ```
+    ///
+    /// **Note**: Static directories aren't part of the config, they
+    /// still have to be mounted with
+    /// `app.add(Octane::static_dir("dir"))`
+    pub fn from_config(config: OctaneConfig) -> Self {
+        let mut app = Self::new();
+        app.settings = config;
+        app
+    }
     /// Appends the config of the Octane struct with a custom
     /// generated one. The Octane struct contains an OctaneConfig
     /// instance by default
@@ -74,10 +180,25 @@ impl Octane {
     /// app.with_config(config);
     /// ```
     ///
-    /// **Note**: While it replaces properties that must be unique
-    /// i.e which can only have one value at a time, so for
-    /// static_dirs, it appends the locations defined in config
-    /// with the settings that Octane struct already has
+    /// A field of the appended config only replaces the current
+    /// value if it was changed from its default, so appending a
+    /// config which only sets up ssl keeps the keep alive, body
+    /// size and other settings made before. In particular
+    ///
+    /// - the ssl key and cert are replaced when they aren't empty,
+    ///   the ssl port when it isn't 443
+    /// - `keep_alive`, `tcp_nodelay`, `max_body_size`,
+    ///   `max_request_line`, `max_header_size`, `max_headers` and
+    ///   `listen_backlog` are replaced when they differ from their
+    ///   defaults, so a config can disable keep alive or the size
+    ///   limits with `None`
+    /// - `spool_threshold` and the worker threads are replaced when
+    ///   they are set, and `metrics` can only be turned on
+    ///
+    /// **Note**: Static directories aren't part of the config, they
+    /// are mounted as middlewares with
+    /// `app.add(Octane::static_dir("dir"))`, see
+    /// [`static_dir()`](#method.static_dir)
     pub fn with_config(&mut self, config: OctaneConfig) {
         self.settings.append(config);
     }
@@ -104,8 +225,243 @@ impl Octane {
     pub fn with_router(&mut self, router: Router) {
         self.router.append(router);
     }
+    /// Adds a group of routes under a prefix, middlewares added inside
+    /// the group only run for requests under the prefix. See
+    /// [`Router::group()`](router/struct.Router.html#method.group)
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use octane::prelude::*;
+    ///
+    /// let mut app = Octane::new();
+    /// app.group("/admin", |admin| {
+    ///     admin.add(route!(|req, res| {
+    ///         if req.headers.get("authorization").is_some() {
+    ///             Flow::Next
+    ///         } else {
+    ///             res.status(StatusCode::Unauthorized).send("");
+    ///             Flow::Stop
+    ///         }
+    ///     }))?;
+    ///     admin.get("/stats", route_stop!(|req, res| res.send("stats")))
+    /// })
+    /// .unwrap();
+    /// ```
+    pub fn group<F>(&mut self, prefix: &str, build: F) -> RouterResult
+    where
+        F: FnOnce(&mut Router) -> RouterResult,
+    {
+        self.router.group(prefix, build)
+    }
+    /// Overrides `settings.max_body_size` for requests on the path, the
+    /// limit is checked before the body is read. Paths can have url
+    /// variables, a path without them wins over one with them and `None`
+    /// lifts the limit. Setting a limit for the same path again replaces it
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use octane::prelude::*;
+    ///
+    /// let mut app = Octane::new();
+    /// app.settings.max_body_size = Some(64 * 1024);
+    /// // uploads can be larger than the api requests
+    /// app.body_limit("/upload", Some(100 * 1024 * 1024)).unwrap();
+    /// app.body_limit("/users/:id/avatar", Some(1024 * 1024)).unwrap();
+    /// ```
+    pub fn body_limit(&mut self, path: &str, limit: Option<usize>) -> RouterResult {
+        let path = PathBuf::parse(path)?;
+        // replace the previous limit of the path instead of stacking
+        let mut limits = PathNode::new();
+        for entry in std::mem::replace(&mut self.body_limits, PathNode::new()) {
+            if entry.orig_path != path {
+                limits.insert(entry.orig_path, entry.data);
+            }
+        }
+        limits.insert(path, limit);
+        self.body_limits = limits;
+        Ok(())
+    }
+    // Returns the body size limit for a request path
+    fn body_limit_for(&self, path: &PathBuf) -> Option<usize> {
+        self.body_limits
+            .get(path)
+            .first()
+            .map_or(self.settings.max_body_size, |limit| *limit.data)
+    }
+    /// Returns the method and path of every route registered on the
+    /// app, see [`Router::routes()`](router/struct.Router.html#method.routes).
+    /// Routes of the virtual hosts aren't included
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use octane::prelude::*;
+    ///
+    /// let mut app = Octane::new();
+    /// app.get("/", route_stop!(|req, res| res.send("home")));
+    /// for (method, path) in app.routes() {
+    ///     println!("{:?} {}", method, path);
+    /// }
+    /// ```
+    pub fn routes(&self) -> Vec<(RequestMethod, String)> {
+        self.router.routes()
+    }
+    /// Runs on the path for any method, but only when no route for
+    /// the method of the request matched it, see
+    /// [`Router::default_route()`](router/struct.Router.html#method.default_route)
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use octane::prelude::*;
+    ///
+    /// let mut app = Octane::new();
+    /// app.get("/items", route_stop!(|req, res| res.send("items")));
+    /// app.default_route(
+    ///     "/items",
+    ///     route_stop!(|req, res| {
+    ///         res.status(StatusCode::MethodNotAllowed).send("read only");
+    ///     }),
+    /// );
+    /// ```
+    pub fn default_route(&mut self, path: &str, closure: Closure) -> RouterResult {
+        self.router.default_route(path, closure)
+    }
+    /// Adds a route for any method, including the ones which don't
+    /// have a method of their own, see
+    /// [`Router::method()`](router/struct.Router.html#method.method)
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use octane::prelude::*;
+    /// use octane::responder::StatusCode;
+    ///
+    /// let mut app = Octane::new();
+    /// app.method(
+    ///     "PROPFIND",
+    ///     "/files",
+    ///     route_stop!(|req, res| {
+    ///         res.status(StatusCode::Multistatus)
+    ///             .with_type("application/xml")
+    ///             .send("<multistatus xmlns=\"DAV:\"/>");
+    ///     }),
+    /// );
+    /// ```
+    pub fn method(&mut self, method: &str, path: &str, closure: Closure) -> RouterResult {
+        self.router.method(method, path, closure)
+    }
+    /// Lists the closures which would run for a request with the method
+    /// and path, in order, see
+    /// [`Router::explain_dispatch()`](router/struct.Router.html#method.explain_dispatch).
+    /// The closure set with [`any()`](#method.any), or else the
+    /// [`fallback`](#method.fallback), comes last as it runs when the
+    /// others didn't respond. Routes of the virtual hosts aren't included
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use octane::prelude::*;
+    /// use octane::router::Dispatch;
+    ///
+    /// let mut app = Octane::new();
+    /// app.add(route_next!(|req, res| {}));
+    /// app.fallback(route_stop!(|req, res| res.send("index")));
+    /// assert_eq!(
+    ///     app.explain_dispatch("GET", "/missing"),
+    ///     vec![Dispatch::Middleware(0), Dispatch::Fallback]
+    /// );
+    /// ```
+    pub fn explain_dispatch(&self, method: &str, path: &str) -> Vec<Dispatch> {
+        let ignore_case = self.settings.case_insensitive_paths;
+        let mut dispatch = self.router.explain(method, path, ignore_case);
+        if self.any.is_some() {
+            dispatch.push(Dispatch::Any);
+        } else if self.fallback.is_some() {
+            dispatch.push(Dispatch::Fallback);
+        }
+        dispatch
+    }
+    /// Registers a router for a virtual host, requests whose `Host`
+    /// header (or absolute url) names the host are handled by it
+    /// instead of the routes on the app. Host names are matched
+    /// case-insensitively and without the port, registering the same
+    /// host again appends the routes
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use octane::prelude::*;
+    ///
+    /// let mut app = Octane::new();
+    /// let mut api = Router::new();
+    /// api.get("/", route_stop!(|req, res| res.send("api")));
+    /// app.host("api.example.com", api);
+    /// // every other host uses the routes on the app
+    /// app.get("/", route_stop!(|req, res| res.send("website")));
+    /// ```
+    pub fn host(&mut self, host: &str, router: Router) {
+        self.hosts
+            .entry(host.to_ascii_lowercase())
+            .or_insert_with(Router::new)
+            .append(router);
+    }
+    // Runs the closures for the request, the https redirect first, then
+    // the routes and what is left over falls to the catch all or the
+    // fallback. Returns the flow of the last closure which ran
+    fn route(&self, request: &Request, res: &mut Response) -> Flow {
+        let router = self.router_for(&request.request_line, &request.headers);
+        let mut flow = match &self.https_redirect {
+            Some(redirect) => {
+                let matched = MatchedRequest {
+                    request,
+                    #[cfg(feature = "url_variables")]
+                    vars: HashMap::new(),
+                };
+                redirect(&matched, res)
+            }
+            None => Flow::Next,
+        };
+        if flow.should_continue() {
+            flow = router.run(request, res, self.settings.case_insensitive_paths);
+        }
+        if flow.should_continue() && !res.has_body() {
+            let matched = MatchedRequest {
+                request,
+                #[cfg(feature = "url_variables")]
+                vars: HashMap::new(),
+            };
+            if let Some(any) = &self.any {
+                // the catch all always produces the final response
+                any(&matched, res);
+                flow = Flow::Stop;
+            } else if let Some(fallback) = &self.fallback {
+                flow = fallback(&matched, res);
+            }
+        }
+        flow
+    }
+    // Returns the router for the host the request was made to
+    fn router_for(&self, request_line: &RequestLine, headers: &Headers) -> &Router {
+        if self.hosts.is_empty() {
+            return &self.router;
+        }
+        let host = match &request_line.target {
+            RequestTarget::Absolute(authority) => Some(authority.as_str()),
+            _ => headers.get("host").map(String::as_str),
+        };
+        host.map(strip_port)
+            .and_then(|host| self.hosts.get(&host.to_ascii_lowercase()))
+            .unwrap_or(&self.router)
+    }
     /// Returns a closure which can be used with the add or add_route method
-    /// to serve a static directory.
+    /// to serve a static directory. The request path is looked up
+    /// inside the directory and the file is sent if it exists, otherwise
+    /// the request moves on to the next closure. Nothing is served from
+    /// disk unless a static directory is added, so apps serving their
+    /// assets elsewhere get their own fallback or the 404 page.
     ///
     /// # Example
     ///
@@ -118,22 +474,359 @@ impl Octane {
     ///    "/templates"
     /// )));
     /// ```
-    pub fn static_dir(dir: &'static str) -> Closure {
+    ///
+    /// Several directories can be added, they are tried in the order
+    /// they were added and the first one holding the file serves it.
+    /// The request only 404s if none of them has the file and no later
+    /// route or [`fallback()`](#method.fallback) answers it
+    ///
+    /// ```
+    /// use octane::prelude::*;
+    ///
+    /// let mut app = Octane::new();
+    /// app.add(Octane::static_dir("public"));
+    /// app.add(Octane::static_dir("assets"));
+    /// ```
+    ///
+    /// The directory doesn't have to be known at compile time
+    ///
+    /// ```
+    /// use octane::prelude::*;
+    ///
+    /// let mut app = Octane::new();
+    /// let dir = std::env::current_dir().unwrap().join("templates");
+    /// app.add(Octane::static_dir(dir));
+    /// ```
+    pub fn static_dir<P: Into<std::path::PathBuf>>(dir: P) -> Closure {
+        let dir = dir.into();
         route_next!(|req, res| {
-            let static_dir_name = std::path::PathBuf::from(dir);
-            let final_url = static_dir_name.join(req.request_line.path.to_std_pathbuf());
-            let final_string = final_url.to_str().unwrap();
-            if &final_string[final_string.len() - 1..] == "/" {
-                let stripped = &final_string[..final_string.len() - 1];
-                res.send_file(stripped).ok();
-            } else {
-                res.send_file(final_string).ok();
+            // don't clobber a response some earlier closure produced
+            if res.has_body() {
+                return Flow::Next;
+            }
+            let final_url = dir.join(req.request_line.path.to_std_pathbuf());
+            if let Some(final_string) = final_url.to_str() {
+                let stripped = final_string.strip_suffix('/').unwrap_or(final_string);
+                if let Ok(Some(())) = res.send_file(stripped) {
+                    res.cache_static();
+                } else if res.autoindex
+                    && final_url.is_dir()
+                    && !final_url.join("index.html").exists()
+                {
+                    let json = req.preferred(&["text/html", "application/json"])
+                        == Some("application/json");
+                    res.send_listing(final_url.clone(), &req.path(), json);
+                }
+            }
+        })
+    }
+    /// Returns a closure which redirects requests that didn't arrive
+    /// over https to the same host and path on the https port, see
+    /// [`Request::is_secure()`](request/struct.Request.html#method.is_secure).
+    /// `GET` and `HEAD` requests get a `301 Moved Permanently` while other
+    /// methods get a `308 Permanent Redirect` so that clients repeat the
+    /// method and body. Requests for paths starting with one of the
+    /// exempt paths are passed through
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use octane::prelude::*;
+    ///
+    /// let mut app = Octane::new();
+    /// // let the certificate authority reach the challenge over http
+    /// let redirect = app.force_https(&["/.well-known/acme-challenge"]);
+    /// app.add(redirect);
+    /// ```
+    pub fn force_https(&self, exempt: &[&str]) -> Closure {
+        let port = self.settings.ssl.port;
+        let exempt: Vec<PathBuf> = exempt
+            .iter()
+            .filter_map(|path| PathBuf::parse(path).ok())
+            .collect();
+        route!(|req, res| {
+            let path = &req.request_line.path.chunks;
+            if req.is_secure() || exempt.iter().any(|e| path.starts_with(&e.chunks)) {
+                return Flow::Next;
+            }
+            let host = match &req.request_line.target {
+                RequestTarget::Absolute(authority) => Some(authority.as_str()),
+                _ => req.headers.get("host").map(String::as_str),
+            };
+            let host = match host {
+                Some(host) => strip_port(host),
+                // there is nowhere to redirect to
+                None => return Flow::Next,
+            };
+            let mut location = format!("https://{}", host);
+            if port != 443 {
+                location.push_str(&format!(":{}", port));
+            }
+            location.push('/');
+            location.push_str(&path.join("/"));
+            if !req.request_line.query.is_empty() {
+                location.push('?');
+                location.push_str(&req.request_line.query);
+            }
+            let status = match req.request_line.method {
+                RequestMethod::Get | RequestMethod::Head => StatusCode::MovedPermanently,
+                _ => StatusCode::PermanentRedirect,
             };
+            res.status(status).set("Location", &location).send("");
+            Flow::Stop
+        })
+    }
+    /// Sets a closure which runs when no route or static file produced
+    /// a response, instead of sending the built-in 404 page. Setting
+    /// it again replaces the previous one
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use octane::prelude::*;
+    ///
+    /// let mut app = Octane::new();
+    /// app.add(Octane::static_dir("dist"));
+    /// // serve the index for every other path, for client side routing
+    /// app.fallback(route_stop!(|req, res| {
+    ///     res.send_file("dist/index.html").ok();
+    /// }));
+    /// ```
+    ///
+    /// If the fallback doesn't produce a body either, the 404 page is sent
+    pub fn fallback(&mut self, closure: Closure) {
+        self.fallback = Some(closure);
+    }
+    /// Sets a closure which handles every request the routes didn't
+    /// finish, whatever its path and method, even methods no route was
+    /// registered for which would otherwise get `501 Not Implemented`.
+    /// Unlike [`add()`](router/trait.Route.html#tymethod.add), which
+    /// registers a middleware running in order with the other routes,
+    /// it runs last and whatever response it produced is sent, so the
+    /// [`fallback`](#method.fallback) and the 404 page are never reached.
+    /// Setting it again replaces the previous one
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use octane::prelude::*;
+    ///
+    /// let mut app = Octane::new();
+    /// app.get("/health", route_stop!(|req, res| res.send("ok")));
+    /// // forward everything else
+    /// app.any(route_stop!(|req, res| {
+    ///     res.status(StatusCode::BadGateway)
+    ///         .send(format!("no upstream for {:?}", req.request_line.method));
+    /// }));
+    /// ```
+    pub fn any(&mut self, closure: Closure) {
+        self.any = Some(closure);
+    }
+    /// Sets a closure which receives the errors the server can't send
+    /// back to a client, like failing to accept a connection, a failed
+    /// tls handshake or a connection which broke while being served.
+    /// They are printed to stderr if no closure is set, setting it
+    /// again replaces the previous one
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use octane::prelude::*;
+    ///
+    /// let mut app = Octane::new();
+    /// app.on_error(|err| eprintln!("connection error: {}", err));
+    /// ```
+    pub fn on_error<F>(&mut self, sink: F)
+    where
+        F: Fn(&dyn StdError) + Send + Sync + 'static,
+    {
+        self.error_sink = Some(Box::new(sink));
+    }
+    /// Adds a closure which can change the request line, like its
+    /// path, before the request is routed. Unlike middlewares it runs
+    /// before any route is matched, so the routes, the body limits
+    /// and the virtual hosts all see the rewritten path. Rewrites run
+    /// in the order they were added
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use octane::prelude::*;
+    ///
+    /// let mut app = Octane::new();
+    /// // serve `/v1/users` with the `/users` route
+    /// app.rewrite(|line, _headers| {
+    ///     if line.path.chunks.first().map(String::as_str) == Some("v1") {
+    ///         line.path.chunks.remove(0);
+    ///     }
+    /// });
+    /// ```
+    pub fn rewrite<F>(&mut self, rewrite: F)
+    where
+        F: Fn(&mut RequestLine, &Headers) + Send + Sync + 'static,
+    {
+        self.rewrites.push(Box::new(rewrite));
+    }
+    /// Enables sessions, kept in the store and tracked with a cookie
+    /// of the given name. Closures get the session of the request with
+    /// [`req.session()`](request/struct.Request.html#method.session),
+    /// see [`Session`](session/struct.Session.html). This requires the
+    /// `cookies` feature
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use octane::prelude::*;
+    /// use octane::session::MemoryStore;
+    ///
+    /// let mut app = Octane::new();
+    /// app.with_sessions("sid", MemoryStore::new());
+    /// app.post("/login", route_stop!(|req, res| {
+    ///     req.session().unwrap().set("user", "alice");
+    ///     res.send("logged in");
+    /// }));
+    /// ```
+    #[cfg(feature = "cookies")]
+    pub fn with_sessions<S: SessionStore + 'static>(&mut self, cookie: &str, store: S) {
+        self.sessions = Some(Sessions {
+            cookie: cookie.to_owned(),
+            store: Box::new(store),
+        });
+    }
+    pub(crate) fn report(&self, error: &dyn StdError) {
+        match &self.error_sink {
+            Some(sink) => sink(error),
+            None => eprintln!("WARNING: {}", error),
+        }
+    }
+    /// Returns a handle which can put the server in the draining
+    /// state, see [`ShutdownHandle`](shutdown/struct.ShutdownHandle.html)
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use octane::prelude::*;
+    ///
+    /// let app = Octane::new();
+    /// let handle = app.shutdown_handle();
+    /// handle.shutdown();
+    /// assert!(handle.is_shutting_down());
+    /// ```
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        ShutdownHandle(Arc::clone(&self.drain))
+    }
+    /// Returns a copy of the current metrics, the counters are only
+    /// updated when `settings.metrics` is enabled
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use octane::prelude::*;
+    ///
+    /// let mut app = Octane::new();
+    /// app.settings.metrics = true;
+    /// let snapshot = app.metrics_snapshot();
+    /// println!("{} requests served", snapshot.requests);
+    /// ```
+    pub fn metrics_snapshot(&self) -> MetricsSnapshot {
+        self.metrics.snapshot()
+    }
+    /// Returns a closure which responds with the metrics in the
+    /// Prometheus text format, to be mounted on a route of your
+    /// choice. Requires `settings.metrics` to be enabled
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use octane::prelude::*;
+    ///
+    /// let mut app = Octane::new();
+    /// app.settings.metrics = true;
+    /// let handler = app.metrics_handler();
+    /// app.get("/metrics", handler);
+    /// ```
+    pub fn metrics_handler(&self) -> Closure {
+        let metrics = Arc::clone(&self.metrics);
+        route_stop!(|req, res| {
+            res.with_type("text/plain; version=0.0.4")
+                .send(metrics.snapshot().to_prometheus());
         })
     }
+    /// Returns a closure which responds to `GET` and `HEAD` requests
+    /// for the path with the metrics, like
+    /// [`metrics_handler()`](#method.metrics_handler) but to be added
+    /// with [`add()`](#method.add) instead of being mounted on a route
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use octane::prelude::*;
+    ///
+    /// let mut app = Octane::new();
+    /// app.settings.metrics = true;
+    /// let metrics = app.metrics_endpoint("/metrics");
+    /// app.add(metrics);
+    /// ```
+    pub fn metrics_endpoint(&self, path: &str) -> Closure {
+        endpoint(path, self.metrics_handler())
+    }
+    /// Returns a closure which sets the CORS headers on requests from
+    /// the allowed origins and answers their preflight requests with a
+    /// `204 No Content`. The preflight responses carry
+    /// `Access-Control-Max-Age` when it is set in the settings so that
+    /// browsers cache them
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use octane::cors::CorsConfig;
+    /// use octane::prelude::*;
+    /// use std::time::Duration;
+    ///
+    /// let mut app = Octane::new();
+    /// let mut cors = CorsConfig::new();
+    /// cors.origin("https://example.com")
+    ///     .max_age(Duration::from_secs(600));
+    /// app.add(Octane::cors(cors));
+    /// ```
+    pub fn cors(settings: CorsConfig) -> Closure {
+        route!(|req, res| settings.apply(req, res))
+    }
+    /// Returns a closure which answers `GET` and `HEAD` requests for
+    /// the path with a `200 OK` and a `{"status":"ok"}` json body, for
+    /// load balancers and container orchestrators to probe. Other
+    /// requests are passed through
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use octane::prelude::*;
+    ///
+    /// let mut app = Octane::new();
+    /// app.add(Octane::health("/healthz"));
+    /// ```
+    pub fn health(path: &str) -> Closure {
+        endpoint(
+            path,
+            route_stop!(|req, res| {
+                res.with_type("application/json").send(r#"{"status":"ok"}"#);
+            }),
+        )
+    }
+    // Returns the metrics if they are enabled
+    pub(crate) fn metrics(&self) -> Option<&Metrics> {
+        if self.settings.metrics {
+            Some(&self.metrics)
+        } else {
+            None
+        }
+    }
     /// Start listening on the port specified, the listen
     /// function also starts the Ssl server if the features
-    /// are enabled and the key/certs are provided
+    /// are enabled and the key/certs are provided. The callback
+    /// runs once the port is bound, use
+    /// [`listen_addr()`](#method.listen_addr) to know which
+    /// address was bound
     ///
     /// # Example
     ///
@@ -153,17 +846,58 @@ impl Octane {
     where
         F: FnOnce(),
     {
-        let server = Arc::new(self);
-        let mut _ssl = false;
-
-        #[cfg(any(feature = "openSSL", feature = "rustls"))]
-        {
-            use crate::task;
-            let clone = Arc::clone(&server);
-            _ssl = true;
-
+        self.listen_addr(port, |_| exec()).await
+    }
+    /// Works like [`listen()`](#method.listen) without a callback
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use octane::Octane;
+    ///
+    /// #[octane::main]
+    /// async fn main() {
+    ///     let app = Octane::new();
+    ///     app.start(8080).await.expect("Cannot establish connection");
+    /// }
+    /// ```
+    pub async fn start(self, port: u16) -> Result<(), Box<dyn StdError>> {
+        self.listen_addr(port, |_| {}).await
+    }
+    /// Works like [`listen()`](#method.listen) but the callback
+    /// gets the address which was bound. The server listens on all
+    /// interfaces so the ip is always `0.0.0.0`, the port is useful
+    /// when binding to port 0 to let the OS pick a free one
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use octane::Octane;
+    ///
+    /// #[octane::main]
+    /// async fn main() {
+    ///     let app = Octane::new();
+    ///     app.listen_addr(0, |addr| println!("Server started on port {}", addr.port()))
+    ///         .await
+    ///         .expect("Cannot establish connection");
+    /// }
+    /// ```
+    pub async fn listen_addr<F>(self, port: u16, exec: F) -> Result<(), Box<dyn StdError>>
+    where
+        F: FnOnce(SocketAddr),
+    {
+        let server = Arc::new(self);
+        let mut _ssl = false;
+
+        #[cfg(any(feature = "openSSL", feature = "rustls"))]
+        {
+            use crate::task;
+            let clone = Arc::clone(&server);
+            _ssl = true;
+
             async fn listen_ssl(server: Arc<Octane>) -> Result<(), Box<dyn StdError>> {
-                let server_builder = ServerBuilder::new(server.settings.ssl.port);
+                let server_builder =
+                    ServerBuilder::new(server.settings.ssl.port, server.settings.listen_backlog);
                 server_builder?
                     .listen_ssl(
                         |stream, server| async { Octane::serve(stream, server).await },
@@ -174,14 +908,86 @@ impl Octane {
             }
 
             task!({
-                if let Err(x) = listen_ssl(clone).await {
-                    println!("WARNING: {}", x);
+                if let Err(x) = listen_ssl(Arc::clone(&clone)).await {
+                    clone.report(&*x);
                 }
             });
         }
-        exec();
-        let server_builder = ServerBuilder::new(port);
-        server_builder?
+        let server_builder = ServerBuilder::new(port, server.settings.listen_backlog)?;
+        exec(server_builder.local_addr()?);
+        server_builder
+            .listen(
+                move |stream, server| async move { Octane::serve(stream, server).await },
+                server,
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    /// Serves the app over plain http on `port` and over https on
+    /// `tls_port` at the same time, with the key and certificate of
+    /// the ssl settings. When `redirect` is true the plain listener
+    /// redirects every request to https, like
+    /// [`force_https()`](#method.force_https), instead of serving it.
+    /// The callback gets the plain and the tls address once both ports
+    /// are bound, which is useful when binding to port 0
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use octane::prelude::*;
+    ///
+    /// #[octane::main]
+    /// async fn main() {
+    ///     let mut app = Octane::new();
+    ///     app.ssl(443)
+    ///         .key("templates/key.pem")
+    ///         .cert("templates/cert.pem");
+    ///     app.listen_tls_and_plain(80, 443, true, |plain, tls| {
+    ///         println!("Serving on {} and {}", plain.port(), tls.port())
+    ///     })
+    ///     .await
+    ///     .expect("Cannot establish connection");
+    /// }
+    /// ```
+    #[cfg(any(feature = "openSSL", feature = "rustls"))]
+    pub async fn listen_tls_and_plain<F>(
+        mut self,
+        port: u16,
+        tls_port: u16,
+        redirect: bool,
+        exec: F,
+    ) -> Result<(), Box<dyn StdError>>
+    where
+        F: FnOnce(SocketAddr, SocketAddr),
+    {
+        use crate::task;
+        let backlog = self.settings.listen_backlog;
+        let plain = ServerBuilder::new(port, backlog)?;
+        let tls = ServerBuilder::new(tls_port, backlog)?;
+        let (plain_addr, tls_addr) = (plain.local_addr()?, tls.local_addr()?);
+        // the redirects have to point to the port which was bound
+        self.settings.ssl.port = tls_addr.port();
+        if redirect {
+            self.https_redirect = Some(self.force_https(&[]));
+        }
+        let server = Arc::new(self);
+        let clone = Arc::clone(&server);
+        task!({
+            let served: Result<(), Box<dyn StdError>> = tls
+                .listen_ssl(
+                    |stream, server| async { Octane::serve(stream, server).await },
+                    Arc::clone(&clone),
+                )
+                .await
+                .map_err(Into::into);
+            if let Err(x) = served {
+                clone.report(&*x);
+            }
+        });
+        exec(plain_addr, tls_addr);
+        plain
             .listen(
                 move |stream, server| async move { Octane::serve(stream, server).await },
                 server,
@@ -191,45 +997,404 @@ impl Octane {
         Ok(())
     }
 
-    async fn serve<S>(stream_async: S, server: Arc<Octane>) -> Result<(), Box<dyn StdError>>
+    // Applies the socket level settings to a freshly accepted stream
+    fn configure_stream<S>(stream: &mut S, settings: &OctaneConfig) -> std::io::Result<()>
+    where
+        S: AsMutStream,
+    {
+        stream.set_nodelay(settings.tcp_nodelay)
+    }
+
+    pub(crate) async fn serve<S>(
+        mut stream_async: S,
+        server: Arc<Octane>,
+    ) -> Result<(), Box<dyn StdError>>
     where
         S: AsyncRead + AsyncWrite + Unpin + AsMutStream,
     {
-        let (mut reader, writer) = split(stream_async);
-        let mut data = Vec::<u8>::new();
+        Octane::configure_stream(&mut stream_async, &server.settings)?;
+        let _connection = server.metrics().map(Metrics::connection);
+        let _draining = server.drain.connection();
+        let secure = stream_async.is_secure();
+        let alpn = stream_async.alpn_protocol();
+        let (mut reader, mut writer) = split(stream_async);
+        let mut idle_timeout = None;
+        // bytes of pipelined requests which were read along with the
+        // previous one
+        let mut pending = Vec::new();
+        loop {
+            let served = Octane::serve_request(
+                &mut reader,
+                &mut writer,
+                &server,
+                idle_timeout,
+                secure,
+                alpn.as_deref(),
+                &mut pending,
+            );
+            #[cfg(feature = "tracing")]
+            let served = served.instrument(trace::request_span());
+            match served.await? {
+                Some(timeout) => idle_timeout = Some(timeout),
+                None => return Ok(()),
+            }
+        }
+    }
+
+    // Reads, routes and responds to a single request on the connection.
+    // Returns for how long to wait for the next request if the connection
+    // should be kept alive, or None if it should be closed. Bytes read
+    // past the end of the request are left in pending for the next one
+    async fn serve_request<R, W>(
+        reader: &mut R,
+        writer: &mut W,
+        server: &Arc<Octane>,
+        idle_timeout: Option<Duration>,
+        secure: bool,
+        alpn: Option<&str>,
+        pending: &mut Vec<u8>,
+    ) -> Result<Option<Duration>, Box<dyn StdError>>
+    where
+        R: AsyncRead + Unpin,
+        W: AsyncWrite + Unpin,
+    {
+        let mut data = std::mem::take(pending);
         let mut buf: [u8; BUF_SIZE] = [0; BUF_SIZE];
         let body: &[u8];
-        let request_line: RequestLine;
+        let mut request_line: RequestLine;
         let headers: Headers;
         let body_remainder: &[u8];
+        let mut started = Instant::now();
 
         loop {
-            let read = reader.read(&mut buf).await?;
+            // reject as soon as the line or the headers are too long
+            // instead of buffering them until the client gives up
+            let line_len = find_in_slice(&data[..], b"\r\n").unwrap_or(data.len());
+            if let Some(max) = server.settings.max_request_line {
+                if line_len > max {
+                    declare_error!(writer, StatusCode::UriTooLong, None, server);
+                }
+            }
+            if let Some(max) = server.settings.max_path_segments {
+                if path_segments(&data[..line_len]) > max {
+                    declare_error!(
+                        writer,
+                        StatusCode::UriTooLong,
+                        None,
+                        server,
+                        "The request path has too many segments"
+                    );
+                }
+            }
+            // a request line without a version is an HTTP/0.9 simple
+            // request, there are no headers and no blank line to wait for
+            #[cfg(feature = "faithful")]
+            if let Some(line) = data.get(..line_len).filter(|_| line_len < data.len()) {
+                if let Some(line) = simple_request(line) {
+                    if !server.settings.http09 {
+                        declare_error!(
+                            writer,
+                            StatusCode::BadRequest,
+                            None,
+                            server,
+                            "HTTP/0.9 requests are not supported"
+                        );
+                    }
+                    match RequestLine::parse(&format!("{} HTTP/0.9", line)) {
+                        Some(line) if line.method == RequestMethod::Get => {
+                            request_line = line;
+                            headers = Headers::default();
+                            body_remainder = &data[line_len + 2..];
+                            break;
+                        }
+                        _ => {
+                            declare_error!(
+                                writer,
+                                StatusCode::BadRequest,
+                                None,
+                                server,
+                                "The request line is malformed"
+                            );
+                        }
+                    }
+                }
+            }
+            let head_end = find_in_slice(&data[..], b"\r\n\r\n");
+            if let Some(max) = server.settings.max_header_size {
+                if head_end.unwrap_or(data.len()) - line_len > max {
+                    declare_error!(
+                        writer,
+                        StatusCode::RequestHeaderFieldsTooLarge,
+                        None,
+                        server,
+                        "The request headers are too large"
+                    );
+                }
+            }
+            if let Some(i) = head_end {
+                let first = &data[..i];
+                // every header line is preceded by a CRLF, count them
+                // before parsing allocates anything for them
+                if let Some(max) = server.settings.max_headers {
+                    if first.windows(2).filter(|w| w == b"\r\n").count() > max {
+                        declare_error!(
+                            writer,
+                            StatusCode::RequestHeaderFieldsTooLarge,
+                            None,
+                            server,
+                            "The request has too many headers"
+                        );
+                    }
+                }
+                body_remainder = &data[i + 4..];
+                let head = match str::from_utf8(first) {
+                    Ok(head) => head,
+                    Err(_) => {
+                        declare_error!(
+                            writer,
+                            StatusCode::BadRequest,
+                            None,
+                            server,
+                            "The request head is not valid UTF-8"
+                        );
+                    }
+                };
+                match parse_without_body(head) {
+                    Ok((rl, heads)) => {
+                        request_line = rl;
+                        headers = heads;
+                        break;
+                    }
+                    Err(reason) => {
+                        declare_error!(writer, StatusCode::BadRequest, None, server, reason);
+                    }
+                }
+            }
+            let read = match idle_timeout {
+                Some(duration) if data.is_empty() => {
+                    match timeout(duration, reader.read(&mut buf)).await {
+                        Ok(read) => read?,
+                        // the keep alive connection went idle
+                        Err(_) => return Ok(None),
+                    }
+                }
+                _ => reader.read(&mut buf).await?,
+            };
             if read == 0 {
-                declare_error!(writer, StatusCode::BadRequest);
+                // the client closed the connection before sending anything
+                if data.is_empty() {
+                    return Ok(None);
+                }
+                declare_error!(
+                    writer,
+                    StatusCode::BadRequest,
+                    None,
+                    server,
+                    "The connection was closed before the request ended"
+                );
             }
             let cur = &buf[..read];
+            if data.is_empty() {
+                // the request starts with its first bytes, not when
+                // we started waiting on the connection
+                started = Instant::now();
+            }
 
             data.extend_from_slice(cur);
-            if let Some(i) = find_in_slice(&data[..], b"\r\n\r\n") {
-                let first = &data[..i];
-                body_remainder = &data[i + 4..];
-                if let Ok(Some((rl, heads))) = str::from_utf8(first).map(parse_without_body) {
-                    request_line = rl;
-                    headers = heads;
-                    break;
-                } else {
-                    declare_error!(writer, StatusCode::BadRequest);
+        }
+        let raw_head = if server.settings.capture_raw_head {
+            Some(data[..data.len() - body_remainder.len()].to_vec())
+        } else {
+            None
+        };
+        for rewrite in &server.rewrites {
+            rewrite(&mut request_line, &headers);
+        }
+        if let Some(metrics) = server.metrics() {
+            metrics.request();
+        }
+        if server.drain.is_shutting_down() {
+            let sent = Error::respond(
+                server,
+                StatusCode::ServiceUnavailable,
+                None,
+                false,
+                Some(&request_line),
+                &[("Retry-After", &delay_seconds(RETRY_AFTER))],
+                &mut *writer,
+            )
+            .await?;
+            if let Some(metrics) = server.metrics() {
+                metrics.response(StatusCode::ServiceUnavailable, sent);
+            }
+            #[cfg(feature = "tracing")]
+            trace::responded(StatusCode::ServiceUnavailable, sent);
+            return Ok(None);
+        }
+        // transfer codings are listed in the order they were applied and
+        // chunked has to come last, as it is what marks the end of the body.
+        // The ones before it are decoded along with the content codings
+        let codings: Vec<&str> = headers
+            .get("transfer-encoding")
+            .map_or_else(Vec::new, |value| {
+                value
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|coding| !coding.is_empty())
+                    .collect()
+            });
+        let chunked = match codings.split_last() {
+            None => false,
+            Some((last, applied)) if last.eq_ignore_ascii_case("chunked") => {
+                if applied.iter().any(|c| c.eq_ignore_ascii_case("chunked")) {
+                    declare_error!(
+                        writer,
+                        StatusCode::BadRequest,
+                        None,
+                        server,
+                        "The request body is chunked more than once"
+                    );
+                }
+                #[cfg(feature = "compression")]
+                let supported = applied.iter().all(|coding| supports(coding));
+                #[cfg(not(feature = "compression"))]
+                let supported = applied.is_empty();
+                if !supported {
+                    declare_error!(
+                        writer,
+                        StatusCode::NotImplemented,
+                        None,
+                        server,
+                        "The transfer coding is not supported"
+                    );
+                }
+                true
+            }
+            Some(_) => {
+                declare_error!(
+                    writer,
+                    StatusCode::NotImplemented,
+                    None,
+                    server,
+                    "Only chunked transfer codings are supported"
+                );
+            }
+        };
+        // the length of a chunked body isn't known up front. A request
+        // framed both ways could be read differently by a proxy in front
+        let body_len = match headers.get("content-length") {
+            Some(_) if chunked => {
+                declare_error!(
+                    writer,
+                    StatusCode::BadRequest,
+                    None,
+                    server,
+                    "The request has both a Content-Length and a chunked body"
+                );
+            }
+            None => 0,
+            Some(value) => match parse_content_length(value) {
+                Some(len) => len,
+                // a length that can't be trusted leaves the end of the
+                // body unknown, reading on could smuggle in a request
+                None => {
+                    declare_error!(
+                        writer,
+                        StatusCode::BadRequest,
+                        None,
+                        server,
+                        "The Content-Length is invalid"
+                    );
+                }
+            },
+        };
+        let max_body_size = server.body_limit_for(&request_line.path);
+        if let Some(max) = max_body_size {
+            if body_len > max {
+                declare_error!(writer, StatusCode::PayloadTooLarge, None, server);
+            }
+        }
+        if let Some(expect) = headers.get("expect") {
+            if !expect.eq_ignore_ascii_case("100-continue") {
+                declare_error!(writer, StatusCode::ExpectationFailed, None, server);
+            }
+            // only invite the body if it is still to come and there is
+            // something to handle it, otherwise the final response is sent
+            // right away. Http 1.0 clients don't know about 100 Continue
+            let waiting = (body_len > 0 || chunked) && body_remainder.is_empty();
+            if waiting && request_line.version == HttpVersion::Http11 {
+                let router = server.router_for(&request_line, &headers);
+                let caught = server.fallback.is_some() || server.any.is_some();
+                if !caught
+                    && !router.may_handle(&request_line, server.settings.case_insensitive_paths)
+                {
+                    declare_error!(
+                        writer,
+                        StatusCode::NotFound,
+                        None,
+                        server,
+                        None,
+                        false,
+                        Some(&request_line)
+                    );
                 }
+                writer.write_all(b"HTTP/1.1 100 Continue\r\n\r\n").await?;
+                writer.flush().await?;
             }
         }
-        let body_len = headers
-            .get("content-length")
-            .and_then(|s| s.parse().ok())
-            .unwrap_or(0);
+        #[cfg(feature = "compression")]
+        let encoding = match (headers.get("content-encoding"), codings.split_last()) {
+            // content codings were applied before the transfer codings
+            (Some(content), Some((_, applied))) if !applied.is_empty() => {
+                Some(format!("{}, {}", content, applied.join(", ")))
+            }
+            (_, Some((_, applied))) if !applied.is_empty() => Some(applied.join(", ")),
+            (content, _) => content.cloned(),
+        };
+        // encoded bodies are never spooled as they have to be decoded,
+        // they are kept in memory under the body limit instead
+        #[cfg(feature = "compression")]
+        let spool_threshold = server
+            .settings
+            .spool_threshold
+            .filter(|_| encoding.is_none());
+        #[cfg(not(feature = "compression"))]
+        let spool_threshold = server.settings.spool_threshold;
         let mut body_vec: Vec<u8>;
-        if body_len > 0 {
-            if body_remainder.len() < body_len {
+        let mut spooled_body = None;
+        let mut trailers = Headers::default();
+        if chunked {
+            let announced = headers.get("trailer").map(String::as_str);
+            let max_trailers = server.settings.max_header_size;
+            let limit = BodyLimit::new(max_body_size).spool_over(spool_threshold);
+            match read_chunked(body_remainder, reader, limit, max_trailers, announced).await {
+                Ok(decoded) => {
+                    body_vec = decoded.body;
+                    spooled_body = decoded.spooled;
+                    trailers = decoded.trailers;
+                    pending.extend_from_slice(&decoded.rest);
+                    body = &body_vec[..];
+                }
+                Err(ChunkedError::TooLarge) => {
+                    declare_error!(writer, StatusCode::PayloadTooLarge, None, server);
+                }
+                Err(ChunkedError::Malformed) => {
+                    declare_error!(
+                        writer,
+                        StatusCode::BadRequest,
+                        None,
+                        server,
+                        "The chunked body is malformed"
+                    );
+                }
+                Err(ChunkedError::Io(err)) => return Err(err.into()),
+            }
+        } else if body_len > 0 {
+            let spool = spool_threshold.map_or(false, |threshold| body_len > threshold);
+            if spool {
+                spooled_body = Some(Octane::spool(body_remainder, body_len, reader).await?);
+                body = &[];
+            } else if body_remainder.len() < body_len {
                 let mut temp: Vec<u8> = vec![0; body_len - body_remainder.len()];
                 reader.read_exact(&mut temp[..]).await?;
                 body_vec = Vec::with_capacity(body_len);
@@ -237,48 +1402,396 @@ impl Octane {
                 body_vec.extend_from_slice(&temp[..]);
                 body = &body_vec[..];
             } else {
-                body = body_remainder;
+                body = &body_remainder[..body_len];
             }
         } else {
             body = &[];
         }
-        if let Some(request) = Request::parse(request_line, headers, body) {
+        if !chunked && body_remainder.len() > body_len {
+            pending.extend_from_slice(&body_remainder[body_len..]);
+        }
+        #[cfg(feature = "compression")]
+        let decoded: Vec<u8>;
+        #[cfg(feature = "compression")]
+        let body = if let Some(encoding) = encoding {
+            match decode(&encoding, body, max_body_size.or(Some(MAX_BODY_SIZE))) {
+                Ok(v) => {
+                    decoded = v;
+                    &decoded[..]
+                }
+                Err(DecodeError::TooLarge) => {
+                    declare_error!(writer, StatusCode::PayloadTooLarge, None, server);
+                }
+                Err(DecodeError::Unsupported) => {
+                    declare_error!(writer, StatusCode::UnsupportedMediaType, None, server);
+                }
+                Err(DecodeError::Malformed) => {
+                    declare_error!(
+                        writer,
+                        StatusCode::BadRequest,
+                        None,
+                        server,
+                        "The request body could not be decoded"
+                    );
+                }
+            }
+        } else {
+            body
+        };
+        if server.settings.strict_utf8
+            && headers
+                .get("content-type")
+                .map_or(false, |mime| is_text(mime))
+            && str::from_utf8(body).is_err()
+        {
+            declare_error!(
+                writer,
+                StatusCode::BadRequest,
+                None,
+                server,
+                "The request body is not valid UTF-8"
+            );
+        }
+        if let Some(mut request) = Request::parse(request_line, headers, body) {
+            request.spooled_body = spooled_body;
+            request.trailers = trailers;
+            request.raw_head = raw_head;
+            request.started = started;
+            request.state = server.state.clone();
+            request.secure = secure || server.settings.trust_proxy && forwarded_https(&request);
+            request.alpn = alpn.map(str::to_owned);
+            #[cfg(feature = "cookies")]
+            {
+                request.session = server.sessions.as_ref().map(|s| s.load(&request));
+            }
+            #[cfg(feature = "tracing")]
+            trace::parsed(&request);
             let request_line = &request.request_line;
             let mut res = Response::new_empty();
+            res.unknown_type = server.settings.unknown_file_type.clone();
+            res.static_cache_control = server.settings.static_cache_control.clone();
+            res.autoindex = server.settings.autoindex;
+            res.case_insensitive_paths = server.settings.case_insensitive_paths;
             // Detect http version and validate
+            // clients asking for json get their errors as json too
+            let json_errors =
+                request.preferred(&["text/html", "application/json"]) == Some("application/json");
             let checker = Http::validate(&request);
             if checker.is_malformed() {
-                declare_error!(writer, checker.err_code.unwrap());
+                declare_error!(
+                    writer,
+                    checker.err_code.unwrap(),
+                    None,
+                    server,
+                    None,
+                    json_errors
+                );
             }
-            if request_line.method.is_some() {
+            let requested = checker.keep_alive;
+            // the request has been read whole so the errors from here on
+            // don't have to close the connection
+            let error_keep_alive = || server.keep_alive(&requested);
+            let router = server.router_for(&request.request_line, &request.headers);
+            // methods without a variant of their own are only
+            // implemented if some route was registered for them
+            let implemented = match &request_line.method {
+                RequestMethod::Other(_) => {
+                    server.any.is_some() || router.handles(&request_line.method)
+                }
+                method => method.is_some(),
+            };
+            if implemented {
                 // run closures
-                server.router.run(request.clone(), &mut res);
+                let flow = match server.settings.route_timeout {
+                    None => server.route(&request, &mut res),
+                    Some(limit) => {
+                        // the closures can't be interrupted, so they run off
+                        // the task and are left behind if they take too long
+                        let app = Arc::clone(server);
+                        let line = request.request_line.clone();
+                        let owned = request.body.to_vec();
+                        let detached = request.with_body(&[]);
+                        let routed = spawn_blocking(move || {
+                            let request = detached.with_body(&owned);
+                            let flow = app.route(&request, &mut res);
+                            (request.with_body(&[]), res, flow)
+                        });
+                        match timeout(limit, routed).await {
+                            Ok(routed) => {
+                                let (routed, routed_res, flow) = routed?;
+                                request = routed.with_body(body);
+                                res = routed_res;
+                                flow
+                            }
+                            Err(_) => {
+                                declare_error!(
+                                    writer,
+                                    StatusCode::GatewayTimeout,
+                                    error_keep_alive(),
+                                    server,
+                                    None,
+                                    json_errors,
+                                    Some(&line)
+                                );
+                            }
+                        }
+                    }
+                };
+                let request_line = &request.request_line;
+                // a closure which stopped the flow has produced the final
+                // response, even if it is just a status code
+                // unanswered OPTIONS requests get the methods of the path,
+                // or of the whole server for the asterisk form
+                if request_line.method == RequestMethod::Options
+                    && flow.should_continue()
+                    && !res.has_body()
+                {
+                    let allowed = match request_line.target {
+                        RequestTarget::Asterisk => router.methods(),
+                        _ => router
+                            .allowed(&request_line.path, server.settings.case_insensitive_paths),
+                    };
+                    if !allowed.is_empty() {
+                        let allowed: Vec<&str> =
+                            allowed.iter().map(RequestMethod::as_str).collect();
+                        res.set("Allow", &allowed.join(", ")).no_content();
+                    }
+                }
+                #[cfg(feature = "cookies")]
+                if let (Some(sessions), Some(session)) = (&server.sessions, &request.session) {
+                    sessions.save(session, request.secure, &mut res);
+                }
+                #[cfg(feature = "tracing")]
+                trace::routed(!flow.should_continue() || res.has_body());
+                if flow.should_continue() && !res.has_body() {
+                    declare_error!(
+                        writer,
+                        StatusCode::NotFound,
+                        error_keep_alive(),
+                        server,
+                        None,
+                        json_errors,
+                        Some(&request.request_line)
+                    );
+                }
+                if request_line.method == RequestMethod::Get {
+                    if let Some(range) = request.headers.get("range") {
+                        res.range(range, request.headers.get("if-range").map(String::as_str));
+                    }
+                }
                 if !res.has_body() {
-                    declare_error!(writer, StatusCode::NotFound);
+                    res.set("Content-Length", "0");
                 }
+                // close the connections once their requests are done
+                let keep_alive = server
+                    .keep_alive(&requested)
+                    .filter(|_| !res.is_unsized() && !res.closes_connection());
+                res.set(
+                    "Connection",
+                    if keep_alive.is_some() {
+                        "keep-alive"
+                    } else {
+                        "close"
+                    },
+                );
 
-                Octane::send(res.get_data(), writer).await?;
+                let status = res.status_code;
+                let flush_headers = server.settings.flush_headers_early || res.is_unsized();
+                let body_len = res
+                    .get("Content-Length")
+                    .and_then(|len| len.parse::<usize>().ok());
+                let (mut head, mut body) = res.get_data();
+                // HTTP/0.9 responses are only the body
+                if request_line.version == HttpVersion::Http09 {
+                    head.clear();
+                }
+                // responses to HEAD keep the headers of the body they
+                // would have had, including its length
+                if request_line.method == RequestMethod::Head {
+                    body = Box::new(tokio::io::empty());
+                }
+                let expected = match request_line.method {
+                    RequestMethod::Head => head.len(),
+                    _ => head.len() + body_len.unwrap_or(0),
+                };
+                let sent = Octane::send((head, body), writer, flush_headers).await?;
+                if let Some(metrics) = server.metrics() {
+                    metrics.response(status, sent);
+                }
+                #[cfg(feature = "tracing")]
+                trace::responded(status, sent);
+                // a body which ended short of its length leaves the client
+                // waiting for the rest, only closing tells it there is none
+                if sent < expected as u64 {
+                    return Ok(None);
+                }
+                Ok(keep_alive)
             } else {
-                declare_error!(writer, StatusCode::NotImplemented);
+                let reason = match &request_line.method {
+                    RequestMethod::Other(method) => {
+                        format!("The {} method is not implemented", method)
+                    }
+                    _ => "The request method is not supported".to_owned(),
+                };
+                declare_error!(
+                    writer,
+                    StatusCode::NotImplemented,
+                    error_keep_alive(),
+                    server,
+                    Some(&reason),
+                    json_errors,
+                    Some(&request_line)
+                );
             }
         } else {
-            declare_error!(writer, StatusCode::BadRequest);
+            declare_error!(
+                writer,
+                StatusCode::BadRequest,
+                None,
+                server,
+                "The request is malformed"
+            );
         }
-        Ok(())
     }
+    // How long the connection is kept for the next request once the
+    // current one is answered, None if it should be closed
+    fn keep_alive(&self, requested: &KeepAliveState) -> Option<Duration> {
+        match (self.settings.keep_alive, requested) {
+            _ if self.drain.is_shutting_down() => None,
+            (None, _) | (_, KeepAliveState::Close) => None,
+            (Some(duration), KeepAliveState::Particular(requested)) => {
+                Some(duration.min(*requested))
+            }
+            (Some(duration), KeepAliveState::UserDefined) => Some(duration),
+        }
+    }
+    // Streams a body to a temporary file instead of buffering it
+    // in memory, the part of the body which was already read along
+    // with the headers is written first
+    async fn spool<R>(
+        received: &[u8],
+        len: usize,
+        reader: &mut R,
+    ) -> Result<SpooledBody, Box<dyn StdError>>
+    where
+        R: AsyncRead + Unpin,
+    {
+        // the handle is created along with the file so it is removed on failure
+        let (file, spooled) = SpooledBody::create()?;
+        let mut file = File::from_std(file);
+        let spooled = spooled.with_len(len);
+        let received = &received[..received.len().min(len)];
+        file.write_all(received).await?;
+        let remaining = (len - received.len()) as u64;
+        if copy(&mut reader.take(remaining), &mut file).await? < remaining {
+            return Err(Box::new(std::io::Error::from(
+                std::io::ErrorKind::UnexpectedEof,
+            )));
+        }
+        file.flush().await?;
+        Ok(spooled)
+    }
+    // Writes the response and returns the number of bytes sent. Unless
+    // the headers are flushed right away, they are buffered along with
+    // the body so that small responses take a single write
     pub(crate) async fn send<S>(
         mut response: (String, BoxReader),
         mut stream_async: S,
-    ) -> Result<(), Box<dyn StdError>>
+        flush_headers: bool,
+    ) -> Result<u64, Box<dyn StdError>>
     where
         S: AsyncWrite + Unpin,
     {
-        stream_async.write_all(response.0.as_bytes()).await?;
-        copy(&mut response.1, &mut stream_async).await?;
-        Ok(())
+        let body = if flush_headers {
+            stream_async.write_all(response.0.as_bytes()).await?;
+            stream_async.flush().await?;
+            copy(&mut response.1, &mut stream_async).await?
+        } else {
+            let mut buffered = BufWriter::new(stream_async);
+            buffered.write_all(response.0.as_bytes()).await?;
+            let body = copy(&mut response.1, &mut buffered).await?;
+            buffered.flush().await?;
+            body
+        };
+        Ok(response.0.len() as u64 + body)
+    }
+}
+
+// Whether the proxy in front received the request over https, the
+// header is a list when there are several proxies and the first
+// entry is the one the client connected to
+fn forwarded_https(request: &Request) -> bool {
+    request
+        .headers
+        .get("x-forwarded-proto")
+        .and_then(|proto| proto.split(',').next())
+        .map_or(false, |proto| proto.trim().eq_ignore_ascii_case("https"))
+}
+
+// Runs the closure for `GET` and `HEAD` requests of exactly the
+// path, an invalid path never matches
+fn endpoint(path: &str, closure: Closure) -> Closure {
+    let path = PathBuf::parse(path).ok();
+    route!(|req, res| {
+        let method = &req.request_line.method;
+        let wanted = *method == RequestMethod::Get || *method == RequestMethod::Head;
+        if !wanted || path.as_ref() != Some(&req.request_line.path) {
+            return Flow::Next;
+        }
+        closure(req, res)
+    })
+}
+
+// Parses a Content-Length value, which must be a bare number. Repeated
+// headers arrive joined by commas and are rejected along with signs and
+// anything else that isn't a digit
+fn parse_content_length(value: &str) -> Option<usize> {
+    let value = value.trim();
+    if value.is_empty() || !value.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    value.parse().ok()
+}
+
+// Whether a content type is meant to be text, and so utf-8
+fn is_text(content_type: &str) -> bool {
+    let mime = content_type.split(';').next().unwrap_or("").trim();
+    let mime = mime.to_ascii_lowercase();
+    mime.starts_with("text/")
+        || mime == "application/json"
+        || mime.ends_with("+json")
+        || mime == "application/x-www-form-urlencoded"
+}
+
+// Counts the segments of the path of a request line by its slashes,
+// without parsing it. The query string doesn't count
+fn path_segments(line: &[u8]) -> usize {
+    line.split(|b| *b == b' ')
+        .nth(1)
+        .and_then(|target| target.split(|b| *b == b'?').next())
+        .map_or(0, |path| path.iter().filter(|b| **b == b'/').count())
+}
+
+// Returns the request line if it is an HTTP/0.9 simple request,
+// which only has a method and a path
+#[cfg(feature = "faithful")]
+fn simple_request(line: &[u8]) -> Option<&str> {
+    let line = str::from_utf8(line).ok()?;
+    if line.split_whitespace().count() == 2 {
+        Some(line)
+    } else {
+        None
     }
 }
 
+// Strips the port off a host, ipv6 addresses are in brackets
+fn strip_port(host: &str) -> &str {
+    if host.starts_with('[') {
+        return host.find(']').map_or(host, |i| &host[..=i]);
+    }
+    host.split(':').next().unwrap_or(host)
+}
+
 default!(Octane);
 
 impl Route for Octane {
@@ -317,12 +1830,2917 @@ impl Config for Octane {
     fn set_keepalive(&mut self, duration: Duration) {
         self.settings.keep_alive = Some(duration);
     }
+    fn disable_keepalive(&mut self) {
+        self.settings.keep_alive = None;
+    }
+    fn set_static_cache_control(&mut self, value: &str) {
+        self.settings.static_cache_control = Some(value.to_owned());
+    }
     fn with_ssl_config(&mut self, ssl_conf: Ssl) {
         self.settings.ssl.key = ssl_conf.key;
         self.settings.ssl.cert = ssl_conf.cert;
+        self.settings.ssl.alpn = ssl_conf.alpn;
     }
     fn ssl(&mut self, port: u16) -> &mut Ssl {
         self.settings.ssl.port = port;
         &mut self.settings.ssl
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::task;
+    use std::pin::Pin;
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+    use std::task::{Context, Poll};
+    use tokio::io::ReadBuf;
+    use tokio::net::{TcpListener, TcpStream};
+
+    // Returns a connected (server side, client side) socket pair
+    async fn socket_pair() -> (TcpStream, TcpStream) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (server, _) = listener.accept().await.unwrap();
+        (server, client)
+    }
+
+    // A plain tcp stream which claims to be encrypted, standing in
+    // for the ssl streams
+    struct SecureStream(TcpStream);
+
+    impl AsyncRead for SecureStream {
+        fn poll_read(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &mut ReadBuf<'_>,
+        ) -> Poll<std::io::Result<()>> {
+            Pin::new(&mut self.0).poll_read(cx, buf)
+        }
+    }
+
+    impl AsyncWrite for SecureStream {
+        fn poll_write(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &[u8],
+        ) -> Poll<std::io::Result<usize>> {
+            Pin::new(&mut self.0).poll_write(cx, buf)
+        }
+        fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+            Pin::new(&mut self.0).poll_flush(cx)
+        }
+        fn poll_shutdown(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+        ) -> Poll<std::io::Result<()>> {
+            Pin::new(&mut self.0).poll_shutdown(cx)
+        }
+    }
+
+    impl AsMutStream for SecureStream {
+        fn stream_mut(&mut self) -> &mut TcpStream {
+            &mut self.0
+        }
+        fn is_secure(&self) -> bool {
+            true
+        }
+    }
+
+    // Serves a single connection on the app with the raw request
+    // and returns everything the server wrote back
+    async fn request(app: Octane, raw: &[u8]) -> String {
+        let (server, mut client) = socket_pair().await;
+        let handle = task!({
+            Octane::serve(server, Arc::new(app)).await.ok();
+        });
+        client.write_all(raw).await.unwrap();
+        // closing our side ends a keep alive connection after the response
+        AsyncWriteExt::shutdown(&mut client).await.unwrap();
+        let mut response = Vec::new();
+        client.read_to_end(&mut response).await.unwrap();
+        handle.await.unwrap();
+        String::from_utf8_lossy(&response).into_owned()
+    }
+
+    // Reads a single response off the stream using its Content-Length
+    async fn read_response(client: &mut TcpStream) -> String {
+        let mut data = Vec::new();
+        let mut buf = [0; BUF_SIZE];
+        loop {
+            if let Some(i) = find_in_slice(&data, b"\r\n\r\n") {
+                let head = String::from_utf8_lossy(&data[..i]).into_owned();
+                let len = head
+                    .lines()
+                    .find_map(|line| line.strip_prefix("Content-Length: "))
+                    .map_or(0, |len| len.parse().unwrap());
+                if data.len() >= i + 4 + len {
+                    return String::from_utf8_lossy(&data[..i + 4 + len]).into_owned();
+                }
+            }
+            let read = client.read(&mut buf).await.unwrap();
+            assert_ne!(read, 0, "connection closed before a full response");
+            data.extend_from_slice(&buf[..read]);
+        }
+    }
+
+    // An app which responds with "Hello, World" on /
+    fn hello_app() -> Octane {
+        let mut app = Octane::new();
+        app.get("/", route_stop!(|req, res| res.send("Hello, World")))
+            .unwrap();
+        app
+    }
+
+    // An app which echoes the body of POST requests on /
+    fn echo_app() -> Octane {
+        let mut app = Octane::new();
+        app.post("/", route_stop!(|req, res| res.send(req.body)))
+            .unwrap();
+        app
+    }
+
+    #[crate::test]
+    async fn nodelay_enabled() {
+        // tcp_nodelay is on by default and should be applied to the socket
+        let app = Octane::new();
+        let (mut stream, _client) = socket_pair().await;
+        Octane::configure_stream(&mut stream, &app.settings).unwrap();
+        assert!(stream.nodelay().unwrap());
+    }
+
+    #[crate::test]
+    async fn nodelay_disabled() {
+        // disabling tcp_nodelay should leave nagle's algorithm on
+        let mut app = Octane::new();
+        app.settings.tcp_nodelay = false;
+        let (mut stream, _client) = socket_pair().await;
+        stream.set_nodelay(true).unwrap();
+        Octane::configure_stream(&mut stream, &app.settings).unwrap();
+        assert!(!stream.nodelay().unwrap());
+    }
+
+    #[crate::test]
+    async fn success_plain_body() {
+        // Bodies without a content encoding should reach the handler untouched.
+        let response = request(
+            echo_app(),
+            b"POST / HTTP/1.1\r\nHost: localhost\r\nContent-Length: 5\r\n\r\nhello",
+        )
+        .await;
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.ends_with("\r\n\r\nhello"));
+    }
+
+    #[crate::test]
+    async fn success_body_bytes() {
+        // Binary bodies should reach the handler byte for byte.
+        let raw = b"\x00\xff\r\n\r\nraw\x7f";
+        let mut app = Octane::new();
+        app.post(
+            "/",
+            route_stop!(|req, res| {
+                res.send(if req.body_bytes() == raw {
+                    "unchanged"
+                } else {
+                    "changed"
+                })
+            }),
+        )
+        .unwrap();
+        let mut data = b"POST / HTTP/1.1\r\nHost: localhost\r\nContent-Length: 10\r\n\r\n".to_vec();
+        data.extend_from_slice(raw);
+        assert!(request(app, &data).await.ends_with("\r\n\r\nunchanged"));
+    }
+
+    #[crate::test]
+    async fn fail_content_length_duplicate() {
+        // Repeated Content-Length headers shouldn't let the body through
+        // as a second request
+        let mut app = echo_app();
+        app.get("/secret", route_stop!(|_req, res| res.send("secret")))
+            .unwrap();
+        let response = request(
+            app,
+            b"POST / HTTP/1.1\r\nHost: localhost\r\nContent-Length: 30\r\nContent-Length: 30\r\n\r\nGET /secret HTTP/1.1\r\nHost: a\r\n\r\n",
+        )
+        .await;
+        assert!(response.starts_with("HTTP/1.1 400 BAD REQUEST\r\n"));
+        assert!(response.contains("Connection: close\r\n"));
+        assert!(!response.contains("secret"));
+    }
+
+    #[crate::test]
+    async fn fail_content_length_not_numeric() {
+        // A Content-Length with trailing garbage should be rejected
+        let response = request(
+            echo_app(),
+            b"POST / HTTP/1.1\r\nHost: localhost\r\nContent-Length: 3x\r\n\r\nabc",
+        )
+        .await;
+        assert!(response.starts_with("HTTP/1.1 400 BAD REQUEST\r\n"));
+        assert_eq!(response.matches("HTTP/1.1 ").count(), 1);
+    }
+
+    #[crate::test]
+    async fn fail_content_length_negative() {
+        // A negative Content-Length should be rejected
+        let response = request(
+            echo_app(),
+            b"POST / HTTP/1.1\r\nHost: localhost\r\nContent-Length: -1\r\n\r\nGET / HTTP/1.1\r\n\r\n",
+        )
+        .await;
+        assert!(response.starts_with("HTTP/1.1 400 BAD REQUEST\r\n"));
+        assert_eq!(response.matches("HTTP/1.1 ").count(), 1);
+    }
+
+    #[crate::test]
+    async fn success_route_timeout() {
+        // Closures answering in time should respond as usual, with the body
+        // of the request.
+        let mut app = echo_app();
+        app.settings.route_timeout = Some(Duration::from_secs(5));
+        let response = request(
+            app,
+            b"POST / HTTP/1.1\r\nHost: localhost\r\nContent-Length: 5\r\n\r\nhello",
+        )
+        .await;
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.ends_with("\r\n\r\nhello"));
+    }
+
+    #[crate::test]
+    async fn fail_route_timeout() {
+        // Closures running over the timeout should get a 504.
+        let mut app = Octane::new();
+        app.get(
+            "/slow",
+            route_stop!(|req, res| {
+                std::thread::sleep(Duration::from_millis(200));
+                res.send("too late");
+            }),
+        )
+        .unwrap();
+        app.settings.route_timeout = Some(Duration::from_millis(20));
+        let response = request(app, b"GET /slow HTTP/1.1\r\nHost: localhost\r\n\r\n").await;
+        assert!(response.starts_with("HTTP/1.1 504 GATEWAY TIMEOUT\r\n"));
+        assert!(!response.contains("too late"));
+    }
+
+    #[crate::test]
+    async fn fail_body_too_large() {
+        // Bodies over the limit should be rejected before being read.
+        let mut app = echo_app();
+        app.settings.max_body_size = Some(4);
+        let response = request(
+            app,
+            b"POST / HTTP/1.1\r\nHost: localhost\r\nContent-Length: 5\r\n\r\nhello",
+        )
+        .await;
+        assert!(response.starts_with("HTTP/1.1 413 PAYLOAD TOO LARGE\r\n"));
+    }
+
+    #[cfg(feature = "compression")]
+    fn gzip(body: &[u8]) -> Vec<u8> {
+        use flate2::write::GzEncoder;
+        use flate2::Compression;
+        use std::io::Write;
+
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(body).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    #[cfg(feature = "compression")]
+    fn gzip_request(body: &[u8]) -> Vec<u8> {
+        let compressed = gzip(body);
+        let mut raw = format!(
+            "POST / HTTP/1.1\r\nHost: localhost\r\nContent-Encoding: gzip\r\nContent-Length: {}\r\n\r\n",
+            compressed.len()
+        )
+        .into_bytes();
+        raw.extend(compressed);
+        raw
+    }
+
+    #[crate::test]
+    #[cfg(feature = "compression")]
+    async fn success_gzip_body() {
+        // Handlers should see the decompressed body.
+        let json = r#"{"server": "Octane"}"#;
+        let response = request(echo_app(), &gzip_request(json.as_bytes())).await;
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.ends_with(json));
+    }
+
+    #[crate::test]
+    #[cfg(feature = "compression")]
+    async fn success_gzip_chunked_body() {
+        // Transfer codings before chunked should be undone after the chunks.
+        let json = r#"{"server": "Octane"}"#;
+        let chunked = |head: &str, body: &[u8]| {
+            let (first, second) = body.split_at(body.len() / 2);
+            let mut raw = format!(
+                "POST / HTTP/1.1\r\nHost: localhost\r\n{}\r\n{:x}\r\n",
+                head,
+                first.len()
+            )
+            .into_bytes();
+            raw.extend_from_slice(first);
+            raw.extend(format!("\r\n{:x}\r\n", second.len()).into_bytes());
+            raw.extend_from_slice(second);
+            raw.extend_from_slice(b"\r\n0\r\n\r\n");
+            raw
+        };
+        let head = "Transfer-Encoding: gzip, chunked\r\n";
+        let response = request(echo_app(), &chunked(head, &gzip(json.as_bytes()))).await;
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.ends_with(json));
+        // the content coding was applied first
+        let head = "Content-Encoding: deflate\r\nTransfer-Encoding: gzip\r\nTransfer-Encoding: chunked\r\n";
+        let deflated = {
+            use flate2::write::ZlibEncoder;
+            use flate2::Compression;
+            use std::io::Write;
+
+            let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+            encoder.write_all(json.as_bytes()).unwrap();
+            encoder.finish().unwrap()
+        };
+        let response = request(echo_app(), &chunked(head, &gzip(&deflated))).await;
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.ends_with(json));
+        let head = "Transfer-Encoding: br, chunked\r\n";
+        let response = request(echo_app(), &chunked(head, b"body")).await;
+        assert!(response.starts_with("HTTP/1.1 501"));
+        let head = "Transfer-Encoding: chunked, chunked\r\n";
+        let response = request(echo_app(), &chunked(head, b"body")).await;
+        assert!(response.starts_with("HTTP/1.1 400"));
+    }
+
+    #[crate::test]
+    #[cfg(feature = "compression")]
+    async fn fail_gzip_bomb_without_limit() {
+        // Decompressed bodies should be limited even when no limit is set.
+        let app = echo_app();
+        assert_eq!(app.settings.max_body_size, None);
+        let response = request(app, &gzip_request(&vec![0; MAX_BODY_SIZE + 1])).await;
+        assert!(response.starts_with("HTTP/1.1 413 PAYLOAD TOO LARGE\r\n"));
+    }
+
+    #[crate::test]
+    #[cfg(feature = "compression")]
+    async fn fail_gzip_bomb() {
+        // The body limit should apply to the decompressed size.
+        let mut app = echo_app();
+        app.settings.max_body_size = Some(1024 * 16);
+        let response = request(app, &gzip_request(&vec![0; 1024 * 1024])).await;
+        assert!(response.starts_with("HTTP/1.1 413 PAYLOAD TOO LARGE\r\n"));
+    }
+
+    #[crate::test]
+    async fn success_spooled_body() {
+        // Bodies over the threshold should be readable from the spooled file.
+        let mut app = Octane::new();
+        app.settings.spool_threshold = Some(16);
+        app.post(
+            "/",
+            route_stop!(|req, res| {
+                use std::io::Read;
+
+                assert!(req.body.is_empty());
+                let spooled = req.spooled_body.as_ref().unwrap();
+                let mut contents = String::new();
+                spooled
+                    .reader()
+                    .unwrap()
+                    .read_to_string(&mut contents)
+                    .unwrap();
+                assert_eq!(spooled.len(), contents.len());
+                res.send(contents);
+            }),
+        )
+        .unwrap();
+        let body = "a".repeat(BUF_SIZE * 4);
+        let raw = format!(
+            "POST / HTTP/1.1\r\nHost: localhost\r\nContent-Length: {}\r\n\r\n{}",
+            body.len(),
+            body
+        );
+        let response = request(app, raw.as_bytes()).await;
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.ends_with(&format!("\r\n\r\n{}", body)));
+    }
+
+    #[crate::test]
+    #[cfg(feature = "compression")]
+    async fn success_encoded_body_not_spooled() {
+        // Encoded bodies should be decoded in memory instead of spooled.
+        let mut app = Octane::new();
+        app.settings.spool_threshold = Some(16);
+        app.post(
+            "/",
+            route_stop!(|req, res| {
+                assert!(req.spooled_body.is_none());
+                res.send(req.body);
+            }),
+        )
+        .unwrap();
+        let body = "a".repeat(BUF_SIZE * 4);
+        let response = request(app, &gzip_request(body.as_bytes())).await;
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.ends_with(&format!("\r\n\r\n{}", body)));
+    }
+
+    #[crate::test]
+    #[cfg(feature = "compression")]
+    async fn success_encoded_chunked_body_not_spooled() {
+        // Transfer codings should be decoded instead of spooling the chunks.
+        let mut app = Octane::new();
+        app.settings.spool_threshold = Some(8);
+        app.post(
+            "/",
+            route_stop!(|req, res| {
+                assert!(req.spooled_body.is_none());
+                res.send(req.body);
+            }),
+        )
+        .unwrap();
+        let body = "a".repeat(BUF_SIZE * 4);
+        let compressed = gzip(body.as_bytes());
+        let mut raw = format!(
+            "POST / HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: gzip, chunked\r\n\r\n{:x}\r\n",
+            compressed.len()
+        )
+        .into_bytes();
+        raw.extend(compressed);
+        raw.extend_from_slice(b"\r\n0\r\n\r\n");
+        let response = request(app, &raw).await;
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.ends_with(&format!("\r\n\r\n{}", body)));
+    }
+
+    #[crate::test]
+    async fn success_spooled_chunked_body() {
+        // Chunked bodies growing past the threshold should be spooled.
+        let mut app = Octane::new();
+        app.settings.spool_threshold = Some(8);
+        app.post(
+            "/",
+            route_stop!(|req, res| {
+                let spooled = req.spooled_body.as_ref().unwrap();
+                let contents = std::fs::read_to_string(spooled.path()).unwrap();
+                assert_eq!(spooled.len(), contents.len());
+                res.send(contents);
+            }),
+        )
+        .unwrap();
+        let raw = b"POST / HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: chunked\r\n\r\n\
+            5\r\nHello\r\n7\r\n, World\r\n0\r\n\r\n";
+        let response = request(app, raw).await;
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.ends_with("\r\n\r\nHello, World"));
+    }
+
+    #[crate::test]
+    async fn fail_chunked_with_content_length() {
+        // A chunked body with a Content-Length shouldn't be read either way
+        let mut app = echo_app();
+        app.get("/secret", route_stop!(|_req, res| res.send("secret")))
+            .unwrap();
+        let raw = b"POST / HTTP/1.1\r\nHost: localhost\r\nContent-Length: 4\r\n\
+            Transfer-Encoding: chunked\r\n\r\n0\r\n\r\nGET /secret HTTP/1.1\r\n\r\n";
+        let response = request(app, raw).await;
+        assert!(response.starts_with("HTTP/1.1 400 BAD REQUEST\r\n"));
+        assert!(response.contains("Connection: close\r\n"));
+        assert!(!response.contains("secret"));
+    }
+
+    #[crate::test]
+    async fn fail_streamed_chunked_body_too_large() {
+        // A chunked body should be cut off once it goes over the limit.
+        let mut app = echo_app();
+        app.settings.max_body_size = Some(16);
+        app.settings.spool_threshold = Some(8);
+        let (server, mut client) = socket_pair().await;
+        task!({
+            Octane::serve(server, Arc::new(app)).await.ok();
+        });
+        client
+            .write_all(b"POST / HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: chunked\r\n\r\n")
+            .await
+            .unwrap();
+        // the third chunk goes over the limit and is refused before its
+        // data, which never comes
+        client
+            .write_all(b"8\r\n12345678\r\n8\r\n12345678\r\n8\r\n")
+            .await
+            .unwrap();
+        let response = timeout(Duration::from_secs(1), read_response(&mut client))
+            .await
+            .expect("the body was waited for");
+        assert!(response.starts_with("HTTP/1.1 413"));
+        let mut rest = Vec::new();
+        client.read_to_end(&mut rest).await.unwrap();
+        assert!(rest.is_empty());
+    }
+
+    // An app where a middleware denies every request
+    // without an authorization header
+    fn auth_app(ran: Arc<AtomicBool>) -> Octane {
+        let mut app = Octane::new();
+        app.add(route!(|req, res| {
+            if req.headers.get("authorization").is_some() {
+                return Flow::Next;
+            }
+            res.status(StatusCode::Unauthorized).send("Unauthorized");
+            Flow::Stop
+        }))
+        .unwrap();
+        app.get(
+            "/protected",
+            route_stop!(|req, res| {
+                ran.store(true, Ordering::SeqCst);
+                res.send("secret");
+            }),
+        )
+        .unwrap();
+        app
+    }
+
+    #[crate::test]
+    async fn success_middleware_short_circuit() {
+        // A middleware stopping with a response should skip the handlers.
+        let ran = Arc::new(AtomicBool::new(false));
+        let app = auth_app(Arc::clone(&ran));
+        let response = request(app, b"GET /protected HTTP/1.1\r\nHost: localhost\r\n\r\n").await;
+        assert!(response.starts_with("HTTP/1.1 401 UNAUTHORIZED\r\n"));
+        assert!(response.ends_with("\r\n\r\nUnauthorized"));
+        assert!(!ran.load(Ordering::SeqCst));
+    }
+
+    #[crate::test]
+    async fn success_middleware_pass_through() {
+        // The handler should run when the middleware lets the request through.
+        let ran = Arc::new(AtomicBool::new(false));
+        let app = auth_app(Arc::clone(&ran));
+        let response = request(
+            app,
+            b"GET /protected HTTP/1.1\r\nHost: localhost\r\nAuthorization: yes\r\n\r\n",
+        )
+        .await;
+        assert!(response.ends_with("\r\n\r\nsecret"));
+        assert!(ran.load(Ordering::SeqCst));
+    }
+
+    #[crate::test]
+    async fn success_status_only_short_circuit() {
+        // Stopping with just a status shouldn't turn into a 404.
+        let mut app = Octane::new();
+        app.add(route!(|req, res| {
+            res.status(StatusCode::Unauthorized);
+            Flow::Stop
+        }))
+        .unwrap();
+        let response = request(app, b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n").await;
+        assert!(response.starts_with("HTTP/1.1 401 UNAUTHORIZED\r\n"));
+        assert!(response.contains("Content-Length: 0\r\n"));
+    }
+
+    #[crate::test]
+    async fn success_keepalive() {
+        // Http 1.1 connections should serve several requests.
+        let (server, mut client) = socket_pair().await;
+        let handle = task!({
+            Octane::serve(server, Arc::new(hello_app())).await.ok();
+        });
+        for _ in 0..2 {
+            client
+                .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n")
+                .await
+                .unwrap();
+            let response = read_response(&mut client).await;
+            assert!(response.contains("Connection: keep-alive\r\n"));
+            assert!(response.ends_with("Hello, World"));
+        }
+        AsyncWriteExt::shutdown(&mut client).await.unwrap();
+        handle.await.unwrap();
+    }
+
+    #[crate::test]
+    async fn success_keepalive_disabled() {
+        // With keep alive disabled, the connection is closed after one request.
+        let mut app = hello_app();
+        app.disable_keepalive();
+        let (server, mut client) = socket_pair().await;
+        let handle = task!({
+            Octane::serve(server, Arc::new(app)).await.ok();
+        });
+        client
+            .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\nConnection: keep-alive\r\n\r\n")
+            .await
+            .unwrap();
+        let mut response = Vec::new();
+        // the server has to close without waiting for us to close first
+        timeout(Duration::from_secs(1), client.read_to_end(&mut response))
+            .await
+            .expect("connection wasn't closed")
+            .unwrap();
+        let response = String::from_utf8(response).unwrap();
+        assert!(response.contains("Connection: close\r\n"));
+        assert!(response.ends_with("Hello, World"));
+        handle.await.unwrap();
+    }
+
+    #[crate::test]
+    async fn success_http10_closes() {
+        // Http 1.0 connections are closed unless asked to be kept alive.
+        let (server, mut client) = socket_pair().await;
+        let handle = task!({
+            Octane::serve(server, Arc::new(hello_app())).await.ok();
+        });
+        client.write_all(b"GET / HTTP/1.0\r\n\r\n").await.unwrap();
+        let mut response = Vec::new();
+        timeout(Duration::from_secs(1), client.read_to_end(&mut response))
+            .await
+            .expect("connection wasn't closed")
+            .unwrap();
+        assert!(String::from_utf8(response)
+            .unwrap()
+            .contains("Connection: close\r\n"));
+        handle.await.unwrap();
+    }
+
+    #[crate::test]
+    async fn success_request_elapsed() {
+        // The elapsed time should be non zero and monotonic.
+        let mut app = Octane::new();
+        app.get(
+            "/",
+            route_stop!(|req, res| {
+                std::thread::sleep(Duration::from_millis(10));
+                let first = req.elapsed();
+                let second = req.elapsed();
+                assert!(first >= Duration::from_millis(10));
+                assert!(second >= first);
+                res.send("timed");
+            }),
+        )
+        .unwrap();
+        let response = request(app, b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n").await;
+        assert!(response.ends_with("timed"));
+    }
+
+    #[crate::test]
+    async fn success_options() {
+        // OPTIONS should list the methods of the server or of the path.
+        let app = || {
+            let mut app = hello_app();
+            app.post("/users", route_stop!(|req, res| res.send("created")))
+                .unwrap();
+            app.delete("/users/:id", route_stop!(|req, res| res.no_content()))
+                .unwrap();
+            app
+        };
+        let response = request(app(), b"OPTIONS * HTTP/1.1\r\nHost: localhost\r\n\r\n").await;
+        assert!(response.starts_with("HTTP/1.1 204 NO CONTENT\r\n"));
+        assert!(response.contains("Allow: GET, HEAD, POST, DELETE, OPTIONS\r\n"));
+        let response = request(app(), b"OPTIONS /users HTTP/1.1\r\nHost: localhost\r\n\r\n").await;
+        assert!(response.starts_with("HTTP/1.1 204 NO CONTENT\r\n"));
+        assert!(response.contains("Allow: POST, OPTIONS\r\n"));
+        let response = request(app(), b"OPTIONS /posts HTTP/1.1\r\nHost: localhost\r\n\r\n").await;
+        assert!(response.starts_with("HTTP/1.1 404"));
+    }
+
+    #[crate::test]
+    #[cfg(feature = "cookies")]
+    async fn success_sessions() {
+        // A value stored in one request should be read back with the cookie.
+        use crate::session::MemoryStore;
+
+        let mut app = Octane::new();
+        app.with_sessions("sid", MemoryStore::new());
+        app.post(
+            "/login",
+            route_stop!(|req, res| {
+                req.session().unwrap().set("user", "alice");
+                res.send("logged in");
+            }),
+        )
+        .unwrap();
+        app.get(
+            "/me",
+            route_stop!(|req, res| {
+                let user = req.session().unwrap().get("user");
+                res.send(user.unwrap_or_else(|| "nobody".to_owned()));
+            }),
+        )
+        .unwrap();
+        let (server, mut client) = socket_pair().await;
+        task!({
+            Octane::serve(server, Arc::new(app)).await.ok();
+        });
+        client
+            .write_all(b"POST /login HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .await
+            .unwrap();
+        let response = read_response(&mut client).await;
+        let cookie = response
+            .lines()
+            .find_map(|line| line.strip_prefix("Set-Cookie:"))
+            .unwrap();
+        assert!(cookie.ends_with("; Path=/; HttpOnly; SameSite=Lax"));
+        let id = cookie.split(';').next().unwrap().trim();
+        assert!(id.starts_with("sid="));
+        let raw = format!(
+            "GET /me HTTP/1.1\r\nHost: localhost\r\nCookie: {}\r\n\r\n",
+            id
+        );
+        client.write_all(raw.as_bytes()).await.unwrap();
+        let response = read_response(&mut client).await;
+        assert!(response.ends_with("\r\n\r\nalice"));
+        // reading the session doesn't set the cookie again
+        assert!(!response.contains("Set-Cookie"));
+        client
+            .write_all(b"GET /me HTTP/1.1\r\nHost: localhost\r\nCookie: sid=forged\r\n\r\n")
+            .await
+            .unwrap();
+        assert!(read_response(&mut client).await.ends_with("nobody"));
+    }
+
+    #[crate::test]
+    async fn success_health() {
+        // The health endpoint should answer GET and HEAD on its path only.
+        let health = || {
+            let mut app = Octane::new();
+            app.add(Octane::health("/healthz")).unwrap();
+            app
+        };
+        let response = request(
+            health(),
+            b"GET /healthz HTTP/1.1\r\nHost: localhost\r\n\r\n",
+        )
+        .await;
+        assert!(response.starts_with("HTTP/1.1 200"));
+        assert!(response.contains("Content-Type: application/json"));
+        assert!(response.ends_with(r#"{"status":"ok"}"#));
+        let response = request(
+            health(),
+            b"HEAD /healthz HTTP/1.1\r\nHost: localhost\r\n\r\n",
+        )
+        .await;
+        assert!(response.starts_with("HTTP/1.1 200"));
+        assert!(response.ends_with("\r\n\r\n"));
+        let response = request(
+            health(),
+            b"POST /healthz HTTP/1.1\r\nHost: localhost\r\n\r\n",
+        )
+        .await;
+        assert!(response.starts_with("HTTP/1.1 404"));
+        let response = request(health(), b"GET /other HTTP/1.1\r\nHost: localhost\r\n\r\n").await;
+        assert!(response.starts_with("HTTP/1.1 404"));
+    }
+
+    #[crate::test]
+    async fn success_metrics_endpoint() {
+        // The metrics endpoint should serve the prometheus text on its path.
+        let mut app = Octane::new();
+        app.settings.metrics = true;
+        let metrics = app.metrics_endpoint("/internal/metrics");
+        app.add(metrics).unwrap();
+        let raw = b"GET /internal/metrics HTTP/1.1\r\nHost: localhost\r\n\r\n";
+        let response = request(app, raw).await;
+        assert!(response.starts_with("HTTP/1.1 200"));
+        assert!(response.contains("octane_requests_total"));
+    }
+
+    #[crate::test]
+    async fn success_metrics() {
+        // Requests, status classes, bytes and connections should be counted.
+        let mut app = hello_app();
+        app.settings.metrics = true;
+        let handler = app.metrics_handler();
+        app.get("/metrics", handler).unwrap();
+        let app = Arc::new(app);
+        let (server, mut client) = socket_pair().await;
+        let served = Arc::clone(&app);
+        let handle = task!({
+            Octane::serve(server, served).await.ok();
+        });
+        let mut received = 0;
+        for _ in 0..2 {
+            client
+                .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n")
+                .await
+                .unwrap();
+            received += read_response(&mut client).await.len();
+        }
+        let snapshot = app.metrics_snapshot();
+        assert_eq!(snapshot.requests, 2);
+        assert_eq!(snapshot.responses_2xx, 2);
+        assert_eq!(snapshot.bytes_sent, received as u64);
+        assert_eq!(snapshot.active_connections, 1);
+        client
+            .write_all(b"GET /missing HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+            .await
+            .unwrap();
+        let mut rest = Vec::new();
+        client.read_to_end(&mut rest).await.unwrap();
+        handle.await.unwrap();
+        let snapshot = app.metrics_snapshot();
+        assert_eq!(snapshot.requests, 3);
+        assert_eq!(snapshot.responses_4xx, 1);
+        assert_eq!(snapshot.bytes_sent, (received + rest.len()) as u64);
+        assert_eq!(snapshot.active_connections, 0);
+        let response = request(
+            Arc::try_unwrap(app).ok().unwrap(),
+            b"GET /metrics HTTP/1.1\r\nHost: localhost\r\n\r\n",
+        )
+        .await;
+        assert!(response.contains("octane_requests_total 4\n"));
+        assert!(response.contains("octane_responses_total{class=\"4xx\"} 1\n"));
+    }
+
+    #[crate::test]
+    async fn success_metrics_disabled() {
+        // Nothing should be counted unless metrics are enabled.
+        let app = Arc::new(hello_app());
+        let (server, mut client) = socket_pair().await;
+        let served = Arc::clone(&app);
+        let handle = task!({
+            Octane::serve(server, served).await.ok();
+        });
+        client
+            .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .await
+            .unwrap();
+        read_response(&mut client).await;
+        AsyncWriteExt::shutdown(&mut client).await.unwrap();
+        handle.await.unwrap();
+        assert_eq!(app.metrics_snapshot(), Default::default());
+    }
+
+    #[crate::test]
+    async fn fail_static_dir_directory() {
+        // Requesting a directory from a static dir should 404.
+        let mut app = Octane::new();
+        app.add(Octane::static_dir(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/templates"
+        )))
+        .unwrap();
+        let response = request(app, b"GET /test/ HTTP/1.1\r\nHost: localhost\r\n\r\n").await;
+        assert!(response.starts_with("HTTP/1.1 404"));
+    }
+
+    #[crate::test]
+    async fn success_static_dirs_fallback() {
+        // A file missing from every static dir should go to the fallback.
+        let app = |fallback: bool| {
+            let mut app = Octane::new();
+            app.add(Octane::static_dir("templates")).unwrap();
+            app.add(Octane::static_dir("templates/test")).unwrap();
+            if fallback {
+                app.fallback(route_stop!(|req, res| res.send("fallback")));
+            }
+            app
+        };
+        let raw = b"GET /file.txt HTTP/1.1\r\nHost: localhost\r\n\r\n";
+        let file = std::fs::read_to_string("templates/test/file.txt").unwrap();
+        assert!(request(app(true), raw).await.ends_with(&file));
+        let raw = b"GET /missing.txt HTTP/1.1\r\nHost: localhost\r\n\r\n";
+        let response = request(app(true), raw).await;
+        assert!(response.starts_with("HTTP/1.1 200"));
+        assert!(response.ends_with("\r\n\r\nfallback"));
+        assert!(request(app(false), raw).await.starts_with("HTTP/1.1 404"));
+    }
+
+    #[crate::test]
+    async fn success_autoindex() {
+        // Directories should be listed once autoindex is enabled.
+        let app = || {
+            let mut app = Octane::new();
+            app.settings.autoindex = true;
+            app.add(Octane::static_dir("templates")).unwrap();
+            app
+        };
+        let response = request(app(), b"GET /test/ HTTP/1.1\r\nHost: localhost\r\n\r\n").await;
+        assert!(response.starts_with("HTTP/1.1 200"));
+        assert!(response.contains("Content-Type: text/html"));
+        assert!(response.contains("<a href=\"/test/file.txt\">file.txt</a>"));
+        assert!(response.contains("<a href=\"/test/nestedfolder/\">nestedfolder/</a>"));
+        assert!(response.contains("Vary: Accept\r\n"));
+        let raw = b"GET /test HTTP/1.1\r\nHost: localhost\r\nAccept: application/json\r\n\r\n";
+        let response = request(app(), raw).await;
+        assert!(response.contains("Content-Type: application/json"));
+        assert!(response.contains("Vary: Accept\r\n"));
+        assert!(response.ends_with("\r\n\r\n[\"file.txt\",\"nestedfolder/\"]"));
+    }
+
+    #[crate::test]
+    async fn success_autoindex_encoded_links() {
+        // Links to entries with reserved characters should be percent-encoded.
+        let dir = std::env::temp_dir().join(format!("octane-index-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("sub")).unwrap();
+        std::fs::write(dir.join("sub").join("a?b#c%d&e.txt"), "").unwrap();
+        let mut app = Octane::new();
+        app.settings.autoindex = true;
+        app.add(Octane::static_dir(&dir)).unwrap();
+        let raw = b"GET /sub/ HTTP/1.1\r\nHost: localhost\r\n\r\n";
+        let response = request(app, raw).await;
+        std::fs::remove_dir_all(&dir).ok();
+        assert!(response.starts_with("HTTP/1.1 200"));
+        assert!(response.contains("<a href=\"/sub/a%3Fb%23c%25d%26e.txt\">a?b#c%d&amp;e.txt</a>"));
+    }
+
+    #[crate::test]
+    async fn success_fallback() {
+        // The fallback should serve the index for any unmatched path.
+        let mut app = Octane::new();
+        app.get("/api", route_stop!(|req, res| res.send("api")))
+            .unwrap();
+        app.fallback(route_stop!(|req, res| {
+            res.send_file("templates/test.html").unwrap();
+        }));
+        let app = Arc::new(app);
+        let index = std::fs::read_to_string("templates/test.html").unwrap();
+        for path in &["/", "/users/1", "/deeply/nested/page"] {
+            let (server, mut client) = socket_pair().await;
+            let served = Arc::clone(&app);
+            task!({
+                Octane::serve(server, served).await.ok();
+            });
+            let raw = format!("GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path);
+            client.write_all(raw.as_bytes()).await.unwrap();
+            let response = read_response(&mut client).await;
+            assert!(response.starts_with("HTTP/1.1 200"));
+            assert!(response.contains("Content-Type: text/html; charset=utf-8\r\n"));
+            assert!(response.ends_with(&index));
+        }
+        let (server, mut client) = socket_pair().await;
+        let served = Arc::clone(&app);
+        task!({
+            Octane::serve(server, served).await.ok();
+        });
+        client
+            .write_all(b"GET /api HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .await
+            .unwrap();
+        assert!(read_response(&mut client).await.ends_with("api"));
+    }
+
+    // Writes a file of len pseudo random bytes to the temp dir
+    fn large_fixture(name: &str, len: usize) -> (std::path::PathBuf, Vec<u8>) {
+        let path = std::env::temp_dir().join(format!("octane-{}-{}", name, std::process::id()));
+        let mut seed: u32 = 0x9e37_79b9;
+        let data: Vec<u8> = (0..len)
+            .map(|_| {
+                seed ^= seed << 13;
+                seed ^= seed >> 17;
+                seed ^= seed << 5;
+                seed as u8
+            })
+            .collect();
+        std::fs::write(&path, &data).unwrap();
+        (path, data)
+    }
+
+    // Serves the file at path on / and reads the whole response
+    async fn fetch_file(path: &std::path::Path) -> (String, Vec<u8>) {
+        let mut app = Octane::new();
+        let file = path.to_str().unwrap().to_owned();
+        app.get(
+            "/",
+            route_stop!(|req, res| {
+                res.send_file(&file).unwrap().unwrap();
+            }),
+        )
+        .unwrap();
+        let (server, mut client) = socket_pair().await;
+        let app = Arc::new(app);
+        task!({
+            Octane::serve(server, app).await.ok();
+        });
+        client
+            .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+            .await
+            .unwrap();
+        let mut data = Vec::new();
+        client.read_to_end(&mut data).await.unwrap();
+        let end = find_in_slice(&data, b"\r\n\r\n").unwrap();
+        let head = String::from_utf8_lossy(&data[..end]).into_owned();
+        (head, data.split_off(end + 4))
+    }
+
+    #[crate::test]
+    async fn success_large_file() {
+        // Large files should be streamed to the client unchanged.
+        let (path, expected) = large_fixture("large-file", 8 * 1024 * 1024 + 7);
+        let (head, body) = fetch_file(&path).await;
+        std::fs::remove_file(&path).unwrap();
+        assert!(head.starts_with("HTTP/1.1 200"));
+        assert!(head.contains(&format!("Content-Length: {}\r\n", expected.len())));
+        assert!(body == expected);
+    }
+
+    // Rough throughput of serving a large file, run with
+    // `cargo test --release -- --ignored bench_ --nocapture`
+    #[crate::test]
+    #[ignore]
+    async fn bench_large_file() {
+        let len = 64 * 1024 * 1024;
+        let (path, _) = large_fixture("bench-file", len);
+        let rounds = 10;
+        let start = Instant::now();
+        for _ in 0..rounds {
+            let (_, body) = fetch_file(&path).await;
+            assert_eq!(body.len(), len);
+        }
+        let elapsed = start.elapsed();
+        std::fs::remove_file(&path).unwrap();
+        println!(
+            "{:?} per file, {:.1} MiB/s",
+            elapsed / rounds,
+            (len as f64 * rounds as f64) / elapsed.as_secs_f64() / (1024.0 * 1024.0)
+        );
+    }
+
+    #[crate::test]
+    async fn success_static_accept_ranges() {
+        // Static files should advertise byte ranges but generated bodies shouldn't.
+        let app = || {
+            let mut app = Octane::new();
+            app.get("/api", route_stop!(|req, res| res.send("api")))
+                .unwrap();
+            app.add(Octane::static_dir("templates")).unwrap();
+            app
+        };
+        let response = request(app(), b"GET /test.html HTTP/1.1\r\nHost: localhost\r\n\r\n").await;
+        assert!(response.starts_with("HTTP/1.1 200"));
+        assert!(response.contains("Accept-Ranges: bytes\r\n"));
+        let response = request(app(), b"GET /api HTTP/1.1\r\nHost: localhost\r\n\r\n").await;
+        assert!(response.starts_with("HTTP/1.1 200"));
+        assert!(!response.contains("Accept-Ranges"));
+    }
+
+    #[crate::test]
+    async fn success_file_route() {
+        // A single file route should serve the file with its caching headers.
+        let app = || {
+            let mut app = Octane::new();
+            app.file("/page", "templates/test.html").unwrap();
+            app.file("/gone", "templates/missing.html").unwrap();
+            app
+        };
+        let response = request(app(), b"GET /page HTTP/1.1\r\nHost: localhost\r\n\r\n").await;
+        let contents = std::fs::read_to_string("templates/test.html").unwrap();
+        assert!(response.starts_with("HTTP/1.1 200"));
+        assert!(response.contains("Content-Type: text/html"));
+        assert!(response.contains("ETag: "));
+        assert!(response.contains("Last-Modified: "));
+        assert!(response.ends_with(&contents));
+        let response = request(app(), b"GET /gone HTTP/1.1\r\nHost: localhost\r\n\r\n").await;
+        assert!(response.starts_with("HTTP/1.1 404"));
+    }
+
+    #[crate::test]
+    async fn success_unknown_file_type() {
+        // Files with unknown extensions should use the configured type.
+        let path = std::env::temp_dir().join(format!("octane-{}.unknown", std::process::id()));
+        std::fs::write(&path, "plain").unwrap();
+        let app = |unknown_file_type: Option<&str>| {
+            let mut app = Octane::new();
+            app.settings.unknown_file_type = unknown_file_type.map(str::to_owned);
+            let file = path.to_str().unwrap().to_owned();
+            app.get(
+                "/",
+                route_stop!(|req, res| {
+                    res.send_file(&file).unwrap().unwrap();
+                }),
+            )
+            .unwrap();
+            app
+        };
+        let raw = b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n";
+        let default = request(app(None), raw).await;
+        let configured = request(app(Some("text/plain")), raw).await;
+        std::fs::remove_file(&path).unwrap();
+        assert!(default.contains("Content-Type: application/octet-stream\r\n"));
+        assert!(configured.contains("Content-Type: text/plain; charset=utf-8\r\n"));
+        assert!(configured.ends_with("plain"));
+    }
+
+    #[crate::test]
+    async fn success_empty_responses() {
+        // The empty body helpers should set the status and skip later closures.
+        let app = || {
+            let mut app = Octane::new();
+            app.get(
+                "/no-content",
+                route_next!(|req, res| {
+                    res.send("a body");
+                    res.no_content();
+                }),
+            )
+            .unwrap();
+            app.get("/reset", route_next!(|req, res| res.reset_content()))
+                .unwrap();
+            app.get(
+                "/not-modified",
+                route_next!(|req, res| {
+                    res.set("ETag", "\"abc\"").not_modified();
+                }),
+            )
+            .unwrap();
+            app.add(Octane::static_dir("templates")).unwrap();
+            app
+        };
+        let get = |path: &str| format!("GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path);
+        let response = request(app(), get("/no-content").as_bytes()).await;
+        assert!(response.starts_with("HTTP/1.1 204 NO CONTENT\r\n"));
+        assert!(!response.contains("Content-Length"));
+        assert!(!response.contains("Content-Type"));
+        assert!(response.ends_with("\r\n\r\n"));
+        let response = request(app(), get("/reset").as_bytes()).await;
+        assert!(response.starts_with("HTTP/1.1 205 RESET CONTENT\r\n"));
+        assert!(response.contains("Content-Length: 0\r\n"));
+        assert!(response.ends_with("\r\n\r\n"));
+        let response = request(app(), get("/not-modified").as_bytes()).await;
+        assert!(response.starts_with("HTTP/1.1 304 NOT MODIFIED\r\n"));
+        assert!(response.contains("ETag: \"abc\"\r\n"));
+        assert!(!response.contains("Content-Length"));
+        assert!(response.ends_with("\r\n\r\n"));
+    }
+
+    #[crate::test]
+    async fn success_raw_head() {
+        // The head should be captured as received only when asked for.
+        let app = |capture_raw_head| {
+            let mut app = Octane::new();
+            app.settings.capture_raw_head = capture_raw_head;
+            app.post(
+                "/echo",
+                route_stop!(|req, res| match req.raw_head() {
+                    Some(head) => res.send(head.to_vec()),
+                    None => res.send("none"),
+                }),
+            )
+            .unwrap();
+            app
+        };
+        let head = "POST //echo HTTP/1.1\r\nHost: localhost\r\nX-Odd:  spaced \r\nContent-Length: 4\r\n\r\n";
+        let raw = format!("{}body", head);
+        let response = request(app(true), raw.as_bytes()).await;
+        assert!(response.starts_with("HTTP/1.1 200"));
+        assert!(response.ends_with(&format!("\r\n\r\n{}", head)));
+        let response = request(app(false), raw.as_bytes()).await;
+        assert!(response.ends_with("\r\n\r\nnone"));
+    }
+
+    #[crate::test]
+    async fn success_normalized_paths() {
+        // Paths should be normalized before they are matched.
+        let app = || {
+            let mut app = Octane::new();
+            app.get("/foo/bar", route_stop!(|req, res| res.send("bar")))
+                .unwrap();
+            app
+        };
+        for path in &["/foo//bar", "/foo/./bar", "/foo/baz/../bar"] {
+            let raw = format!("GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path);
+            let response = request(app(), raw.as_bytes()).await;
+            assert!(response.starts_with("HTTP/1.1 200"), "{}", path);
+            assert!(response.ends_with("bar"));
+        }
+        let raw = b"GET /../foo/bar HTTP/1.1\r\nHost: localhost\r\n\r\n";
+        let response = request(app(), raw).await;
+        assert!(response.starts_with("HTTP/1.1 400"));
+    }
+
+    #[crate::test]
+    async fn success_case_insensitive_paths() {
+        // Paths should only match in another case when the setting is on.
+        let app = |case_insensitive_paths| {
+            let mut app = Octane::new();
+            app.settings.case_insensitive_paths = case_insensitive_paths;
+            app.get("/users", route_stop!(|req, res| res.send("users")))
+                .unwrap();
+            app
+        };
+        let raw = b"GET /Users HTTP/1.1\r\nHost: localhost\r\n\r\n";
+        let response = request(app(true), raw).await;
+        assert!(response.starts_with("HTTP/1.1 200"));
+        assert!(response.ends_with("users"));
+        let response = request(app(false), raw).await;
+        assert!(response.starts_with("HTTP/1.1 404"));
+    }
+
+    #[crate::test]
+    async fn success_case_insensitive_registered_paths() {
+        // Routes registered in uppercase should match, keeping the case of variables.
+        let dir = std::env::temp_dir().join(format!("octane-case-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("README.txt"), "readme").unwrap();
+        let app = || {
+            let mut app = Octane::new();
+            app.settings.case_insensitive_paths = true;
+            app.get("/Users", route_stop!(|req, res| res.send("users")))
+                .unwrap();
+            #[cfg(feature = "url_variables")]
+            app.get(
+                "/posts/:slug",
+                route_stop!(|req, res| res.send(req.vars["slug"])),
+            )
+            .unwrap();
+            app.add(Octane::static_dir(&dir)).unwrap();
+            app
+        };
+        let get = |path: &str| format!("GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path);
+        let response = request(app(), get("/users").as_bytes()).await;
+        assert!(response.ends_with("\r\n\r\nusers"));
+        let response = request(app(), get("/USERS").as_bytes()).await;
+        assert!(response.ends_with("\r\n\r\nusers"));
+        #[cfg(feature = "url_variables")]
+        {
+            let response = request(app(), get("/Posts/Hello-World").as_bytes()).await;
+            assert!(response.ends_with("\r\n\r\nHello-World"));
+        }
+        let response = request(app(), get("/README.txt").as_bytes()).await;
+        assert!(response.starts_with("HTTP/1.1 200"));
+        assert!(response.ends_with("\r\n\r\nreadme"));
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[crate::test]
+    async fn fail_strict_utf8() {
+        // Invalid text bodies should only be rejected in strict mode.
+        let raw = |content_type: &str| {
+            let mut raw = format!(
+                "POST / HTTP/1.1\r\nHost: localhost\r\nContent-Type: {}\r\nContent-Length: 3\r\n\r\n",
+                content_type
+            )
+            .into_bytes();
+            raw.extend_from_slice(b"a\xffb");
+            raw
+        };
+        let strict = || {
+            let mut app = echo_app();
+            app.settings.strict_utf8 = true;
+            app
+        };
+        let response = request(echo_app(), &raw("text/plain")).await;
+        assert!(response.starts_with("HTTP/1.1 200"));
+        let response = request(strict(), &raw("text/plain; charset=utf-8")).await;
+        assert!(response.starts_with("HTTP/1.1 400"));
+        assert!(response.contains("not valid UTF-8"));
+        let response = request(strict(), &raw("application/json")).await;
+        assert!(response.starts_with("HTTP/1.1 400"));
+        let response = request(strict(), &raw("application/octet-stream")).await;
+        assert!(response.starts_with("HTTP/1.1 200"));
+    }
+
+    #[crate::test]
+    async fn fail_without_static_dir() {
+        // Files should never be served from disk without a static dir.
+        let mut app = Octane::new();
+        app.fallback(route_stop!(|req, res| {
+            res.status(StatusCode::NotFound).send("custom");
+        }));
+        for path in &["/templates/test.html", "/test.html", "/Cargo.toml"] {
+            let raw = format!("GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path);
+            let response = request(Octane::new(), raw.as_bytes()).await;
+            assert!(response.starts_with("HTTP/1.1 404"));
+            assert!(!response.contains("Accept-Ranges"));
+        }
+        let response = request(app, b"GET /test.html HTTP/1.1\r\nHost: localhost\r\n\r\n").await;
+        assert!(response.starts_with("HTTP/1.1 404"));
+        assert!(response.ends_with("custom"));
+    }
+
+    #[crate::test]
+    async fn success_keep_alive_after_not_found() {
+        // A 404 shouldn't close a keep-alive connection.
+        let (server, mut client) = socket_pair().await;
+        task!({
+            Octane::serve(server, Arc::new(hello_app())).await.ok();
+        });
+        client
+            .write_all(b"GET /missing HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .await
+            .unwrap();
+        let response = read_response(&mut client).await;
+        assert!(response.starts_with("HTTP/1.1 404"));
+        assert!(response.contains("Connection: keep-alive\r\n"));
+        client
+            .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .await
+            .unwrap();
+        let response = read_response(&mut client).await;
+        assert!(response.starts_with("HTTP/1.1 200"));
+        assert!(response.ends_with("Hello, World"));
+    }
+
+    #[crate::test]
+    async fn fail_not_found_head() {
+        // The 404 to a HEAD request should keep its length but not its
+        // body, and leave the connection open.
+        let (server, mut client) = socket_pair().await;
+        task!({
+            Octane::serve(server, Arc::new(hello_app())).await.ok();
+        });
+        client
+            .write_all(b"HEAD /missing HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .await
+            .unwrap();
+        client
+            .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .await
+            .unwrap();
+        AsyncWriteExt::shutdown(&mut client).await.unwrap();
+        let mut response = Vec::new();
+        client.read_to_end(&mut response).await.unwrap();
+        let response = String::from_utf8_lossy(&response);
+        let (head, rest) = response.split_at(response.find("\r\n\r\n").unwrap() + 4);
+        assert!(head.starts_with("HTTP/1.1 404"));
+        assert!(head.contains("Connection: keep-alive\r\n"));
+        assert!(!head.contains("Content-Length: 0\r\n"));
+        assert!(head.contains("Content-Length: "));
+        assert!(rest.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(rest.ends_with("Hello, World"));
+    }
+
+    #[crate::test]
+    async fn fail_not_found_json() {
+        // Clients preferring json should get the 404 in the json envelope.
+        let raw = b"GET /missing HTTP/1.1\r\nHost: localhost\r\nAccept: application/json\r\n\r\n";
+        let response = request(hello_app(), raw).await;
+        assert!(response.starts_with("HTTP/1.1 404"));
+        assert!(response.contains("Content-Type: application/json\r\n"));
+        let body = &response[response.find("\r\n\r\n").unwrap() + 4..];
+        let value = crate::json::Value::parse(body).unwrap();
+        let error = value.as_object().unwrap()["error"].as_object().unwrap();
+        assert_eq!(error["code"], crate::json::Value::Integer(404));
+        assert_eq!(
+            error["message"],
+            crate::json::Value::String("Not Found".to_owned())
+        );
+        let raw = b"GET /missing HTTP/1.1\r\nHost: localhost\r\nAccept: text/html, */*\r\n\r\n";
+        let response = request(hello_app(), raw).await;
+        assert!(response.contains("Content-Type: text/html; charset=utf-8\r\n"));
+    }
+
+    #[crate::test]
+    async fn fail_not_found_template() {
+        // The 404 page should be rendered from the template with the path.
+        let mut app = hello_app();
+        app.settings.error_template =
+            Some("<h1>{{code}} {{status}}</h1><p>{{path}} is gone{{reason}}</p>".to_owned());
+        let raw = b"GET /missing/<b> HTTP/1.1\r\nHost: localhost\r\n\r\n";
+        let response = request(app, raw).await;
+        assert!(response.starts_with("HTTP/1.1 404"));
+        assert!(response.contains("Content-Type: text/html; charset=utf-8\r\n"));
+        let status = StatusCode::NotFound.to_string();
+        assert!(response.ends_with(&format!(
+            "<h1>404 {}</h1><p>/missing/&lt;b&gt; is gone</p>",
+            status
+        )));
+    }
+
+    #[crate::test]
+    async fn success_any() {
+        // The catch all should answer every method and path the routes left.
+        let app = || {
+            let mut app = Octane::new();
+            app.get("/health", route_stop!(|req, res| res.send("ok")))
+                .unwrap();
+            app.fallback(route_stop!(|req, res| res.send("fallback")));
+            app.any(route_next!(|req, res| {
+                res.send(format!(
+                    "{} /{}",
+                    req.request_line.method.as_str(),
+                    req.request_line.path.chunks.join("/")
+                ));
+            }));
+            app
+        };
+        let raw = b"GET /health HTTP/1.1\r\nHost: localhost\r\n\r\n";
+        assert!(request(app(), raw).await.ends_with("\r\n\r\nok"));
+        for (method, path) in &[
+            ("GET", "/"),
+            ("POST", "/health"),
+            ("DELETE", "/a/b"),
+            ("PROPFIND", "/dav/files"),
+        ] {
+            let raw = format!(
+                "{} {} HTTP/1.1\r\nHost: localhost\r\nContent-Length: 0\r\n\r\n",
+                method, path
+            );
+            let response = request(app(), raw.as_bytes()).await;
+            assert!(response.starts_with("HTTP/1.1 200"), "{}", method);
+            assert!(response.ends_with(&format!("\r\n\r\n{} {}", method, path)));
+        }
+        // a response without a body is still final
+        let mut app = Octane::new();
+        app.any(route_next!(|req, res| {
+            res.status(StatusCode::Accepted);
+        }));
+        let raw = b"GET /missing HTTP/1.1\r\nHost: localhost\r\n\r\n";
+        assert!(request(app, raw).await.starts_with("HTTP/1.1 202"));
+    }
+
+    #[crate::test]
+    async fn fail_fallback_without_body() {
+        // A fallback which doesn't respond should still lead to a 404.
+        let mut app = Octane::new();
+        app.fallback(route_next!(|req, res| {}));
+        let response = request(app, b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n").await;
+        assert!(response.starts_with("HTTP/1.1 404"));
+    }
+
+    #[crate::test]
+    async fn success_sse() {
+        // Events should be framed and the connection closed afterwards.
+        let mut app = Octane::new();
+        app.get(
+            "/events",
+            route_stop!(|req, res| {
+                let events = res.sse();
+                std::thread::spawn(move || {
+                    events.send("first");
+                    std::thread::sleep(Duration::from_millis(10));
+                    events.event("update", "second");
+                });
+            }),
+        )
+        .unwrap();
+        let (server, mut client) = socket_pair().await;
+        let handle = task!({
+            Octane::serve(server, Arc::new(app)).await.ok();
+        });
+        client
+            .write_all(b"GET /events HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .await
+            .unwrap();
+        let mut response = Vec::new();
+        timeout(Duration::from_secs(1), client.read_to_end(&mut response))
+            .await
+            .expect("connection wasn't closed")
+            .unwrap();
+        let response = String::from_utf8(response).unwrap();
+        assert!(response.contains("Content-Type: text/event-stream\r\n"));
+        assert!(response.contains("Connection: close\r\n"));
+        assert!(!response.contains("Content-Length"));
+        assert!(response.ends_with("\r\n\r\ndata: first\n\nevent: update\ndata: second\n\n"));
+        handle.await.unwrap();
+    }
+
+    #[crate::test]
+    async fn success_body_writer() {
+        // Chunks written from another thread should make up the body.
+        let mut app = Octane::new();
+        app.get(
+            "/listing",
+            route_stop!(|req, res| {
+                let mut body = res.with_type("application/xml").body_writer();
+                std::thread::spawn(move || {
+                    use std::io::Write;
+                    write!(body, "<multistatus>").unwrap();
+                    for i in 0..3 {
+                        write!(body, "<response>{}</response>", i).unwrap();
+                    }
+                    write!(body, "</multistatus>").unwrap();
+                });
+            }),
+        )
+        .unwrap();
+        let (server, mut client) = socket_pair().await;
+        let handle = task!({
+            Octane::serve(server, Arc::new(app)).await.ok();
+        });
+        client
+            .write_all(b"GET /listing HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .await
+            .unwrap();
+        let mut response = Vec::new();
+        timeout(Duration::from_secs(1), client.read_to_end(&mut response))
+            .await
+            .expect("connection wasn't closed")
+            .unwrap();
+        let response = String::from_utf8(response).unwrap();
+        assert!(response.contains("Content-Type: application/xml\r\n"));
+        assert!(response.contains("Connection: close\r\n"));
+        assert!(!response.contains("Content-Length"));
+        assert!(response.ends_with(
+            "\r\n\r\n<multistatus><response>0</response><response>1</response>\
+             <response>2</response></multistatus>"
+        ));
+        handle.await.unwrap();
+    }
+
+    #[crate::test]
+    async fn success_static_dir() {
+        // Files in the static dir should be served with their mime type.
+        let mut app = Octane::new();
+        app.add(Octane::static_dir(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/templates/"
+        )))
+        .unwrap();
+        let response = request(app, b"GET /test.css HTTP/1.1\r\nHost: localhost\r\n\r\n").await;
+        let css = std::fs::read_to_string("templates/test.css").unwrap();
+        assert!(response.starts_with("HTTP/1.1 200"));
+        assert!(response.contains("Content-Type: text/css; charset=utf-8\r\n"));
+        assert!(response.ends_with(&css));
+    }
+
+    #[crate::test]
+    async fn success_static_cache_control() {
+        // Static files should get the configured Cache-Control with validators.
+        let app = || {
+            let mut app = Octane::new();
+            app.set_static_cache_control("public, max-age=31536000, immutable");
+            app.get(
+                "/test.html",
+                route_next!(|req, res| {
+                    res.set("Cache-Control", "no-cache");
+                }),
+            )
+            .unwrap();
+            app.add(Octane::static_dir(concat!(
+                env!("CARGO_MANIFEST_DIR"),
+                "/templates/"
+            )))
+            .unwrap();
+            app.file("/style.css", "templates/test.css").unwrap();
+            app
+        };
+        for path in &["/test.css", "/style.css"] {
+            let raw = format!("GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path);
+            let response = request(app(), raw.as_bytes()).await;
+            assert!(response.starts_with("HTTP/1.1 200"));
+            assert!(response.contains("Cache-Control: public, max-age=31536000, immutable\r\n"));
+            assert!(response.contains("ETag: "));
+            assert!(response.contains("Last-Modified: "));
+        }
+        let raw = b"GET /test.html HTTP/1.1\r\nHost: localhost\r\n\r\n";
+        let response = request(app(), raw).await;
+        assert!(response.starts_with("HTTP/1.1 200"));
+        assert!(response.contains("Cache-Control: no-cache\r\n"));
+        assert!(!response.contains("immutable"));
+    }
+
+    #[crate::test]
+    async fn success_static_dir_nested() {
+        // Nested files should be found with a runtime path.
+        let mut app = Octane::new();
+        let dir = std::env::current_dir().unwrap().join("templates");
+        app.add(Octane::static_dir(dir)).unwrap();
+        let response = request(
+            app,
+            b"GET /test/file.txt HTTP/1.1\r\nHost: localhost\r\n\r\n",
+        )
+        .await;
+        let text = std::fs::read_to_string("templates/test/file.txt").unwrap();
+        assert!(response.contains("Content-Type: text/plain; charset=utf-8\r\n"));
+        assert!(response.ends_with(&text));
+    }
+
+    #[crate::test]
+    async fn success_static_dir_fallthrough() {
+        // Each root should be tried in order before giving up.
+        let root = std::env::current_dir().unwrap().join("templates/test");
+        let static_app = || {
+            let mut app = Octane::new();
+            app.add(Octane::static_dir(root.join("nestedfolder")))
+                .unwrap();
+            app.add(Octane::static_dir(root.clone())).unwrap();
+            app
+        };
+        let response = request(
+            static_app(),
+            b"GET /file.txt HTTP/1.1\r\nHost: localhost\r\n\r\n",
+        )
+        .await;
+        let text = std::fs::read_to_string("templates/test/file.txt").unwrap();
+        assert!(response.ends_with(&text));
+        let response = request(
+            static_app(),
+            b"GET /nested-file.txt HTTP/1.1\r\nHost: localhost\r\n\r\n",
+        )
+        .await;
+        let text = std::fs::read_to_string("templates/test/nestedfolder/nested-file.txt").unwrap();
+        assert!(response.ends_with(&text));
+        let response = request(
+            static_app(),
+            b"GET /missing.txt HTTP/1.1\r\nHost: localhost\r\n\r\n",
+        )
+        .await;
+        assert!(response.starts_with("HTTP/1.1 404"));
+    }
+
+    #[crate::test]
+    async fn fail_invalid_target() {
+        // Targets escaping the root should be a bad request.
+        let response = request(
+            hello_app(),
+            b"GET /../etc HTTP/1.1\r\nHost: localhost\r\n\r\n",
+        )
+        .await;
+        assert!(response.starts_with("HTTP/1.1 400"));
+    }
+
+    #[crate::test]
+    #[cfg(feature = "faithful")]
+    async fn fail_asterisk_target() {
+        // Only OPTIONS can use the asterisk target.
+        let response = request(hello_app(), b"GET * HTTP/1.1\r\nHost: localhost\r\n\r\n").await;
+        assert!(response.starts_with("HTTP/1.1 400"));
+    }
+
+    #[crate::test]
+    async fn fail_request_line_too_long() {
+        // An oversized request line should be rejected before it ends.
+        let mut app = hello_app();
+        app.settings.max_request_line = Some(64);
+        let (server, mut client) = socket_pair().await;
+        let handle = task!({
+            Octane::serve(server, Arc::new(app)).await.ok();
+        });
+        // no CRLF is ever sent, the server must not wait for one
+        let raw = format!("GET /{} HTTP/1.1", "a".repeat(100));
+        client.write_all(raw.as_bytes()).await.unwrap();
+        let mut response = Vec::new();
+        timeout(Duration::from_secs(1), client.read_to_end(&mut response))
+            .await
+            .expect("connection wasn't closed")
+            .unwrap();
+        assert!(String::from_utf8(response)
+            .unwrap()
+            .starts_with("HTTP/1.1 414"));
+        handle.await.unwrap();
+    }
+
+    #[crate::test]
+    async fn fail_path_too_deep() {
+        // Paths with more segments than allowed should get a 414.
+        let app = || {
+            let mut app = Octane::new();
+            app.settings.max_path_segments = Some(8);
+            app.add(route_stop!(|req, res| res.send("deep enough")))
+                .unwrap();
+            app
+        };
+        let deep = |depth| {
+            format!(
+                "GET {}?a=/b/c/d/e/f/g/h/i HTTP/1.1\r\nHost: localhost\r\n\r\n",
+                "/a".repeat(depth)
+            )
+        };
+        let response = request(app(), deep(8).as_bytes()).await;
+        assert!(response.starts_with("HTTP/1.1 200"));
+        let response = request(app(), deep(9).as_bytes()).await;
+        assert!(response.starts_with("HTTP/1.1 414"));
+        assert!(response.contains("The request path has too many segments"));
+        // the path is rejected before the request line ends
+        let (server, mut client) = socket_pair().await;
+        let handle = task!({
+            Octane::serve(server, Arc::new(hello_app())).await.ok();
+        });
+        let raw = format!("GET {}", "/a".repeat(200));
+        client.write_all(raw.as_bytes()).await.unwrap();
+        let mut response = Vec::new();
+        timeout(Duration::from_secs(1), client.read_to_end(&mut response))
+            .await
+            .expect("connection wasn't closed")
+            .unwrap();
+        assert!(String::from_utf8(response)
+            .unwrap()
+            .starts_with("HTTP/1.1 414"));
+        handle.await.unwrap();
+    }
+
+    #[crate::test]
+    async fn fail_headers_too_large() {
+        // Oversized headers should get their own status.
+        let mut app = hello_app();
+        app.settings.max_header_size = Some(64);
+        let raw = format!(
+            "GET / HTTP/1.1\r\nHost: localhost\r\nX-Big: {}\r\n\r\n",
+            "a".repeat(100)
+        );
+        let response = request(app, raw.as_bytes()).await;
+        assert!(response.starts_with("HTTP/1.1 431"));
+        assert!(response.contains("The request headers are too large"));
+    }
+
+    #[crate::test]
+    async fn fail_too_many_headers() {
+        // Requests over the header count should get a 431.
+        let mut app = hello_app();
+        app.settings.max_headers = Some(4);
+        let mut raw = String::from("GET / HTTP/1.1\r\nHost: localhost\r\n");
+        for i in 0..4 {
+            raw.push_str(&format!("X-Header-{}: {}\r\n", i, i));
+        }
+        raw.push_str("\r\n");
+        let response = request(app, raw.as_bytes()).await;
+        assert!(response.starts_with("HTTP/1.1 431"));
+        assert!(response.contains("The request has too many headers"));
+    }
+
+    #[crate::test]
+    async fn success_headers_within_count() {
+        // Requests up to the header count should be served.
+        let mut app = hello_app();
+        app.settings.max_headers = Some(2);
+        let raw = b"GET / HTTP/1.1\r\nHost: localhost\r\nX-Header: 1\r\n\r\n";
+        let response = request(app, raw).await;
+        assert!(response.starts_with("HTTP/1.1 200"));
+    }
+
+    #[crate::test]
+    async fn fail_bad_request_reasons() {
+        // Every kind of malformed request should say what was wrong.
+        let cases: [(&[u8], &str); 3] = [
+            (
+                b"GET\r\nHost: localhost\r\n\r\n",
+                "The request line is malformed",
+            ),
+            (
+                b"GET / HTTP/1.1\r\nHost localhost\r\n\r\n",
+                "The request headers are malformed",
+            ),
+            (
+                b"GET /\xff HTTP/1.1\r\nHost: localhost\r\n\r\n",
+                "The request head is not valid UTF-8",
+            ),
+        ];
+        for (raw, reason) in cases.iter() {
+            let response = request(hello_app(), raw).await;
+            assert!(response.starts_with("HTTP/1.1 400"));
+            assert!(response.contains(reason), "{}", response);
+        }
+    }
+
+    #[crate::test]
+    async fn success_head_from_get() {
+        // A HEAD request should run the GET route without the body.
+        let mut app = Octane::new();
+        app.get("/page", route_stop!(|req, res| res.send("Hello, World")))
+            .unwrap();
+        let response = request(app, b"HEAD /page HTTP/1.1\r\nHost: localhost\r\n\r\n").await;
+        assert!(response.starts_with("HTTP/1.1 200"));
+        assert!(response.contains("Content-Length: 12\r\n"));
+        assert!(response.ends_with("\r\n\r\n"));
+    }
+
+    #[crate::test]
+    async fn success_head_route_preferred() {
+        // A HEAD route should win over the GET one.
+        let mut app = Octane::new();
+        app.get("/page", route_stop!(|req, res| res.send("get")))
+            .unwrap();
+        app.head(
+            "/page",
+            route_stop!(|req, res| {
+                res.set("X-Head", "yes").send("head");
+            }),
+        )
+        .unwrap();
+        let response = request(app, b"HEAD /page HTTP/1.1\r\nHost: localhost\r\n\r\n").await;
+        assert!(response.contains("X-Head: yes\r\n"));
+        assert!(response.contains("Content-Length: 4\r\n"));
+        assert!(response.ends_with("\r\n\r\n"));
+    }
+
+    #[test]
+    fn success_from_config() {
+        // The config should be used as is, with no routes.
+        let mut config = OctaneConfig::new();
+        config.keep_alive = None;
+        config.max_body_size = Some(16);
+        config.ssl.key("key.pem").cert("cert.pem");
+        let app = Octane::from_config(config);
+        assert_eq!(app.settings.keep_alive, None);
+        assert_eq!(app.settings.max_body_size, Some(16));
+        assert_eq!(app.settings.ssl.key, std::path::PathBuf::from("key.pem"));
+        assert!(app.routes().is_empty());
+    }
+
+    #[crate::test]
+    #[cfg(feature = "extended_queries")]
+    async fn success_extended_query() {
+        // The query should be parsed apart from the routed path.
+        let mut app = Octane::new();
+        app.get(
+            "/search",
+            route_stop!(|req, res| {
+                let query = req.get_extended_query();
+                let tags = query["tag"].get_arr().unwrap().join(",");
+                res.send(format!(
+                    "{} {}",
+                    tags,
+                    query["p"].get_obj().unwrap()["page"]
+                ));
+            }),
+        )
+        .unwrap();
+        let response = request(
+            app,
+            b"GET /search?tag=a&tag=b&p[page]=2 HTTP/1.1\r\nHost: localhost\r\n\r\n",
+        )
+        .await;
+        assert!(response.starts_with("HTTP/1.1 200"));
+        assert!(response.ends_with("a,b 2"));
+    }
+
+    #[crate::test]
+    async fn success_chunked_trailers() {
+        // A chunked body should be decoded and its trailers readable.
+        let mut app = Octane::new();
+        app.post(
+            "/",
+            route_stop!(|req, res| {
+                let checksum = req.trailers().get("x-checksum").cloned();
+                let body = String::from_utf8_lossy(req.body).into_owned();
+                res.send(format!("{} {}", body, checksum.unwrap_or_default()));
+            }),
+        )
+        .unwrap();
+        let raw = b"POST / HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: chunked\r\n\
+            Trailer: X-Checksum\r\n\r\n5\r\nHello\r\n6\r\n World\r\n0\r\nX-Checksum: 1234\r\n\r\n";
+        let response = request(app, raw).await;
+        assert!(response.starts_with("HTTP/1.1 200"));
+        assert!(response.ends_with("Hello World 1234"));
+    }
+
+    #[crate::test]
+    #[cfg(feature = "faithful")]
+    async fn success_http09() {
+        // Simple requests should get the bare body when they are enabled.
+        let mut app = hello_app();
+        app.settings.http09 = true;
+        let (server, mut client) = socket_pair().await;
+        let handle = task!({
+            Octane::serve(server, Arc::new(app)).await.ok();
+        });
+        client.write_all(b"GET /\r\n").await.unwrap();
+        let mut response = Vec::new();
+        timeout(Duration::from_secs(1), client.read_to_end(&mut response))
+            .await
+            .expect("connection wasn't closed")
+            .unwrap();
+        assert_eq!(response, b"Hello, World");
+        handle.await.unwrap();
+        let mut app = hello_app();
+        app.settings.http09 = true;
+        let response = request(app, b"POST /\r\n").await;
+        assert!(response.starts_with("HTTP/1.1 400"));
+    }
+
+    #[crate::test]
+    #[cfg(feature = "faithful")]
+    async fn fail_http09() {
+        // Simple requests should be rejected right away by default.
+        let response = request(hello_app(), b"GET /\r\n").await;
+        assert!(response.starts_with("HTTP/1.1 400"));
+        assert!(response.contains("HTTP/0.9 requests are not supported"));
+    }
+
+    #[crate::test]
+    async fn fail_unknown_transfer_coding() {
+        // Transfer codings other than chunked aren't implemented.
+        let raw = b"POST / HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: gzip\r\n\r\n";
+        let response = request(echo_app(), raw).await;
+        assert!(response.starts_with("HTTP/1.1 501"));
+        let raw = b"POST / HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: br, chunked\r\n\r\n0\r\n\r\n";
+        let response = request(echo_app(), raw).await;
+        assert!(response.starts_with("HTTP/1.1 501"));
+    }
+
+    #[crate::test]
+    async fn fail_unknown_method() {
+        // Unknown methods should be named in the 501 page and close the connection.
+        let (server, mut client) = socket_pair().await;
+        task!({
+            Octane::serve(server, Arc::new(hello_app())).await.ok();
+        });
+        client
+            .write_all(b"FOOBAR / HTTP/1.1\r\nHost: localhost\r\n\r\nGET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+            .await
+            .unwrap();
+        let mut data = Vec::new();
+        client.read_to_end(&mut data).await.unwrap();
+        let response = String::from_utf8(data).unwrap();
+        assert!(response.starts_with("HTTP/1.1 501"));
+        assert!(response.contains("The FOOBAR method is not implemented"));
+        // the connection stays usable for the pipelined request
+        assert_eq!(response.matches("HTTP/1.1").count(), 2);
+        assert!(response.ends_with("Hello, World"));
+        let response = request(hello_app(), b"A&B / HTTP/1.1\r\nHost: localhost\r\n\r\n").await;
+        assert!(response.starts_with("HTTP/1.1 501"));
+        assert!(response.contains("The A&amp;B method"));
+    }
+
+    #[crate::test]
+    async fn success_rewrite() {
+        // Rewritten paths should be routed to their new route.
+        let mut app = Octane::new();
+        app.get("/users", route_stop!(|req, res| res.send("users")))
+            .unwrap();
+        app.rewrite(|line, _| {
+            if line.path.chunks.first().map(String::as_str) == Some("v1") {
+                line.path.chunks.remove(0);
+            }
+        });
+        let response = request(app, b"GET /v1/users HTTP/1.1\r\nHost: localhost\r\n\r\n").await;
+        assert!(response.starts_with("HTTP/1.1 200"));
+        assert!(response.ends_with("users"));
+    }
+
+    // A subscriber which keeps the fields of every span and the
+    // events along with the span they happened in
+    #[cfg(feature = "tracing")]
+    #[derive(Default)]
+    struct Capture {
+        spans: std::sync::Mutex<Vec<Vec<String>>>,
+        metadata: std::sync::Mutex<Vec<&'static tracing::Metadata<'static>>>,
+        events: std::sync::Mutex<Vec<(usize, Vec<String>)>>,
+        entered: std::sync::Mutex<Vec<usize>>,
+    }
+
+    #[cfg(feature = "tracing")]
+    struct Fields<'a>(&'a mut Vec<String>);
+
+    #[cfg(feature = "tracing")]
+    impl tracing::field::Visit for Fields<'_> {
+        fn record_debug(&mut self, field: &tracing::field::Field, value: &dyn std::fmt::Debug) {
+            self.0.push(format!("{}={:?}", field.name(), value));
+        }
+    }
+
+    #[cfg(feature = "tracing")]
+    impl tracing::Subscriber for Capture {
+        fn enabled(&self, _: &tracing::Metadata<'_>) -> bool {
+            true
+        }
+        fn new_span(&self, span: &tracing::span::Attributes<'_>) -> tracing::Id {
+            let mut spans = self.spans.lock().unwrap();
+            let mut fields = Vec::new();
+            span.record(&mut Fields(&mut fields));
+            spans.push(fields);
+            self.metadata.lock().unwrap().push(span.metadata());
+            tracing::Id::from_u64(spans.len() as u64)
+        }
+        fn record(&self, span: &tracing::Id, values: &tracing::span::Record<'_>) {
+            let mut spans = self.spans.lock().unwrap();
+            values.record(&mut Fields(&mut spans[span.into_u64() as usize - 1]));
+        }
+        fn record_follows_from(&self, _: &tracing::Id, _: &tracing::Id) {}
+        fn event(&self, event: &tracing::Event<'_>) {
+            let span = self.entered.lock().unwrap().last().copied().unwrap_or(0);
+            let mut fields = Vec::new();
+            event.record(&mut Fields(&mut fields));
+            self.events.lock().unwrap().push((span, fields));
+        }
+        fn enter(&self, span: &tracing::Id) {
+            self.entered.lock().unwrap().push(span.into_u64() as usize);
+        }
+        fn exit(&self, _: &tracing::Id) {
+            self.entered.lock().unwrap().pop();
+        }
+        fn current_span(&self) -> tracing_core::span::Current {
+            match self.entered.lock().unwrap().last() {
+                Some(&span) => tracing_core::span::Current::new(
+                    tracing::Id::from_u64(span as u64),
+                    self.metadata.lock().unwrap()[span - 1],
+                ),
+                None => tracing_core::span::Current::none(),
+            }
+        }
+    }
+
+    #[crate::test]
+    #[cfg(feature = "tracing")]
+    async fn success_tracing_spans() {
+        // Requests should be served in spans carrying their id, method and path.
+        let capture = Arc::new(Capture::default());
+        let dispatch = tracing::Dispatch::new(Arc::clone(&capture));
+        let _default = tracing::dispatcher::set_default(&dispatch);
+        let (server, mut client) = socket_pair().await;
+        let requesting = task!({
+            client
+                .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+                .await
+                .unwrap();
+            let mut data = Vec::new();
+            client.read_to_end(&mut data).await.unwrap();
+            data
+        });
+        // serve on this thread so the events reach the subscriber
+        let served = Octane::serve(server, Arc::new(hello_app())).await;
+        let response = requesting.await.unwrap();
+        assert!(served.is_ok());
+        assert!(response.starts_with(b"HTTP/1.1 200"));
+        let spans = capture.spans.lock().unwrap();
+        assert_eq!(spans.len(), 1);
+        assert!(spans[0][0].starts_with("id="));
+        assert!(spans[0].contains(&"method=\"GET\"".to_owned()));
+        assert!(spans[0].contains(&"path=\"/\"".to_owned()));
+        let events = capture.events.lock().unwrap();
+        assert!(events.iter().all(|(span, _)| *span == 1));
+        let messages: Vec<&str> = events
+            .iter()
+            .map(|(_, fields)| fields[0].as_str())
+            .collect();
+        assert_eq!(
+            messages,
+            [
+                "message=request parsed",
+                "message=request routed",
+                "message=response sent"
+            ]
+        );
+        assert!(events[1].1.contains(&"handled=true".to_owned()));
+        assert!(events[2].1.contains(&"status=200".to_owned()));
+    }
+
+    #[crate::test]
+    async fn success_route_group() {
+        // Group middlewares should only run under the group prefix.
+        let mut app = Octane::new();
+        app.group("/api", |api| {
+            api.add(route_next!(|req, res| {
+                res.set("X-Group", "api");
+            }))?;
+            api.get("/users", route_stop!(|req, res| res.send("users")))
+        })
+        .unwrap();
+        app.get("/home", route_stop!(|req, res| res.send("home")))
+            .unwrap();
+        let app = Arc::new(app);
+        let mut responses = Vec::new();
+        for path in ["/api/users", "/home", "/users"].iter() {
+            let (server, mut client) = socket_pair().await;
+            let served = Arc::clone(&app);
+            task!({
+                Octane::serve(server, served).await.ok();
+            });
+            let raw = format!("GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path);
+            client.write_all(raw.as_bytes()).await.unwrap();
+            responses.push(read_response(&mut client).await);
+        }
+        assert!(responses[0].contains("X-Group: api\r\n"));
+        assert!(responses[0].ends_with("users"));
+        assert!(!responses[1].contains("X-Group"));
+        assert!(responses[1].ends_with("home"));
+        assert!(responses[2].starts_with("HTTP/1.1 404"));
+    }
+
+    #[crate::test]
+    async fn success_route_group_case_insensitive() {
+        // Group middlewares should run for the prefix in any case when paths
+        // are case insensitive, so they can't be skipped by the case.
+        let app = || {
+            let mut app = Octane::new();
+            app.settings.case_insensitive_paths = true;
+            app.group("/admin", |admin| {
+                admin.add(route!(|req, res| {
+                    if req.headers.get("authorization").is_some() {
+                        return Flow::Next;
+                    }
+                    res.status(StatusCode::Unauthorized).send("unauthorized");
+                    Flow::Stop
+                }))?;
+                admin.get("/panel", route_stop!(|req, res| res.send("panel")))
+            })
+            .unwrap();
+            app
+        };
+        for path in ["/admin/panel", "/Admin/Panel", "/ADMIN/panel"].iter() {
+            let raw = format!("GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path);
+            let response = request(app(), raw.as_bytes()).await;
+            assert!(response.starts_with("HTTP/1.1 401"));
+            assert!(response.ends_with("unauthorized"));
+        }
+        let raw = b"GET /Admin/Panel HTTP/1.1\r\nHost: localhost\r\nAuthorization: yes\r\n\r\n";
+        let response = request(app(), raw).await;
+        assert!(response.ends_with("\r\n\r\npanel"));
+    }
+
+    #[crate::test]
+    async fn success_expect_continue() {
+        // The body should be invited once the route is known to exist.
+        let (server, mut client) = socket_pair().await;
+        task!({
+            Octane::serve(server, Arc::new(echo_app())).await.ok();
+        });
+        client
+            .write_all(b"POST / HTTP/1.1\r\nHost: localhost\r\nExpect: 100-continue\r\nContent-Length: 5\r\n\r\n")
+            .await
+            .unwrap();
+        assert_eq!(
+            read_response(&mut client).await,
+            "HTTP/1.1 100 Continue\r\n\r\n"
+        );
+        client.write_all(b"Hello").await.unwrap();
+        let response = read_response(&mut client).await;
+        assert!(response.starts_with("HTTP/1.1 200"));
+        assert!(response.ends_with("Hello"));
+    }
+
+    #[crate::test]
+    async fn fail_expect_continue_not_found() {
+        // A request which would 404 shouldn't be asked for its body.
+        let (server, mut client) = socket_pair().await;
+        task!({
+            Octane::serve(server, Arc::new(echo_app())).await.ok();
+        });
+        client
+            .write_all(b"POST /missing HTTP/1.1\r\nHost: localhost\r\nExpect: 100-continue\r\nContent-Length: 5\r\n\r\n")
+            .await
+            .unwrap();
+        let response = timeout(Duration::from_secs(1), read_response(&mut client))
+            .await
+            .expect("the body was waited for");
+        assert!(response.starts_with("HTTP/1.1 404"));
+    }
+
+    #[crate::test]
+    async fn fail_expect_continue_too_large() {
+        // An oversized body should be rejected before it is sent.
+        let mut app = echo_app();
+        app.settings.max_body_size = Some(4);
+        let (server, mut client) = socket_pair().await;
+        task!({
+            Octane::serve(server, Arc::new(app)).await.ok();
+        });
+        client
+            .write_all(b"POST / HTTP/1.1\r\nHost: localhost\r\nExpect: 100-continue\r\nContent-Length: 5\r\n\r\n")
+            .await
+            .unwrap();
+        let response = timeout(Duration::from_secs(1), read_response(&mut client))
+            .await
+            .expect("the body was waited for");
+        assert!(response.starts_with("HTTP/1.1 413"));
+    }
+
+    #[crate::test]
+    async fn success_request_line_within_limit() {
+        // Request lines up to the limit should be served.
+        let mut app = hello_app();
+        app.settings.max_request_line = Some(64);
+        let response = request(app, b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n").await;
+        assert!(response.starts_with("HTTP/1.1 200"));
+    }
+
+    #[crate::test]
+    #[cfg(feature = "faithful")]
+    async fn fail_missing_host() {
+        // Http 1.1 requests need a host header.
+        let response = request(hello_app(), b"GET / HTTP/1.1\r\n\r\n").await;
+        assert!(response.starts_with("HTTP/1.1 400"));
+    }
+
+    #[crate::test]
+    async fn success_virtual_hosts() {
+        // Requests should be routed by their host.
+        let mut app = Octane::new();
+        let mut api = Router::new();
+        api.get("/", route_stop!(|req, res| res.send("api")))
+            .unwrap();
+        let mut admin = Router::new();
+        admin
+            .get("/", route_stop!(|req, res| res.send("admin")))
+            .unwrap();
+        app.host("api.example.com", api);
+        app.host("Admin.Example.com", admin);
+        app.get("/", route_stop!(|req, res| res.send("default")))
+            .unwrap();
+        let app = Arc::new(app);
+        let cases: &[(&[u8], &str)] = &[
+            (b"GET / HTTP/1.1\r\nHost: api.example.com\r\n\r\n", "api"),
+            (
+                b"GET / HTTP/1.1\r\nHost: ADMIN.example.com:8080\r\n\r\n",
+                "admin",
+            ),
+            (
+                b"GET http://api.example.com/ HTTP/1.1\r\nHost: other\r\n\r\n",
+                "api",
+            ),
+            (b"GET / HTTP/1.1\r\nHost: example.com\r\n\r\n", "default"),
+        ];
+        for (raw, expected) in cases {
+            let (server, mut client) = socket_pair().await;
+            let served = Arc::clone(&app);
+            task!({
+                Octane::serve(server, served).await.ok();
+            });
+            client.write_all(raw).await.unwrap();
+            assert!(read_response(&mut client).await.ends_with(expected));
+        }
+    }
+
+    #[test]
+    fn success_strip_port() {
+        // Ports should be removed, ipv6 addresses kept whole.
+        assert_eq!(strip_port("example.com:8080"), "example.com");
+        assert_eq!(strip_port("example.com"), "example.com");
+        assert_eq!(strip_port("[::1]:8080"), "[::1]");
+    }
+
+    #[crate::test]
+    async fn success_draining() {
+        // In flight requests should complete, new ones should get a 503.
+        let mut app = Octane::new();
+        let handle = app.shutdown_handle();
+        app.get(
+            "/",
+            route_stop!(|req, res| {
+                // the server starts draining while this request is handled
+                handle.shutdown();
+                res.send("done");
+            }),
+        )
+        .unwrap();
+        let handle = app.shutdown_handle();
+        let app = Arc::new(app);
+        let (server, mut client) = socket_pair().await;
+        let served = Arc::clone(&app);
+        let task = task!({
+            Octane::serve(server, served).await.ok();
+        });
+        client
+            .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .await
+            .unwrap();
+        let mut response = Vec::new();
+        timeout(Duration::from_secs(1), client.read_to_end(&mut response))
+            .await
+            .expect("connection wasn't closed")
+            .unwrap();
+        let response = String::from_utf8(response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 200"));
+        assert!(response.contains("Connection: close\r\n"));
+        assert!(response.ends_with("done"));
+        task.await.unwrap();
+        assert!(handle.is_shutting_down());
+        assert_eq!(handle.connections(), 0);
+
+        let response = request(
+            Arc::try_unwrap(app).ok().unwrap(),
+            b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n",
+        )
+        .await;
+        assert!(response.starts_with("HTTP/1.1 503"));
+        assert!(response.contains("Connection: close\r\n"));
+        assert!(response.contains("Retry-After: 5\r\n"));
+    }
+
+    #[crate::test]
+    async fn success_custom_method() {
+        // Custom methods should be routed, unknown ones not implemented.
+        let mut app = Octane::new();
+        app.method(
+            "PROPFIND",
+            "/files",
+            route_stop!(|req, res| {
+                res.status(StatusCode::Multistatus).send("found");
+            }),
+        )
+        .unwrap();
+        let app = Arc::new(app);
+        let cases: &[(&[u8], &str)] = &[
+            (
+                b"PROPFIND /files HTTP/1.1\r\nHost: localhost\r\n\r\n",
+                "HTTP/1.1 207",
+            ),
+            (
+                b"MKCOL /files HTTP/1.1\r\nHost: localhost\r\n\r\n",
+                "HTTP/1.1 501",
+            ),
+        ];
+        for (raw, expected) in cases {
+            let (server, mut client) = socket_pair().await;
+            let served = Arc::clone(&app);
+            task!({
+                Octane::serve(server, served).await.ok();
+            });
+            client.write_all(raw).await.unwrap();
+            assert!(read_response(&mut client).await.starts_with(expected));
+        }
+    }
+
+    #[crate::test]
+    async fn success_state() {
+        // Handlers should share the state across requests.
+        let mut app = Octane::with_state(AtomicUsize::new(0));
+        app.get(
+            "/",
+            route_stop!(|req, res| {
+                let hits = req.state::<AtomicUsize>().unwrap();
+                res.send((hits.fetch_add(1, Ordering::SeqCst) + 1).to_string());
+                assert!(req.state::<String>().is_none());
+            }),
+        )
+        .unwrap();
+        let app = Arc::new(app);
+        let (server, mut client) = socket_pair().await;
+        let served = Arc::clone(&app);
+        let handle = task!({
+            Octane::serve(server, served).await.ok();
+        });
+        for expected in &["1", "2", "3"] {
+            client
+                .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n")
+                .await
+                .unwrap();
+            assert!(read_response(&mut client).await.ends_with(expected));
+        }
+        AsyncWriteExt::shutdown(&mut client).await.unwrap();
+        handle.await.unwrap();
+        let state = app.state.0.as_ref().unwrap();
+        assert_eq!(
+            state
+                .downcast_ref::<AtomicUsize>()
+                .unwrap()
+                .load(Ordering::SeqCst),
+            3
+        );
+    }
+
+    #[crate::test]
+    async fn success_listen_addr() {
+        // Binding port 0 should report the port picked by the OS.
+        let (sender, receiver) = tokio::sync::oneshot::channel();
+        task!({
+            hello_app()
+                .listen_addr(0, move |addr| {
+                    sender.send(addr).ok();
+                })
+                .await
+                .ok();
+        });
+        let addr = receiver.await.unwrap();
+        assert!(addr.ip().is_unspecified());
+        assert_ne!(addr.port(), 0);
+        let mut client = TcpStream::connect(("127.0.0.1", addr.port()))
+            .await
+            .unwrap();
+        client
+            .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .await
+            .unwrap();
+        assert!(read_response(&mut client).await.ends_with("Hello, World"));
+    }
+
+    #[crate::test]
+    async fn fail_eof_mid_body() {
+        // A keep alive connection closed mid body should end its task with an error.
+        let bodies: [(&[u8], Option<usize>); 3] = [
+            (b"Content-Length: 10\r\n\r\nabc", None),
+            (b"Content-Length: 10\r\n\r\nabc", Some(4)),
+            (b"Transfer-Encoding: chunked\r\n\r\na\r\nabc", None),
+        ];
+        for (body, spool_threshold) in bodies.iter() {
+            let mut app = echo_app();
+            app.settings.spool_threshold = *spool_threshold;
+            let (server, mut client) = socket_pair().await;
+            let handle = task!({
+                Octane::serve(server, Arc::new(app))
+                    .await
+                    .map_err(|err| err.to_string())
+            });
+            client
+                .write_all(b"POST / HTTP/1.1\r\nHost: localhost\r\nContent-Length: 2\r\n\r\nhi")
+                .await
+                .unwrap();
+            assert!(read_response(&mut client).await.ends_with("hi"));
+            client
+                .write_all(b"POST / HTTP/1.1\r\nHost: localhost\r\n")
+                .await
+                .unwrap();
+            client.write_all(body).await.unwrap();
+            drop(client);
+            let served = tokio::time::timeout(Duration::from_secs(5), handle)
+                .await
+                .expect("the connection task should end")
+                .expect("the connection task shouldn't panic");
+            assert!(served.is_err());
+        }
+    }
+
+    #[crate::test]
+    async fn success_error_sink() {
+        // A connection which breaks mid request should be reported.
+        let reported = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let mut app = hello_app();
+        let sink = Arc::clone(&reported);
+        app.on_error(move |err| sink.lock().unwrap().push(err.to_string()));
+        let (sender, receiver) = tokio::sync::oneshot::channel();
+        task!({
+            app.listen_addr(0, move |addr| {
+                sender.send(addr).ok();
+            })
+            .await
+            .ok();
+        });
+        let port = receiver.await.unwrap().port();
+        let mut client = TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+        client
+            .write_all(b"POST / HTTP/1.1\r\nHost: localhost\r\nContent-Length: 10\r\n\r\nabc")
+            .await
+            .unwrap();
+        drop(client);
+        for _ in 0..100 {
+            if !reported.lock().unwrap().is_empty() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(reported.lock().unwrap().len(), 1);
+    }
+
+    #[crate::test]
+    async fn success_listen_callback() {
+        // The callback should run once, after the port is bound.
+        let port = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&calls);
+        let (sender, receiver) = tokio::sync::oneshot::channel();
+        task!({
+            hello_app()
+                .listen(port, move || {
+                    counter.fetch_add(1, Ordering::SeqCst);
+                    let bound = std::net::TcpStream::connect(("127.0.0.1", port)).is_ok();
+                    sender.send(bound).ok();
+                })
+                .await
+                .ok();
+        });
+        assert!(receiver.await.unwrap());
+        let mut client = TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+        client
+            .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .await
+            .unwrap();
+        assert!(read_response(&mut client).await.ends_with("Hello, World"));
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[crate::test]
+    async fn fail_pipe_short_upstream() {
+        // An upstream ending short of its length should close the connection
+        // instead of leaving the client waiting for the rest.
+        let mut app = Octane::new();
+        app.get(
+            "/proxied",
+            route_stop!(|req, res| {
+                let upstream: &'static [u8] = b"short";
+                res.pipe(StatusCode::Ok, &[("Content-Length", "10")], upstream);
+            }),
+        )
+        .unwrap();
+        let raw = b"GET /proxied HTTP/1.1\r\nHost: localhost\r\n\r\n\
+            GET /proxied HTTP/1.1\r\nHost: localhost\r\n\r\n";
+        let response = request(app, raw).await;
+        assert!(response.contains("Content-Length: 10\r\n"));
+        assert!(response.ends_with("\r\n\r\nshort"));
+        assert_eq!(response.matches("HTTP/1.1 200").count(), 1);
+    }
+
+    #[crate::test]
+    async fn success_pipelining() {
+        // Requests sent in a single write should all be answered in order.
+        let response = request(
+            echo_app(),
+            b"POST / HTTP/1.1\r\nHost: localhost\r\nContent-Length: 5\r\n\r\nfirst\
+            POST / HTTP/1.1\r\nHost: localhost\r\nContent-Length: 6\r\n\r\nsecond\
+            POST / HTTP/1.1\r\nHost: localhost\r\nContent-Length: 0\r\n\r\n",
+        )
+        .await;
+        let first = response.find("\r\n\r\nfirst").unwrap();
+        let second = response.find("\r\n\r\nsecond").unwrap();
+        assert!(first < second);
+        assert_eq!(response.matches("HTTP/1.1 200 OK\r\n").count(), 3);
+    }
+
+    fn secure_app() -> Octane {
+        let mut app = Octane::new();
+        app.get(
+            "/",
+            route_stop!(|req, res| res.send(if req.is_secure() { "https" } else { "http" })),
+        )
+        .unwrap();
+        app
+    }
+
+    #[crate::test]
+    async fn success_is_secure() {
+        // Only ssl connections should be secure.
+        let raw = b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n";
+        assert!(request(secure_app(), raw).await.ends_with("http"));
+        let (server, mut client) = socket_pair().await;
+        task!({
+            Octane::serve(SecureStream(server), Arc::new(secure_app()))
+                .await
+                .ok();
+        });
+        client.write_all(raw).await.unwrap();
+        assert!(read_response(&mut client).await.ends_with("https"));
+    }
+
+    #[crate::test]
+    async fn success_forwarded_proto() {
+        // The forwarded scheme should only be used behind a trusted proxy.
+        let raw = b"GET / HTTP/1.1\r\nHost: localhost\r\nX-Forwarded-Proto: https, http\r\n\r\n";
+        assert!(request(secure_app(), raw).await.ends_with("http"));
+        let mut app = secure_app();
+        app.settings.trust_proxy = true;
+        assert!(request(app, raw).await.ends_with("https"));
+    }
+
+    fn https_app() -> Octane {
+        let mut app = Octane::new();
+        let redirect = app.force_https(&["/.well-known/acme-challenge"]);
+        app.add(redirect).unwrap();
+        app.add(route_stop!(|req, res| res.send("served"))).unwrap();
+        app
+    }
+
+    #[crate::test]
+    async fn success_force_https() {
+        // Insecure requests should be redirected to the https url.
+        let response = request(
+            https_app(),
+            b"GET /docs/page?v=1 HTTP/1.1\r\nHost: example.com:8080\r\n\r\n",
+        )
+        .await;
+        assert!(response.starts_with("HTTP/1.1 301"));
+        assert!(response.contains("Location: https://example.com/docs/page?v=1\r\n"));
+        let response = request(
+            https_app(),
+            b"POST /form HTTP/1.1\r\nHost: example.com\r\nContent-Length: 0\r\n\r\n",
+        )
+        .await;
+        assert!(response.starts_with("HTTP/1.1 308"));
+    }
+
+    #[crate::test]
+    async fn success_force_https_passthrough() {
+        // Secure and exempt requests should reach the routes.
+        let raw = b"GET /docs HTTP/1.1\r\nHost: example.com\r\n\r\n";
+        let (server, mut client) = socket_pair().await;
+        task!({
+            Octane::serve(SecureStream(server), Arc::new(https_app()))
+                .await
+                .ok();
+        });
+        client.write_all(raw).await.unwrap();
+        assert!(read_response(&mut client).await.ends_with("served"));
+        let response = request(
+            https_app(),
+            b"GET /.well-known/acme-challenge/token HTTP/1.1\r\nHost: example.com\r\n\r\n",
+        )
+        .await;
+        assert!(response.ends_with("served"));
+    }
+
+    // Gets the body at the url with curl, the test certificate has
+    // expired so it isn't verified
+    #[cfg(feature = "rustls")]
+    async fn fetch_insecure(url: String) -> Vec<u8> {
+        tokio::task::spawn_blocking(move || {
+            let mut body = Vec::new();
+            let mut easy = curl::easy::Easy::new();
+            easy.url(&url).unwrap();
+            easy.ssl_verify_peer(false).unwrap();
+            easy.ssl_verify_host(false).unwrap();
+            let mut transfer = easy.transfer();
+            transfer
+                .write_function(|data| {
+                    body.extend_from_slice(data);
+                    Ok(data.len())
+                })
+                .unwrap();
+            transfer.perform().unwrap();
+            drop(transfer);
+            body
+        })
+        .await
+        .unwrap()
+    }
+
+    #[crate::test]
+    #[cfg(feature = "rustls")]
+    async fn success_listen_tls_and_plain() {
+        // Plain requests should be redirected while tls ones are served.
+        let mut app = hello_app();
+        app.ssl(0)
+            .key("templates/key.pem")
+            .cert("templates/cert.pem");
+        let (sender, receiver) = tokio::sync::oneshot::channel();
+        task!({
+            app.listen_tls_and_plain(0, 0, true, |plain, tls| {
+                sender.send((plain.port(), tls.port())).unwrap();
+            })
+            .await
+            .ok();
+        });
+        let (plain, tls) = receiver.await.unwrap();
+        let mut client = TcpStream::connect(("127.0.0.1", plain)).await.unwrap();
+        client
+            .write_all(b"GET /page?v=1 HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .await
+            .unwrap();
+        let response = read_response(&mut client).await;
+        assert!(response.starts_with("HTTP/1.1 301"));
+        let location = format!("Location: https://localhost:{}/page?v=1\r\n", tls);
+        assert!(response.contains(&location));
+        let body = fetch_insecure(format!("https://127.0.0.1:{}/", tls)).await;
+        assert_eq!(body, b"Hello, World");
+    }
+
+    #[crate::test]
+    #[cfg(feature = "rustls")]
+    async fn success_alpn_protocol() {
+        // Handlers should see the protocol negotiated with ALPN.
+        let alpn_app = || {
+            let mut app = Octane::new();
+            app.get(
+                "/",
+                route_stop!(|req, res| res.send(req.alpn_protocol().unwrap_or("none"))),
+            )
+            .unwrap();
+            app
+        };
+        let mut app = alpn_app();
+        app.ssl(0)
+            .key("templates/key.pem")
+            .cert("templates/cert.pem")
+            .alpn(&["http/1.1"]);
+        let (sender, receiver) = tokio::sync::oneshot::channel();
+        task!({
+            app.listen_tls_and_plain(0, 0, false, |plain, tls| {
+                sender.send((plain.port(), tls.port())).unwrap();
+            })
+            .await
+            .ok();
+        });
+        let (plain, tls) = receiver.await.unwrap();
+        let body = fetch_insecure(format!("https://127.0.0.1:{}/", tls)).await;
+        assert_eq!(body, b"http/1.1");
+        let body = fetch_insecure(format!("http://127.0.0.1:{}/", plain)).await;
+        assert_eq!(body, b"none");
+    }
+
+    #[crate::test]
+    async fn success_cors_max_age() {
+        // Only preflight responses should be cacheable with a max age.
+        let app = || {
+            let mut app = Octane::new();
+            let mut cors = CorsConfig::new();
+            cors.origin("https://example.com")
+                .max_age(Duration::from_secs(600));
+            app.add(Octane::cors(cors)).unwrap();
+            app.get("/", route_stop!(|req, res| res.send("hello")))
+                .unwrap();
+            app
+        };
+        let raw = b"OPTIONS / HTTP/1.1\r\nHost: localhost\r\nOrigin: https://example.com\r\n\
+            Access-Control-Request-Method: PUT\r\n\r\n";
+        let response = request(app(), raw).await;
+        assert!(response.starts_with("HTTP/1.1 204"));
+        assert!(response.contains("Access-Control-Allow-Origin: https://example.com\r\n"));
+        assert!(response.contains("Access-Control-Max-Age: 600\r\n"));
+        let raw = b"GET / HTTP/1.1\r\nHost: localhost\r\nOrigin: https://example.com\r\n\r\n";
+        let response = request(app(), raw).await;
+        assert!(response.contains("Access-Control-Allow-Origin: https://example.com\r\n"));
+        assert!(!response.contains("Access-Control-Max-Age"));
+        assert!(response.ends_with("hello"));
+        let raw = b"OPTIONS / HTTP/1.1\r\nHost: localhost\r\nOrigin: https://example.com\r\n\r\n";
+        assert!(!request(app(), raw).await.contains("Access-Control-Max-Age"));
+        let raw = b"OPTIONS / HTTP/1.1\r\nHost: localhost\r\nOrigin: https://other.com\r\n\
+            Access-Control-Request-Method: PUT\r\n\r\n";
+        assert!(!request(app(), raw).await.contains("Access-Control-"));
+    }
+
+    #[crate::test]
+    async fn success_range() {
+        // Range requests on files should get the partial content.
+        let file = std::fs::read_to_string("templates/test.html").unwrap();
+        let mut app = Octane::new();
+        app.add(Octane::static_dir(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/templates"
+        )))
+        .unwrap();
+        let response = request(
+            app,
+            b"GET /test.html HTTP/1.1\r\nHost: localhost\r\nRange: bytes=-6\r\n\r\n",
+        )
+        .await;
+        assert!(response.starts_with("HTTP/1.1 206"));
+        assert!(response.contains("Content-Length: 6\r\n"));
+        assert!(response.ends_with(&file[file.len() - 6..]));
+    }
+
+    // Sends a response whose body only arrives once the returned
+    // stream is written to, and returns what the client got before that
+    async fn send_delayed(flush_headers: bool) -> (String, TcpStream, TcpStream) {
+        let (body, body_writer) = socket_pair().await;
+        let (server, mut client) = socket_pair().await;
+        let head = String::from("HTTP/1.1 200 OK\r\nContent-Length: 4\r\n\r\n");
+        task!({
+            Octane::send((head, Box::new(body)), server, flush_headers)
+                .await
+                .ok();
+        });
+        let mut buf = [0; 64];
+        let read = timeout(Duration::from_millis(200), client.read(&mut buf)).await;
+        let received = match read {
+            Ok(read) => String::from_utf8_lossy(&buf[..read.unwrap()]).into_owned(),
+            Err(_) => String::new(),
+        };
+        (received, body_writer, client)
+    }
+
+    #[crate::test]
+    async fn success_flush_headers_early() {
+        // The headers should arrive before a slow body.
+        let (received, mut body_writer, mut client) = send_delayed(true).await;
+        assert!(received.ends_with("Content-Length: 4\r\n\r\n"));
+        body_writer.write_all(b"late").await.unwrap();
+        AsyncWriteExt::shutdown(&mut body_writer).await.unwrap();
+        let mut rest = String::new();
+        client.read_to_string(&mut rest).await.unwrap();
+        assert_eq!(rest, "late");
+    }
+
+    #[crate::test]
+    async fn success_buffered_headers() {
+        // By default the headers should wait for the body.
+        let (received, mut body_writer, mut client) = send_delayed(false).await;
+        assert!(received.is_empty());
+        body_writer.write_all(b"late").await.unwrap();
+        AsyncWriteExt::shutdown(&mut body_writer).await.unwrap();
+        let mut rest = String::new();
+        client.read_to_string(&mut rest).await.unwrap();
+        assert!(rest.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(rest.ends_with("\r\n\r\nlate"));
+    }
+
+    #[crate::test]
+    #[cfg(feature = "url_variables")]
+    async fn success_matched_request_data() {
+        // Every closure should see the same request data.
+        let mut app = Octane::new();
+        app.add(route_next!(|req, res| {
+            assert_eq!(req.body, b"payload");
+            res.set("X-Seen", req.headers.get("x-token").unwrap());
+        }))
+        .unwrap();
+        app.post(
+            "/items/:id",
+            route_stop!(|req, res| {
+                let id = req.vars.get("id").unwrap();
+                let body = String::from_utf8_lossy(req.body);
+                res.send(format!("{} {} {}", id, body, req.request_line.path));
+            }),
+        )
+        .unwrap();
+        let response = request(
+            app,
+            b"POST /items/42 HTTP/1.1\r\nHost: localhost\r\nX-Token: abc\r\n\
+            Content-Length: 7\r\n\r\npayload",
+        )
+        .await;
+        assert!(response.contains("X-Seen: abc\r\n"));
+        assert!(response.ends_with("42 payload items/42/"));
+    }
+
+    #[crate::test]
+    async fn success_dispatch_order() {
+        // Middlewares and routes should run in the order they were added.
+        let mut app = Octane::new();
+        app.add(route_next!(|req, res| {
+            res.set("X-Order", "add");
+        }))
+        .unwrap();
+        app.get(
+            "/",
+            route_next!(|req, res| {
+                let order = format!("{},get", res.get("X-Order").unwrap());
+                res.set("X-Order", &order);
+            }),
+        )
+        .unwrap();
+        app.add_route(
+            "/",
+            route_next!(|req, res| {
+                let order = format!("{},all", res.get("X-Order").unwrap());
+                res.set("X-Order", &order);
+            }),
+        )
+        .unwrap();
+        app.add(route_stop!(|req, res| {
+            let order = format!("{},last", res.get("X-Order").unwrap());
+            res.send(order);
+        }))
+        .unwrap();
+        app.get("/", route_stop!(|req, res| res.send("unreachable")))
+            .unwrap();
+        let response = request(app, b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n").await;
+        assert!(response.ends_with("add,get,all,last"));
+    }
+
+    #[crate::test]
+    async fn success_middleware_around_route() {
+        // Middlewares should run before and after a route by registration.
+        let mut app = Octane::new();
+        app.add(route_next!(|req, res| {
+            res.set("X-Before", "1");
+        }))
+        .unwrap();
+        app.get(
+            "/",
+            route_next!(|req, res| {
+                assert_eq!(res.get("X-Before"), Some(&"1".to_owned()));
+                assert_eq!(res.get("X-After"), None);
+                res.send("route");
+            }),
+        )
+        .unwrap();
+        app.add(route_next!(|req, res| {
+            assert!(res.has_body());
+            res.set("X-After", "1");
+        }))
+        .unwrap();
+        let response = request(app, b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n").await;
+        assert!(response.contains("X-Before: 1\r\n"));
+        assert!(response.contains("X-After: 1\r\n"));
+        assert!(response.ends_with("route"));
+    }
+
+    #[crate::test]
+    async fn success_close_connection() {
+        // The connection should be closed when the handler asks for it.
+        let mut app = Octane::new();
+        app.get(
+            "/",
+            route_stop!(|req, res| {
+                res.close_connection().send("bye");
+            }),
+        )
+        .unwrap();
+        let (server, mut client) = socket_pair().await;
+        let handle = task!({
+            Octane::serve(server, Arc::new(app)).await.ok();
+        });
+        client
+            .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\nConnection: keep-alive\r\n\r\n")
+            .await
+            .unwrap();
+        // we don't shut down our side, the server has to close on its own
+        let mut response = String::new();
+        client.read_to_string(&mut response).await.unwrap();
+        assert!(response.contains("Connection: close\r\n"));
+        assert!(response.ends_with("bye"));
+        handle.await.unwrap();
+    }
+
+    #[crate::test]
+    #[cfg(feature = "url_variables")]
+    async fn success_body_limit_override() {
+        // The path limit should replace the global one for that path only.
+        let body_app = || {
+            let mut app = Octane::new();
+            app.settings.max_body_size = Some(4);
+            app.body_limit("/upload", Some(1)).unwrap();
+            app.body_limit("/upload", Some(16)).unwrap();
+            app.body_limit("/files/:name", None).unwrap();
+            app.add(route_stop!(|req, res| res.send(req.body))).unwrap();
+            app
+        };
+        let cases: &[(&str, &str)] = &[
+            ("/", "HTTP/1.1 413"),
+            ("/upload", "HTTP/1.1 200"),
+            ("/upload/nested", "HTTP/1.1 413"),
+            ("/files/big", "HTTP/1.1 200"),
+        ];
+        for (path, expected) in cases {
+            let raw = format!(
+                "POST {} HTTP/1.1\r\nHost: localhost\r\nContent-Length: 10\r\n\r\n0123456789",
+                path
+            );
+            let response = request(body_app(), raw.as_bytes()).await;
+            assert!(response.starts_with(expected), "{}", path);
+        }
+    }
+}
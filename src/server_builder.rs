@@ -1,10 +1,12 @@
 use crate::server::Octane;
 use crate::task;
 use socket2::{Domain, Protocol, SockAddr, Socket, Type};
+use std::error::Error;
 use std::future::Future;
 use std::io::Result;
 use std::net::{IpAddr, Ipv4Addr, SocketAddr};
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::net::{TcpListener, TcpStream};
 use tokio::stream::StreamExt;
 #[cfg(feature = "openSSL")]
@@ -16,31 +18,54 @@ pub struct ServerBuilder {
     socket: TcpListener,
 }
 
+// How long to wait after a failed accept before accepting again. Errors
+// like running out of file descriptors last until connections close, so
+// retrying right away would spin and flood the error sink
+const ACCEPT_BACKOFF: Duration = Duration::from_millis(100);
+
+// Reports a failed accept and backs off before the next one
+async fn accept_failed(server: &Octane, err: &std::io::Error) {
+    server.report(err);
+    tokio::time::sleep(ACCEPT_BACKOFF).await;
+}
+
 impl ServerBuilder {
-    pub fn new(port: u16) -> Result<Self> {
+    pub fn new(port: u16, backlog: i32) -> Result<Self> {
         let stream = Type::stream();
         let socket = Socket::new(Domain::ipv4(), stream.non_blocking(), Some(Protocol::tcp()))?;
         let bind_add = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0)), port);
-        socket.bind(&SockAddr::from(bind_add))?;
-        socket.listen(2048)?;
+        // has to be set before binding to have any effect
         socket.set_reuse_address(true)?;
+        socket.bind(&SockAddr::from(bind_add))?;
+        socket.listen(backlog)?;
         Ok(ServerBuilder {
             socket: TcpListener::from_std(socket.into_tcp_listener())?,
         })
     }
 
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        self.socket.local_addr()
+    }
+
+    // Accepts connections until the listener closes, errors with a single
+    // connection are reported and don't stop the server
     pub async fn listen<C, T>(mut self, exec: C, server: Arc<Octane>) -> Result<()>
     where
-        T: Future + Send,
+        T: Future<Output = std::result::Result<(), Box<dyn Error>>> + Send,
         C: FnOnce(TcpStream, Arc<Octane>) -> T + Send + 'static + Copy,
     {
         while let Some(stream) = self.socket.next().await {
-            stream.map(|stream| {
-                let server = Arc::clone(&server);
-                task!({
-                    exec(stream, server).await;
-                })
-            })?;
+            match stream {
+                Ok(stream) => {
+                    let server = Arc::clone(&server);
+                    task!({
+                        if let Err(e) = exec(stream, Arc::clone(&server)).await {
+                            server.report(&*e);
+                        }
+                    });
+                }
+                Err(e) => accept_failed(&server, &e).await,
+            }
         }
         Ok(())
     }
@@ -48,22 +73,30 @@ impl ServerBuilder {
     #[cfg(feature = "openSSL")]
     pub async fn listen_ssl<C, T>(self, exec: C, server: Arc<Octane>) -> Result<()>
     where
-        T: Future + Send,
+        T: Future<Output = std::result::Result<(), Box<dyn Error>>> + Send,
         C: FnOnce(SslStream<TcpStream>, Arc<Octane>) -> T + Send + 'static + Copy,
     {
         let mut ssl_listener = self.socket;
         let acceptor = crate::tls::openssl::acceptor(&server.settings)?;
         while let Some(stream) = ssl_listener.next().await {
+            let tcp_stream = match stream {
+                Ok(stream) => stream,
+                Err(e) => {
+                    accept_failed(&server, &e).await;
+                    continue;
+                }
+            };
             let acceptor = acceptor.clone();
-            let tcp_stream = stream?;
             let server = Arc::clone(&server);
 
             task!({
-                let stream = tokio_openssl::accept(&acceptor, tcp_stream).await;
-                if let Ok(stream_ssl) = stream {
-                    exec(stream_ssl, server).await;
-                } else {
-                    stream.map_err(|e| println!("{:?}", e)).err();
+                match tokio_openssl::accept(&acceptor, tcp_stream).await {
+                    Ok(stream_ssl) => {
+                        if let Err(e) = exec(stream_ssl, Arc::clone(&server)).await {
+                            server.report(&*e);
+                        }
+                    }
+                    Err(e) => server.report(&e),
                 }
             });
         }
@@ -77,28 +110,61 @@ impl ServerBuilder {
         server: Arc<Octane>,
     ) -> std::result::Result<(), Box<dyn std::error::Error>>
     where
-        T: Future + Send,
+        T: Future<Output = std::result::Result<(), Box<dyn Error>>> + Send,
         C: FnOnce(TlsStream<TcpStream>, Arc<Octane>) -> T + Send + 'static + Copy,
     {
         let mut ssl_listener = self.socket;
         let acceptor = crate::tls::rustls::acceptor(&server.settings)?;
 
         while let Some(stream) = ssl_listener.next().await {
+            let tcp_stream = match stream {
+                Ok(stream) => stream,
+                Err(e) => {
+                    accept_failed(&server, &e).await;
+                    continue;
+                }
+            };
             let acceptor = acceptor.clone();
-
             let server = Arc::clone(&server);
-            let tcp_stream = stream?;
             task!({
-                let stream = acceptor.accept(tcp_stream).await;
-                if let Ok(stream_ssl) = stream {
-                    exec(stream_ssl, server).await;
-                } else {
-                    stream
-                        .map_err(|e| println!("WARNING: {:?}", e.kind()))
-                        .err();
+                match acceptor.accept(tcp_stream).await {
+                    Ok(stream_ssl) => {
+                        if let Err(e) = exec(stream_ssl, Arc::clone(&server)).await {
+                            server.report(&*e);
+                        }
+                    }
+                    Err(e) => server.report(&e),
                 }
             });
         }
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[crate::test]
+    async fn success_custom_backlog() {
+        // The listener should bind and accept with a custom backlog.
+        let builder = ServerBuilder::new(0, 16).unwrap();
+        let port = builder.socket.local_addr().unwrap().port();
+        let client = TcpStream::connect(("127.0.0.1", port)).await;
+        assert!(client.is_ok());
+    }
+
+    #[crate::test]
+    async fn success_accept_backoff() {
+        // A failed accept should be reported once and then waited out.
+        let reported = Arc::new(std::sync::Mutex::new(0));
+        let mut app = Octane::new();
+        let count = Arc::clone(&reported);
+        app.on_error(move |_| *count.lock().unwrap() += 1);
+        let started = std::time::Instant::now();
+        let err = std::io::Error::from_raw_os_error(24);
+        accept_failed(&app, &err).await;
+        assert!(started.elapsed() >= ACCEPT_BACKOFF);
+        assert_eq!(*reported.lock().unwrap(), 1);
+    }
+}
@@ -0,0 +1,249 @@
+use crate::cookies::{CookieAttributes, SameSite};
+use crate::request::Request;
+use crate::responder::Response;
+use crate::util::random_hex;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// The values held by a session
+pub type SessionData = HashMap<String, String>;
+
+/// Where sessions are kept between requests, implement it to keep
+/// them in a database or a cache shared by several servers. The
+/// [`MemoryStore`](struct.MemoryStore.html) keeps them in the process
+pub trait SessionStore: Send + Sync {
+    /// Returns the data of the session, `None` if there is no such session
+    fn get(&self, id: &str) -> Option<SessionData>;
+    /// Saves the data of the session, replacing the previous data
+    fn set(&self, id: &str, data: SessionData);
+    /// Removes the session
+    fn destroy(&self, id: &str);
+}
+
+/// A session store which keeps the sessions in memory, they are
+/// lost when the server stops
+#[derive(Debug, Default)]
+pub struct MemoryStore {
+    sessions: Mutex<HashMap<String, SessionData>>,
+}
+
+impl MemoryStore {
+    /// Creates an empty store
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl SessionStore for MemoryStore {
+    fn get(&self, id: &str) -> Option<SessionData> {
+        self.sessions.lock().unwrap().get(id).cloned()
+    }
+    fn set(&self, id: &str, data: SessionData) {
+        self.sessions.lock().unwrap().insert(id.to_owned(), data);
+    }
+    fn destroy(&self, id: &str) {
+        self.sessions.lock().unwrap().remove(id);
+    }
+}
+
+/// The session of a request, returned by
+/// [`req.session()`](../request/struct.Request.html#method.session)
+/// when sessions are enabled with
+/// [`Octane::with_sessions()`](../struct.Octane.html#method.with_sessions).
+/// Changes are saved to the store once the closures have run, and the
+/// session cookie is only sent for sessions which were changed. The
+/// cookie is sent with `Path=/`, `HttpOnly` and `SameSite=Lax`, and
+/// with `Secure` when the request was made over https
+#[derive(Clone)]
+pub struct Session(Arc<SessionInner>);
+
+struct SessionInner {
+    // the id is only generated once it is needed, so that requests
+    // which never touch the session don't create one
+    id: Mutex<Option<String>>,
+    data: Mutex<SessionData>,
+    changed: AtomicBool,
+    destroyed: AtomicBool,
+    // the id the client sent, if the store knew it
+    known: Option<String>,
+}
+
+impl Session {
+    fn new(known: Option<String>, data: SessionData) -> Self {
+        Session(Arc::new(SessionInner {
+            id: Mutex::new(known.clone()),
+            data: Mutex::new(data),
+            changed: AtomicBool::new(false),
+            destroyed: AtomicBool::new(false),
+            known,
+        }))
+    }
+    /// Returns the id of the session, which the session cookie holds
+    pub fn id(&self) -> String {
+        self.0.id.lock().unwrap().get_or_insert_with(new_id).clone()
+    }
+    /// Returns the value of a key
+    pub fn get(&self, key: &str) -> Option<String> {
+        self.0.data.lock().unwrap().get(key).cloned()
+    }
+    /// Sets the value of a key
+    pub fn set(&self, key: &str, value: &str) {
+        self.0
+            .data
+            .lock()
+            .unwrap()
+            .insert(key.to_owned(), value.to_owned());
+        self.0.changed.store(true, Ordering::SeqCst);
+    }
+    /// Removes a key and returns its value
+    pub fn remove(&self, key: &str) -> Option<String> {
+        let value = self.0.data.lock().unwrap().remove(key);
+        self.0.changed.store(true, Ordering::SeqCst);
+        value
+    }
+    /// Removes the session from the store and expires its cookie,
+    /// like when logging out
+    pub fn destroy(&self) {
+        self.0.destroyed.store(true, Ordering::SeqCst);
+    }
+    /// Gives the session a new id and keeps its data, the old id is
+    /// removed from the store. Call it when a user logs in, so that an
+    /// id planted in their browser beforehand can't be used to take
+    /// over their session
+    pub fn regenerate(&self) {
+        *self.0.id.lock().unwrap() = Some(new_id());
+        self.0.changed.store(true, Ordering::SeqCst);
+    }
+}
+
+impl fmt::Debug for Session {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Session")
+            .field("id", &*self.0.id.lock().unwrap())
+            .finish()
+    }
+}
+
+impl PartialEq for Session {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for Session {}
+
+// The store and the cookie the sessions of an app use
+pub(crate) struct Sessions {
+    pub(crate) cookie: String,
+    pub(crate) store: Box<dyn SessionStore>,
+}
+
+impl Sessions {
+    // Loads the session the request's cookie points to, or starts
+    // a new one if there is no cookie or the session is gone. Ids
+    // the store doesn't know aren't reused so clients can't pick them
+    pub(crate) fn load(&self, request: &Request) -> Session {
+        if let Some(id) = request.cookie(&self.cookie) {
+            if let Some(data) = self.store.get(id) {
+                return Session::new(Some(id.to_owned()), data);
+            }
+        }
+        Session::new(None, SessionData::new())
+    }
+    // Saves the changes made to the session and sets its cookie, which
+    // is only sent over https when the request came in that way
+    pub(crate) fn save(&self, session: &Session, secure: bool, res: &mut Response) {
+        let inner = &session.0;
+        let mut attributes = CookieAttributes::new();
+        attributes.path("/").http_only().same_site(SameSite::Lax);
+        if secure {
+            attributes.secure();
+        }
+        if inner.destroyed.load(Ordering::SeqCst) {
+            if let Some(known) = &inner.known {
+                self.store.destroy(known);
+                attributes.max_age(Duration::from_secs(0));
+                res.cookie_with(&self.cookie, "", &attributes);
+            }
+        } else if inner.changed.load(Ordering::SeqCst) {
+            let id = session.id();
+            // a regenerated session leaves its old id behind
+            if let Some(known) = inner.known.as_ref().filter(|known| **known != id) {
+                self.store.destroy(known);
+            }
+            let data = inner.data.lock().unwrap().clone();
+            self.store.set(&id, data);
+            res.cookie_with(&self.cookie, &id, &attributes);
+        }
+    }
+}
+
+// Generates a 128 bit session id from the random number generator
+// of the os. Ids mustn't be guessable, so there is no fallback if
+// the os can't provide random bytes
+fn new_id() -> String {
+    random_hex(16).expect("the os random number generator failed")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn success_memory_store() {
+        // Sessions should be kept until they are destroyed and ids unique.
+        let store = MemoryStore::new();
+        let mut data = SessionData::new();
+        data.insert("user".to_owned(), "1".to_owned());
+        store.set("abc", data.clone());
+        assert_eq!(store.get("abc"), Some(data));
+        store.destroy("abc");
+        assert_eq!(store.get("abc"), None);
+        let id = new_id();
+        assert_eq!(id.len(), 32);
+        assert_ne!(id, new_id());
+    }
+
+    fn sessions() -> Sessions {
+        Sessions {
+            cookie: "sid".to_owned(),
+            store: Box::new(MemoryStore::new()),
+        }
+    }
+
+    #[test]
+    fn success_untouched_session() {
+        // New sessions which weren't changed should get neither an id nor a cookie.
+        let session = Session::new(None, SessionData::new());
+        let mut res = Response::new_empty();
+        sessions().save(&session, false, &mut res);
+        assert!(session.0.id.lock().unwrap().is_none());
+        assert!(res.cookies.is_empty());
+    }
+
+    #[test]
+    fn success_regenerate() {
+        // A regenerated session should move its data to a new id.
+        let sessions = sessions();
+        let mut data = SessionData::new();
+        data.insert("user".to_owned(), "1".to_owned());
+        sessions.store.set("planted", data.clone());
+        let session = Session::new(Some("planted".to_owned()), data.clone());
+        session.regenerate();
+        let mut res = Response::new_empty();
+        sessions.save(&session, true, &mut res);
+        assert_ne!(session.id(), "planted");
+        assert_eq!(sessions.store.get("planted"), None);
+        assert_eq!(sessions.store.get(&session.id()), Some(data));
+        assert_eq!(
+            res.cookies.serialise(),
+            format!(
+                "Set-Cookie:sid={}; Path=/; HttpOnly; Secure; SameSite=Lax\r\n",
+                session.id()
+            )
+        );
+    }
+}
@@ -0,0 +1,72 @@
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+
+// The state shared between the server and the shutdown handles
+#[derive(Debug, Default)]
+pub(crate) struct DrainState {
+    shutting_down: AtomicBool,
+    connections: AtomicUsize,
+}
+
+// Decrements the open connections when the connection is done
+pub(crate) struct DrainGuard<'a>(&'a DrainState);
+
+impl Drop for DrainGuard<'_> {
+    fn drop(&mut self) {
+        self.0.connections.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+impl DrainState {
+    pub(crate) fn connection(&self) -> DrainGuard<'_> {
+        self.connections.fetch_add(1, Ordering::SeqCst);
+        DrainGuard(self)
+    }
+    pub(crate) fn is_shutting_down(&self) -> bool {
+        self.shutting_down.load(Ordering::SeqCst)
+    }
+}
+
+/// A handle to put the server in the draining state, returned by
+/// [`Octane::shutdown_handle()`](../struct.Octane.html#method.shutdown_handle).
+/// While draining, requests which are already being handled complete
+/// normally but their connections are closed afterwards, and new
+/// requests are answered with `503 Service Unavailable`
+///
+/// # Example
+///
+/// ```no_run
+/// use octane::prelude::*;
+///
+/// #[octane::main]
+/// async fn main() {
+///     let app = Octane::new();
+///     let handle = app.shutdown_handle();
+///     std::thread::spawn(move || {
+///         // on some signal
+///         handle.shutdown();
+///         while handle.connections() > 0 {
+///             std::thread::sleep(std::time::Duration::from_millis(100));
+///         }
+///         std::process::exit(0);
+///     });
+///     app.listen(8080, || {}).await.expect("Cannot establish connection");
+/// }
+/// ```
+#[derive(Debug, Clone)]
+pub struct ShutdownHandle(pub(crate) Arc<DrainState>);
+
+impl ShutdownHandle {
+    /// Starts draining the server
+    pub fn shutdown(&self) {
+        self.0.shutting_down.store(true, Ordering::SeqCst);
+    }
+    /// Returns true if the server is draining
+    pub fn is_shutting_down(&self) -> bool {
+        self.0.is_shutting_down()
+    }
+    /// Returns the number of connections which are still open
+    pub fn connections(&self) -> usize {
+        self.0.connections.load(Ordering::SeqCst)
+    }
+}
@@ -0,0 +1,156 @@
+use std::io::Cursor;
+use std::io::Read;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, ReadBuf, Result};
+use tokio::stream::Stream;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+
+/// The sending half of a server-sent events stream, returned by
+/// [`Response::sse()`](../responder/struct.Response.html#method.sse).
+/// It can be cloned and moved to other threads or tasks to push
+/// events over time, the stream ends once every sender is dropped
+///
+/// # Example
+///
+/// ```
+/// use octane::prelude::*;
+///
+/// let mut app = Octane::new();
+/// app.get(
+///     "/events",
+///     route_stop!(|req, res| {
+///         let events = res.sse();
+///         std::thread::spawn(move || {
+///             events.send("connected");
+///             events.event("update", "{\"count\": 1}");
+///         });
+///     }),
+/// );
+/// ```
+#[derive(Debug, Clone)]
+pub struct EventSender {
+    sender: UnboundedSender<Vec<u8>>,
+}
+
+impl EventSender {
+    /// Sends an unnamed event, each line of the data becomes a
+    /// separate `data:` field. Returns false if the client has
+    /// disconnected
+    pub fn send(&self, data: &str) -> bool {
+        self.push(frame(None, data))
+    }
+    /// Sends an event with an `event:` name so that clients can
+    /// listen for it specifically. Returns false if the client has
+    /// disconnected
+    pub fn event(&self, name: &str, data: &str) -> bool {
+        self.push(frame(Some(name), data))
+    }
+    /// Returns true if the client has disconnected
+    pub fn is_closed(&self) -> bool {
+        self.sender.is_closed()
+    }
+    fn push(&self, frame: Vec<u8>) -> bool {
+        self.sender.send(frame).is_ok()
+    }
+}
+
+// Formats a single event, the blank line terminates it
+fn frame(name: Option<&str>, data: &str) -> Vec<u8> {
+    let mut ret = String::new();
+    if let Some(name) = name {
+        ret.push_str("event: ");
+        ret.push_str(name);
+        ret.push('\n');
+    }
+    for line in data.split('\n') {
+        ret.push_str("data: ");
+        ret.push_str(line.trim_end_matches('\r'));
+        ret.push('\n');
+    }
+    ret.push('\n');
+    ret.into_bytes()
+}
+
+// The receiving half which is used as the response body, every
+// event or chunk is handed to the writer as soon as it is pushed
+pub(crate) struct EventStream {
+    receiver: UnboundedReceiver<Vec<u8>>,
+    pending: Cursor<Vec<u8>>,
+}
+
+impl EventStream {
+    pub(crate) fn new() -> (EventSender, Self) {
+        let (sender, stream) = channel();
+        (EventSender { sender }, stream)
+    }
+}
+
+// Creates a stream along with the raw sender of its chunks, the
+// body writer sends arbitrary bytes through it
+pub(crate) fn channel() -> (UnboundedSender<Vec<u8>>, EventStream) {
+    let (sender, receiver) = unbounded_channel();
+    (
+        sender,
+        EventStream {
+            receiver,
+            pending: Cursor::new(Vec::new()),
+        },
+    )
+}
+
+impl AsyncRead for EventStream {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<Result<()>> {
+        loop {
+            let read = self.pending.read(buf.initialize_unfilled())?;
+            if read > 0 {
+                buf.advance(read);
+                return Poll::Ready(Ok(()));
+            }
+            match Pin::new(&mut self.receiver).poll_next(cx) {
+                Poll::Ready(Some(frame)) => self.pending = Cursor::new(frame),
+                // all the senders are gone, end of the body
+                Poll::Ready(None) => return Poll::Ready(Ok(())),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tokio::io::AsyncReadExt;
+
+    #[test]
+    fn success_frame() {
+        // Every line should be a data field.
+        assert_eq!(frame(None, "a\nb"), b"data: a\ndata: b\n\n");
+        assert_eq!(frame(Some("tick"), "1"), b"event: tick\ndata: 1\n\n");
+    }
+
+    #[crate::test]
+    async fn success_stream() {
+        // Events should be read in order until the senders are dropped.
+        let (sender, mut stream) = EventStream::new();
+        sender.send("one");
+        sender.clone().event("update", "two");
+        drop(sender);
+        let mut body = String::new();
+        stream.read_to_string(&mut body).await.unwrap();
+        assert_eq!(body, "data: one\n\nevent: update\ndata: two\n\n");
+    }
+
+    #[crate::test]
+    async fn success_closed() {
+        // Sending should fail once the stream is gone.
+        let (sender, stream) = EventStream::new();
+        drop(stream);
+        assert!(sender.is_closed());
+        assert!(!sender.send("lost"));
+    }
+}
@@ -0,0 +1,170 @@
+use crate::util::find_in_slice;
+use crate::Octane;
+use std::error::Error;
+use std::str;
+use std::sync::Arc;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+/// A client which sends requests to an app without listening on a
+/// fixed port. Every request is served on a fresh loopback
+/// connection, so the app sees them like it would in production.
+/// It is what [`#[octane::test]`](../attr.test.html) hands to test
+/// functions which take a client
+///
+/// # Example
+///
+/// ```
+/// use octane::prelude::*;
+/// use octane::testing::TestClient;
+///
+/// #[octane::main(flavor = "current_thread")]
+/// async fn main() {
+///     let mut app = Octane::new();
+///     app.get("/", route_stop!(|req, res| res.send("Hello"))).unwrap();
+///     let client = TestClient::new(app);
+///     let res = client.get("/").await.unwrap();
+///     assert_eq!(res.status, 200);
+///     assert_eq!(res.text(), "Hello");
+/// }
+/// ```
+pub struct TestClient {
+    app: Arc<Octane>,
+}
+
+/// A response read by the [`TestClient`](struct.TestClient.html)
+#[derive(Debug, Clone)]
+pub struct TestResponse {
+    /// The status code of the response
+    pub status: u16,
+    /// The headers in the order they were sent
+    pub headers: Vec<(String, String)>,
+    /// The body of the response
+    pub body: Vec<u8>,
+}
+
+impl TestClient {
+    /// Creates a client for the app
+    pub fn new(app: Octane) -> Self {
+        TestClient { app: Arc::new(app) }
+    }
+    /// Sends a `GET` request for the path
+    pub async fn get(&self, path: &str) -> Result<TestResponse, Box<dyn Error>> {
+        self.send("GET", path, &[], b"").await
+    }
+    /// Sends a `POST` request for the path with the body
+    pub async fn post<B: AsRef<[u8]>>(
+        &self,
+        path: &str,
+        body: B,
+    ) -> Result<TestResponse, Box<dyn Error>> {
+        self.send("POST", path, &[], body.as_ref()).await
+    }
+    /// Sends a request with the method, the headers and the body. The
+    /// `Host`, `Content-Length` and `Connection` headers are added
+    pub async fn send(
+        &self,
+        method: &str,
+        path: &str,
+        headers: &[(&str, &str)],
+        body: &[u8],
+    ) -> Result<TestResponse, Box<dyn Error>> {
+        let mut raw = format!("{} {} HTTP/1.1\r\nHost: localhost\r\n", method, path);
+        for (name, value) in headers {
+            raw.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        if !body.is_empty() {
+            raw.push_str(&format!("Content-Length: {}\r\n", body.len()));
+        }
+        raw.push_str("Connection: close\r\n\r\n");
+        let mut raw = raw.into_bytes();
+        raw.extend_from_slice(body);
+        self.send_raw(&raw).await
+    }
+    /// Sends the raw bytes of a request as they are and reads
+    /// the response, the request should close the connection
+    pub async fn send_raw(&self, raw: &[u8]) -> Result<TestResponse, Box<dyn Error>> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let mut client = TcpStream::connect(listener.local_addr()?).await?;
+        let (server, _) = listener.accept().await?;
+        let app = Arc::clone(&self.app);
+        tokio::spawn(async move {
+            Octane::serve(server, app).await.ok();
+        });
+        client.write_all(raw).await?;
+        let mut data = Vec::new();
+        client.read_to_end(&mut data).await?;
+        TestResponse::parse(data).ok_or_else(|| "the response is malformed".into())
+    }
+}
+
+impl TestResponse {
+    // Splits a response into its status, headers and body, the body
+    // is cut to the Content-Length if there is one
+    fn parse(mut data: Vec<u8>) -> Option<Self> {
+        let end = find_in_slice(&data, b"\r\n\r\n")?;
+        let head = str::from_utf8(&data[..end]).ok()?.to_owned();
+        let mut lines = head.split("\r\n");
+        let status = lines.next()?.split(' ').nth(1)?.parse().ok()?;
+        let headers = lines
+            .map(|line| {
+                let (name, value) = line.split_at(line.find(':')?);
+                Some((name.to_owned(), value[1..].trim().to_owned()))
+            })
+            .collect::<Option<Vec<_>>>()?;
+        let mut body = data.split_off(end + 4);
+        let len = headers
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case("content-length"))
+            .and_then(|(_, len)| len.parse().ok());
+        if let Some(len) = len {
+            body.truncate(len);
+        }
+        Some(TestResponse {
+            status,
+            headers,
+            body,
+        })
+    }
+    /// Returns the value of a header, names are case insensitive
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(header, _)| header.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+    /// Returns the body as a string, invalid UTF-8 is replaced
+    pub fn text(&self) -> String {
+        String::from_utf8_lossy(&self.body).into_owned()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::router::{Flow, Route};
+    use crate::{route, route_stop};
+
+    #[test]
+    fn success_parse_response() {
+        // The body should be cut to its length and headers found by name.
+        let raw = b"HTTP/1.1 201 CREATED\r\nContent-Length: 2\r\nX-Id: 7\r\n\r\nokextra".to_vec();
+        let res = TestResponse::parse(raw).unwrap();
+        assert_eq!(res.status, 201);
+        assert_eq!(res.header("x-id"), Some("7"));
+        assert_eq!(res.text(), "ok");
+        assert!(TestResponse::parse(b"HTTP/1.1 200 OK\r\n".to_vec()).is_none());
+    }
+
+    #[crate::test]
+    async fn success_client() {
+        // Requests should be served by the app.
+        let mut app = Octane::new();
+        app.get("/", route_stop!(|req, res| res.send("Hello")))
+            .unwrap();
+        let client = TestClient::new(app);
+        let res = client.get("/").await.unwrap();
+        assert_eq!(res.status, 200);
+        assert_eq!(res.text(), "Hello");
+    }
+}
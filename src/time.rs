@@ -2,7 +2,7 @@ use crate::constants::{
     DAYS_IN_MONTH, DAYS_PER_100Y, DAYS_PER_400Y, DAYS_PER_4Y, LEAPOCH, MONTHS, SP, WEEKS,
 };
 use std::convert::TryInto;
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 pub(crate) struct Time {
     min: i64,
@@ -39,6 +39,10 @@ impl Time {
         };
         Self::time(stamp)
     }
+    pub(crate) fn from_system(time: SystemTime) -> Option<Self> {
+        let stamp = time.duration_since(UNIX_EPOCH).ok()?.as_secs();
+        Self::time(stamp.try_into().ok()?)
+    }
     pub(crate) fn format(&self) -> String {
         if let Some(month_day) = self.month_day() {
             if let Some(week_day) = self.week_day() {
@@ -64,6 +68,35 @@ impl Time {
             String::new()
         }
     }
+    // Parses an HTTP date like `Sun, 06 Nov 1994 08:49:37 GMT`, the
+    // obsolete formats aren't supported
+    pub(crate) fn parse(date: &str) -> Option<SystemTime> {
+        let mut parts = date.split_ascii_whitespace();
+        let week = parts.next()?.strip_suffix(',')?;
+        if !WEEKS.contains(&week) {
+            return None;
+        }
+        let day: i64 = parts.next()?.parse().ok()?;
+        let month = parts.next()?;
+        let month = MONTHS.iter().position(|m| *m == month)? as i64 + 1;
+        let year: i64 = parts.next()?.parse().ok()?;
+        let mut clock = parts.next()?.split(':').map(|n| n.parse::<i64>().ok());
+        let (hour, min, sec) = (clock.next()??, clock.next()??, clock.next()??);
+        if parts.next()? != "GMT" || parts.next().is_some() || clock.next().is_some() {
+            return None;
+        }
+        if !(1..=31).contains(&day) || hour > 23 || min > 59 || sec > 60 {
+            return None;
+        }
+        // days since the epoch of the civil date
+        let y = if month <= 2 { year - 1 } else { year };
+        let era = y.div_euclid(400);
+        let yoe = y - era * 400;
+        let doy = (153 * (month + if month > 2 { -3 } else { 9 }) + 2) / 5 + day - 1;
+        let days = era * 146097 + yoe * 365 + yoe / 4 - yoe / 100 + doy - 719468;
+        let stamp = days * 86400 + hour * 3600 + min * 60 + sec;
+        Some(UNIX_EPOCH + Duration::from_secs(stamp.try_into().ok()?))
+    }
     /*
     pub fn with_stamp(self, stamp: i64) -> Option<Self> {
         Self::time(stamp)
@@ -176,4 +209,27 @@ mod tests {
         let some_time = Time::time(333452334).unwrap().format();
         assert_eq!(some_time, "Sat, 26 Jul 1980 09:38:54 GMT");
     }
+
+    #[test]
+    fn september() {
+        // months should have their three letter names, September included
+        let some_time = Time::time(1630800000).unwrap().format();
+        assert_eq!(some_time, "Sun, 05 Sep 2021 00:00:00 GMT");
+    }
+
+    #[test]
+    fn parse_dates() {
+        // Parsed dates should format back to the same string.
+        use std::time::{Duration, UNIX_EPOCH};
+        let date = Time::parse("Sat, 26 Jul 1980 09:38:54 GMT").unwrap();
+        assert_eq!(date, UNIX_EPOCH + Duration::from_secs(333452334));
+        let date = Time::parse("Thu, 29 Feb 2024 23:59:59 GMT").unwrap();
+        assert_eq!(
+            Time::from_system(date).unwrap().format(),
+            "Thu, 29 Feb 2024 23:59:59 GMT"
+        );
+        assert!(Time::parse("Sun, 05 Sep 2021 00:00:00 GMT").is_some());
+        assert!(Time::parse("Sunday, 06-Nov-94 08:49:37 GMT").is_none());
+        assert!(Time::parse("Sun, 06 Nov 1994 08:49:37").is_none());
+    }
 }
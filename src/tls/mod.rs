@@ -1,3 +1,4 @@
+use std::io::Result;
 use tokio::net::TcpStream;
 
 pub mod openssl;
@@ -5,6 +6,19 @@ pub mod rustls;
 
 pub trait AsMutStream {
     fn stream_mut(&mut self) -> &mut TcpStream;
+    // Sets TCP_NODELAY on the underlying socket, streams which
+    // aren't backed by a tcp socket should override this
+    fn set_nodelay(&mut self, nodelay: bool) -> Result<()> {
+        TcpStream::set_nodelay(self.stream_mut(), nodelay)
+    }
+    // Whether the connection is encrypted, true for the ssl streams
+    fn is_secure(&self) -> bool {
+        false
+    }
+    // The protocol negotiated with ALPN, only ssl streams have one
+    fn alpn_protocol(&self) -> Option<String> {
+        None
+    }
 }
 
 impl AsMutStream for TcpStream {
@@ -1,7 +1,7 @@
 #![cfg(feature = "openSSL")]
 use crate::config::OctaneConfig;
 use crate::tls::AsMutStream;
-use openssl::ssl::{SslAcceptor, SslFiletype, SslMethod};
+use openssl::ssl::{AlpnError, SslAcceptor, SslFiletype, SslMethod};
 use std::io::Result;
 use tokio::net::TcpStream;
 use tokio_openssl::SslStream;
@@ -10,12 +10,39 @@ pub fn acceptor(settings: &OctaneConfig) -> Result<SslAcceptor> {
     let mut acceptor = SslAcceptor::mozilla_intermediate_v5(SslMethod::tls())?;
     acceptor.set_private_key_file(&settings.ssl.key, SslFiletype::PEM)?;
     acceptor.set_certificate_chain_file(&settings.ssl.cert)?;
+    if !settings.ssl.alpn.is_empty() {
+        let protocols = settings.ssl.alpn.clone();
+        acceptor.set_alpn_select_callback(move |_, client| {
+            select_alpn(&protocols, client).ok_or(AlpnError::NOACK)
+        });
+    }
     let acceptor = acceptor.build();
     Ok(acceptor)
 }
 
+// Picks the first of the protocols the client offered too, the client
+// sends them in the wire format where each is prefixed by its length
+fn select_alpn<'a>(protocols: &[String], mut client: &'a [u8]) -> Option<&'a [u8]> {
+    let mut offered = Vec::new();
+    while let Some((&len, rest)) = client.split_first() {
+        let len = (len as usize).min(rest.len());
+        offered.push(&rest[..len]);
+        client = &rest[len..];
+    }
+    protocols
+        .iter()
+        .find_map(|p| offered.iter().find(|o| **o == p.as_bytes()).copied())
+}
+
 impl AsMutStream for SslStream<TcpStream> {
     fn stream_mut(&mut self) -> &mut TcpStream {
         self.get_mut()
     }
+    fn is_secure(&self) -> bool {
+        true
+    }
+    fn alpn_protocol(&self) -> Option<String> {
+        let protocol = self.ssl().selected_alpn_protocol()?;
+        Some(String::from_utf8_lossy(protocol).into_owned())
+    }
 }
@@ -6,7 +6,7 @@ use std::sync::Arc;
 use tokio::net::TcpStream;
 use tokio_rustls::server::TlsStream;
 use tokio_rustls::{
-    rustls::{NoClientAuth, ServerConfig},
+    rustls::{NoClientAuth, ServerConfig, Session},
     TlsAcceptor,
 };
 
@@ -20,6 +20,8 @@ pub fn acceptor(settings: &OctaneConfig) -> Result<TlsAcceptor, Box<dyn Error>>
         );
     }
     config.set_single_cert(settings.get_cert()?, key.remove(0))?;
+    let protocols: Vec<Vec<u8>> = settings.ssl.alpn.iter().map(|p| p.clone().into()).collect();
+    config.set_protocols(&protocols);
     let acceptor = TlsAcceptor::from(Arc::new(config));
     Ok(acceptor)
 }
@@ -28,4 +30,11 @@ impl AsMutStream for TlsStream<TcpStream> {
     fn stream_mut(&mut self) -> &mut TcpStream {
         self.get_mut().0
     }
+    fn is_secure(&self) -> bool {
+        true
+    }
+    fn alpn_protocol(&self) -> Option<String> {
+        let protocol = self.get_ref().1.get_alpn_protocol()?;
+        Some(String::from_utf8_lossy(protocol).into_owned())
+    }
 }
@@ -0,0 +1,34 @@
+use crate::request::Request;
+use crate::responder::StatusCode;
+use std::sync::atomic::{AtomicU64, Ordering};
+use tracing::field::Empty;
+use tracing::Span;
+
+// Ids to tell requests apart in the logs, unique for the process
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+// The span a request is served in, the method and path are recorded
+// once the request has been parsed
+pub fn request_span() -> Span {
+    let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+    tracing::info_span!("request", id, method = Empty, path = Empty)
+}
+
+// Records the request on the current span
+pub fn parsed(request: &Request) {
+    let span = Span::current();
+    span.record("method", request.request_line.method.as_str());
+    let path = format!("/{}", request.request_line.path.chunks.join("/"));
+    span.record("path", path.as_str());
+    tracing::debug!("request parsed");
+}
+
+// Marks the end of routing, handled is false if no closure produced
+// a response
+pub fn routed(handled: bool) {
+    tracing::debug!(handled, "request routed");
+}
+
+pub fn responded(status: StatusCode, sent: u64) {
+    tracing::info!(status = status.as_u16(), bytes = sent, "response sent");
+}
@@ -1,6 +1,4 @@
-use std::io::Read;
 use std::iter::FusedIterator;
-use std::ops::Deref;
 use std::pin::Pin;
 use std::task::{Context, Poll};
 use tokio::io::{AsyncRead, ReadBuf, Result};
@@ -68,28 +66,63 @@ impl<'a, T: Eq> Iterator for Spliterator<'a, T> {
 
 impl<'a, T: Eq> FusedIterator for Spliterator<'a, T> {}
 
-#[derive(Debug, Clone, Default)]
-pub struct AsyncReader<T: Read> {
-    pub reader: T,
+// Percent-encodes a path segment for use in a url, only the
+// unreserved characters are kept as they are
+pub(crate) fn encode_segment(segment: &str) -> String {
+    let mut encoded = String::with_capacity(segment.len());
+    for byte in segment.bytes() {
+        if byte.is_ascii_alphanumeric() || b"-._~".contains(&byte) {
+            encoded.push(byte as char);
+        } else {
+            encoded.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    encoded
 }
 
-impl<T: Read + Unpin> Deref for AsyncReader<T> {
-    type Target = T;
+// Returns random hex digits read from the random number generator
+// of the os, for names and ids which mustn't be guessable
+pub(crate) fn random_hex(bytes: usize) -> Result<String> {
+    let mut buf = vec![0; bytes];
+    getrandom::getrandom(&mut buf)?;
+    Ok(buf.iter().map(|byte| format!("{:02x}", byte)).collect())
+}
 
-    fn deref(&self) -> &Self::Target {
-        &self.reader
-    }
+// Discards the first bytes of a reader, used to start a body at
+// the beginning of a requested range
+pub(crate) struct Skip<R> {
+    reader: R,
+    remaining: usize,
 }
 
-impl<T: Read + Unpin> AsyncRead for AsyncReader<T> {
-    fn poll_read(mut self: Pin<&mut Self>, _: &mut Context, buf: &mut ReadBuf) -> Poll<Result<()>> {
-        self.reader.read(buf.initialized_mut())?;
-        Poll::Ready(Ok(()))
+impl<R: AsyncRead + Unpin> Skip<R> {
+    pub(crate) fn new(reader: R, skip: usize) -> Self {
+        Self {
+            reader,
+            remaining: skip,
+        }
     }
 }
 
-impl<T: Read + Unpin> AsyncReader<T> {
-    pub fn new(reader: T) -> Self {
-        Self { reader }
+impl<R: AsyncRead + Unpin> AsyncRead for Skip<R> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<Result<()>> {
+        let mut scratch = [0; 4096];
+        while self.remaining > 0 {
+            let len = self.remaining.min(scratch.len());
+            let mut discard = ReadBuf::new(&mut scratch[..len]);
+            match Pin::new(&mut self.reader).poll_read(cx, &mut discard) {
+                Poll::Ready(Ok(())) if discard.filled().is_empty() => {
+                    // the reader ended before the range started
+                    return Poll::Ready(Ok(()));
+                }
+                Poll::Ready(Ok(())) => self.remaining -= discard.filled().len(),
+                other => return other,
+            }
+        }
+        Pin::new(&mut self.reader).poll_read(cx, buf)
     }
 }
@@ -0,0 +1,95 @@
+use crate::sse::{channel, EventStream};
+use std::io::{Error, ErrorKind, Result, Write};
+use tokio::sync::mpsc::UnboundedSender;
+
+/// A handle to write a response body incrementally, returned by
+/// [`Response::body_writer()`](../responder/struct.Response.html#method.body_writer).
+/// Every write is sent to the client as it happens instead of building
+/// the whole body in memory first, which suits large generated documents
+/// like WebDAV `PROPFIND` listings. It can be cloned and moved to other
+/// threads, the body ends once every writer is dropped. Each write is
+/// handed over on its own, so wrap it in a `std::io::BufWriter` when
+/// writing lots of small pieces
+///
+/// # Example
+///
+/// ```
+/// use octane::prelude::*;
+/// use std::io::Write;
+///
+/// let mut app = Octane::new();
+/// app.get(
+///     "/listing",
+///     route_stop!(|req, res| {
+///         res.with_type("application/xml");
+///         let mut body = res.body_writer();
+///         std::thread::spawn(move || {
+///             write!(body, "<multistatus>").ok();
+///             for i in 0..1000 {
+///                 write!(body, "<response>{}</response>", i).ok();
+///             }
+///             write!(body, "</multistatus>").ok();
+///         });
+///     }),
+/// );
+/// ```
+#[derive(Debug, Clone)]
+pub struct BodyWriter {
+    sender: UnboundedSender<Vec<u8>>,
+}
+
+impl BodyWriter {
+    pub(crate) fn new() -> (Self, EventStream) {
+        let (sender, stream) = channel();
+        (Self { sender }, stream)
+    }
+    /// Returns true if the client has disconnected
+    pub fn is_closed(&self) -> bool {
+        self.sender.is_closed()
+    }
+}
+
+impl Write for BodyWriter {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        self.sender
+            .send(buf.to_vec())
+            .map_err(|_| Error::new(ErrorKind::BrokenPipe, "the client has disconnected"))?;
+        Ok(buf.len())
+    }
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tokio::io::AsyncReadExt;
+
+    #[crate::test]
+    async fn success_body_writer() {
+        // The chunks should be read in order until the writers are dropped.
+        let (mut writer, mut stream) = BodyWriter::new();
+        let mut other = writer.clone();
+        writer.write_all(b"<multistatus>").unwrap();
+        write!(other, "<response>{}</response>", 1).unwrap();
+        writer.write_all(b"</multistatus>").unwrap();
+        drop((writer, other));
+        let mut body = String::new();
+        stream.read_to_string(&mut body).await.unwrap();
+        assert_eq!(body, "<multistatus><response>1</response></multistatus>");
+    }
+
+    #[test]
+    fn fail_closed() {
+        // Writing should fail once the body is gone.
+        let (mut writer, stream) = BodyWriter::new();
+        drop(stream);
+        assert!(writer.is_closed());
+        let err = writer.write(b"lost").unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::BrokenPipe);
+    }
+}